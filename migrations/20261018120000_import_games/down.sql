@@ -0,0 +1,28 @@
+DROP INDEX line_move_line;
+DROP INDEX line_game;
+DROP INDEX site_name;
+DROP INDEX event_name;
+DROP INDEX player_name;
+
+-- SQLite can't drop columns, so line and line_move are rebuilt without the ones added to them.
+CREATE TABLE line_without_game (
+    id INTEGER PRIMARY KEY NOT NULL,
+    starting_position_id INTEGER NOT NULL,
+    parent_line_id INTEGER NULL
+);
+INSERT INTO line_without_game (id, starting_position_id, parent_line_id)
+    SELECT id, starting_position_id, parent_line_id FROM line;
+DROP TABLE line;
+ALTER TABLE line_without_game RENAME TO line;
+
+CREATE TABLE line_move_without_san (
+    id INTEGER PRIMARY KEY NOT NULL,
+    move_id INTEGER NOT NULL,
+    line_id INTEGER NOT NULL,
+    -- The ply implied colour. n % 2 == 0 -> black || n % 2 == 1 -> white
+    ply INTEGER NOT NULL
+);
+INSERT INTO line_move_without_san (id, move_id, line_id, ply)
+    SELECT id, move_id, line_id, ply FROM line_move;
+DROP TABLE line_move;
+ALTER TABLE line_move_without_san RENAME TO line_move;
@@ -0,0 +1,16 @@
+-- The game that owns the line, and for variations, the ply in the parent line that they replace.
+ALTER TABLE line ADD COLUMN game_id INTEGER NULL;
+ALTER TABLE line ADD COLUMN parent_ply INTEGER NULL;
+-- The comment that appears before the first move of the line.
+ALTER TABLE line ADD COLUMN comment VARCHAR NULL;
+-- The move as it was written in the source, and the annotations that follow it.
+ALTER TABLE line_move ADD COLUMN san VARCHAR NOT NULL DEFAULT '';
+ALTER TABLE line_move ADD COLUMN comment VARCHAR NULL;
+-- Space separated NAG numbers.
+ALTER TABLE line_move ADD COLUMN nags VARCHAR NULL;
+
+CREATE INDEX player_name ON player (last_name, first_name);
+CREATE INDEX event_name ON event (name, year);
+CREATE INDEX site_name ON site (name);
+CREATE INDEX line_game ON line (game_id);
+CREATE INDEX line_move_line ON line_move (line_id, ply);
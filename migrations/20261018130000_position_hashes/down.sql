@@ -0,0 +1,14 @@
+DROP TABLE _move;
+CREATE TABLE _move (
+    id INTEGER PRIMARY KEY NOT NULL,
+    uci INTEGER not null,
+    starting_position_id INTEGER NOT NULL,
+    ending_position_id INTEGER NOT NULL
+);
+
+DROP TABLE position;
+CREATE TABLE position (
+    id INTEGER PRIMARY KEY NOT NULL,
+    hash_1 INTEGER NOT NULL,
+    hash_2 INTEGER NOT NULL
+);
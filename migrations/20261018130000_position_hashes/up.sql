@@ -0,0 +1,19 @@
+-- Nothing wrote to these tables before, so they are recreated rather than migrated. The hashes
+-- are the two halves of a 128 bit Zobrist hash and need all 64 bits.
+DROP TABLE position;
+CREATE TABLE position (
+    id INTEGER PRIMARY KEY NOT NULL,
+    hash_1 BIGINT NOT NULL,
+    hash_2 BIGINT NOT NULL
+);
+CREATE UNIQUE INDEX position_hash ON position (hash_1, hash_2);
+
+DROP TABLE _move;
+CREATE TABLE _move (
+    id INTEGER PRIMARY KEY NOT NULL,
+    uci VARCHAR NOT NULL,
+    starting_position_id INTEGER NOT NULL,
+    ending_position_id INTEGER NOT NULL
+);
+CREATE UNIQUE INDEX move_start_uci ON _move (starting_position_id, uci);
+CREATE INDEX move_end ON _move (ending_position_id);
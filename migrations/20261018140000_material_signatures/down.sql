@@ -0,0 +1,24 @@
+-- SQLite can't drop columns, so game is rebuilt without the material signature columns.
+CREATE TABLE game_without_matsig (
+    id INTEGER PRIMARY KEY NOT NULL,
+    white_player_id INTEGER NOT NULL,
+    white_player_rating INTEGER NOT NULL,
+    black_player_id INTEGER NOT NULL,
+    black_player_rating INTEGER NOT NULL,
+    event_id INTEGER NULL,
+    site_id INTEGER NULL,
+    date VARCHAR NOT NULL,
+    round INTEGER NULL,
+    result VARCHAR NOT NULL,
+    pgn VARCHAR NOT NULL,
+    -- the line that represents the deconstructed game.
+    line_id INTEGER NOT NULL
+);
+INSERT INTO game_without_matsig (id, white_player_id, white_player_rating, black_player_id,
+                                 black_player_rating, event_id, site_id, date, round, result,
+                                 pgn, line_id)
+    SELECT id, white_player_id, white_player_rating, black_player_id, black_player_rating,
+           event_id, site_id, date, round, result, pgn, line_id
+      FROM game;
+DROP TABLE game;
+ALTER TABLE game_without_matsig RENAME TO game;
@@ -0,0 +1,6 @@
+-- The material signature (see scid::matsig) at the end of the main line, and whether the main line
+-- has any promotions, or promotions to something other than a queen. Games imported before this
+-- get an empty signature, which never rules a game out of a material search.
+ALTER TABLE game ADD COLUMN matsig INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE game ADD COLUMN promotions BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE game ADD COLUMN underpromotions BOOLEAN NOT NULL DEFAULT 0;
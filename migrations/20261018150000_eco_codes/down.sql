@@ -0,0 +1,30 @@
+DROP INDEX game_eco;
+
+-- SQLite can't drop columns, so game is rebuilt without the eco column.
+CREATE TABLE game_without_eco (
+    id INTEGER PRIMARY KEY NOT NULL,
+    white_player_id INTEGER NOT NULL,
+    white_player_rating INTEGER NOT NULL,
+    black_player_id INTEGER NOT NULL,
+    black_player_rating INTEGER NOT NULL,
+    event_id INTEGER NULL,
+    site_id INTEGER NULL,
+    date VARCHAR NOT NULL,
+    round INTEGER NULL,
+    result VARCHAR NOT NULL,
+    pgn VARCHAR NOT NULL,
+    -- the line that represents the deconstructed game.
+    line_id INTEGER NOT NULL,
+    matsig INTEGER NOT NULL DEFAULT 0,
+    promotions BOOLEAN NOT NULL DEFAULT 0,
+    underpromotions BOOLEAN NOT NULL DEFAULT 0
+);
+INSERT INTO game_without_eco (id, white_player_id, white_player_rating, black_player_id,
+                              black_player_rating, event_id, site_id, date, round, result,
+                              pgn, line_id, matsig, promotions, underpromotions)
+    SELECT id, white_player_id, white_player_rating, black_player_id, black_player_rating,
+           event_id, site_id, date, round, result, pgn, line_id, matsig, promotions,
+           underpromotions
+      FROM game;
+DROP TABLE game;
+ALTER TABLE game_without_eco RENAME TO game;
@@ -0,0 +1,5 @@
+-- The ECO code of each game's opening (see scid::eco), from the deepest position of its main line
+-- that the opening file knows, or else from its ECO tag. Games imported before this have none
+-- until they're reclassified.
+ALTER TABLE game ADD COLUMN eco VARCHAR NULL;
+CREATE INDEX game_eco ON game (eco);
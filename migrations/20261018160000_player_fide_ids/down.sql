@@ -0,0 +1,15 @@
+DROP INDEX player_fide_id;
+
+-- SQLite can't drop columns, so player is rebuilt without the fide_id column, which takes its
+-- name index with it.
+CREATE TABLE player_without_fide_id (
+    id INTEGER PRIMARY KEY NOT NULL,
+    first_name VARCHAR NOT NULL,
+    last_name VARCHAR NOT NULL,
+    middle_name VARCHAR NULL
+);
+INSERT INTO player_without_fide_id (id, first_name, last_name, middle_name)
+    SELECT id, first_name, last_name, middle_name FROM player;
+DROP TABLE player;
+ALTER TABLE player_without_fide_id RENAME TO player;
+CREATE INDEX player_name ON player (last_name, first_name);
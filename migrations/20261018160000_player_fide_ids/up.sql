@@ -0,0 +1,3 @@
+-- The player's FIDE id, from a spelling file (see scid::spelling) when one knows them.
+ALTER TABLE player ADD COLUMN fide_id INTEGER NULL;
+CREATE INDEX player_fide_id ON player (fide_id);
@@ -0,0 +1,151 @@
+// delila - a desktop version of lila.
+//
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//--------------------------------------------------------------------------------------------------
+// Keeps other local processes and web pages off the server.
+//
+// Each launch makes a new secret token and writes it to a file that only the user can read. A
+// client proves that it can read the file by sending the token, either in the handshake (as a
+// ?token= query parameter or an Authorization: Bearer header) or in an auth::authenticate
+// message. Browsers also send an Origin header, which has to be one of the allowed origins.
+//--------------------------------------------------------------------------------------------------
+
+use rand::{OsRng, Rng};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use errors::*;
+
+const TOKEN_FILE: &'static str = "token";
+const TOKEN_BYTES: usize = 32;
+
+pub struct Token(String);
+
+impl Token {
+    pub fn generate() -> Result<Token> {
+        let mut rng = OsRng::new().chain_err(|| "Unable to find a source of randomness")?;
+        let mut bytes = [0u8; TOKEN_BYTES];
+        rng.fill_bytes(&mut bytes);
+        Ok(Token(bytes.iter().map(|byte| format!("{:02x}", byte)).collect()))
+    }
+
+    // Writes the token where clients can find it, returning the path of the file.
+    pub fn write(&self, directory: &Path) -> Result<PathBuf> {
+        let path = directory.join(TOKEN_FILE);
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        restrict_to_user(&mut options);
+        options.open(&path)
+            .and_then(|mut file| file.write_all(self.0.as_bytes()))
+            .chain_err(|| format!("Unable to write the token to {}", path.display()))?;
+        Ok(path)
+    }
+
+    // Compares in constant time, so that how long it takes doesn't give the token away.
+    pub fn matches(&self, candidate: &str) -> bool {
+        let expected = self.0.as_bytes();
+        let candidate = candidate.trim().as_bytes();
+        if expected.len() != candidate.len() {
+            return false;
+        }
+        expected.iter().zip(candidate.iter()).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+    }
+}
+
+#[cfg(unix)]
+fn restrict_to_user(options: &mut fs::OpenOptions) {
+    use std::os::unix::fs::OpenOptionsExt;
+    options.mode(0o600);
+}
+
+#[cfg(not(unix))]
+fn restrict_to_user(_options: &mut fs::OpenOptions) {
+    // The user's config directory is already private to them.
+}
+
+// Finds the token in a handshake's resource ("/?token=...") or Authorization header.
+pub fn handshake_token<'a>(resource: &'a str, authorization: Option<&'a str>) -> Option<&'a str> {
+    let from_query = resource.splitn(2, '?').nth(1).and_then(|query| {
+        query.split('&')
+            .filter_map(|pair| {
+                let mut parts = pair.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some("token"), Some(value)) => Some(value),
+                    _ => None
+                }
+            })
+            .next()
+    });
+    from_query.or_else(|| authorization.and_then(|header| {
+        if header.starts_with("Bearer ") { Some(&header["Bearer ".len()..]) } else { None }
+    }))
+}
+
+// Clients that aren't browsers don't send an Origin, and are let through to the token check.
+// Allowed origins match with any port, so "http://localhost" allows "http://localhost:8080".
+pub fn origin_allowed(origin: Option<&str>, allowed: &[String]) -> bool {
+    match origin {
+        None => true,
+        Some(origin) => allowed.iter().any(|allowed| {
+            origin == allowed || (origin.starts_with(allowed.as_str()) && origin[allowed.len()..].starts_with(':'))
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Credentials {
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Authenticated {
+    pub authenticated: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens() {
+        let token = Token::generate().unwrap();
+        assert_eq!(token.0.len(), TOKEN_BYTES * 2);
+        assert!(token.matches(&token.0.clone()));
+        assert!(!token.matches(&token.0[1..]));
+        assert!(!token.matches(""));
+        assert!(Token::generate().unwrap().0 != token.0);
+    }
+
+    #[test]
+    fn test_handshake_token() {
+        assert_eq!(handshake_token("/?token=abc", None), Some("abc"));
+        assert_eq!(handshake_token("/?v=2&token=abc", None), Some("abc"));
+        assert_eq!(handshake_token("/", Some("Bearer def")), Some("def"));
+        assert_eq!(handshake_token("/?tokens=abc", Some("Basic def")), None);
+    }
+
+    #[test]
+    fn test_origins() {
+        let allowed = vec!["file://".to_string(), "http://localhost".to_string()];
+        assert!(origin_allowed(None, &allowed));
+        assert!(origin_allowed(Some("file://"), &allowed));
+        assert!(origin_allowed(Some("http://localhost:8080"), &allowed));
+        assert!(!origin_allowed(Some("http://localhost.evil.com"), &allowed));
+        assert!(!origin_allowed(Some("https://example.com"), &allowed));
+    }
+}
@@ -0,0 +1,254 @@
+// delila - a desktop version of lila.
+//
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//--------------------------------------------------------------------------------------------------
+// Forsyth-Edwards Notation
+//--------------------------------------------------------------------------------------------------
+
+use errors::*;
+use scid::common::*;
+use super::Board;
+
+fn invalid(fen: &str, reason: &str) -> Error {
+    ErrorKind::InvalidFen(fen.into(), reason.into()).into()
+}
+
+pub fn piece_from_char(c: char) -> Option<Piece> {
+    let piece = match c.to_ascii_uppercase() {
+        'K' => KING,
+        'Q' => QUEEN,
+        'R' => ROOK,
+        'B' => BISHOP,
+        'N' => KNIGHT,
+        'P' => PAWN,
+        _ => return None
+    };
+    Some(piece_make(if c.is_uppercase() { WHITE } else { BLACK }, piece))
+}
+
+pub fn piece_to_char(piece: Piece) -> char {
+    let c = match piece_type(piece) {
+        KING => 'K',
+        QUEEN => 'Q',
+        ROOK => 'R',
+        BISHOP => 'B',
+        KNIGHT => 'N',
+        PAWN => 'P',
+        _ => return '?'
+    };
+    if piece_color(piece) == WHITE { c } else { c.to_ascii_lowercase() }
+}
+
+pub fn square_from_str(name: &str) -> Option<Square> {
+    let bytes = name.as_bytes();
+    if bytes.len() != 2 || bytes[0] < b'a' || bytes[0] > b'h' || bytes[1] < b'1' || bytes[1] > b'8' {
+        return None;
+    }
+    Some(square_make((bytes[0] - b'a') as File, (bytes[1] - b'1') as Rank))
+}
+
+pub fn square_to_string(sq: Square) -> String {
+    let mut name = String::with_capacity(2);
+    name.push((b'a' + square_fyle(sq) as u8) as char);
+    name.push((b'1' + square_rank(sq) as u8) as char);
+    name
+}
+
+impl Board {
+    //----------------------------------------------------------------------------------------------
+    // from_fen():
+    //      Sets up a board from a FEN string. The move counters may be omitted, as they often are
+    //      in EPD and opening files.
+    //
+    pub fn from_fen(fen: &str) -> Result<Board> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        if fields.len() < 4 {
+            return Err(invalid(fen, "expected at least 4 fields"));
+        }
+        let mut board = Board::empty();
+
+        let ranks: Vec<&str> = fields[0].split('/').collect();
+        if ranks.len() != 8 {
+            return Err(invalid(fen, "expected 8 ranks"));
+        }
+        for (index, rank) in ranks.iter().enumerate() {
+            let r = 7 - index as Rank;
+            let mut f: File = 0;
+            for c in rank.chars() {
+                if let Some(skip) = c.to_digit(10) {
+                    // Checked before adding, as enough skips would overflow the file.
+                    if f as u32 + skip > 8 {
+                        return Err(invalid(fen, "too many squares in a rank"));
+                    }
+                    f += skip as File;
+                } else {
+                    let piece = piece_from_char(c).ok_or_else(|| invalid(fen, "unknown piece"))?;
+                    if f > 7 {
+                        return Err(invalid(fen, "too many squares in a rank"));
+                    }
+                    if piece_type(piece) == KING && board.kings[piece_color(piece) as usize] != NULL_SQUARE {
+                        return Err(invalid(fen, "more than one king"));
+                    }
+                    board.set_piece(square_make(f, r), piece);
+                    f += 1;
+                }
+            }
+            if f != 8 {
+                return Err(invalid(fen, "each rank must have 8 squares"));
+            }
+        }
+        if board.kings[WHITE as usize] == NULL_SQUARE || board.kings[BLACK as usize] == NULL_SQUARE {
+            return Err(invalid(fen, "each side needs a king"));
+        }
+
+        board.to_move = match fields[1] {
+            "w" => WHITE,
+            "b" => BLACK,
+            _ => return Err(invalid(fen, "side to move must be w or b"))
+        };
+
+        if fields[2] != "-" {
+            for c in fields[2].chars() {
+                board.castling |= match c {
+                    'K' => WK_CASTLE,
+                    'Q' => WQ_CASTLE,
+                    'k' => BK_CASTLE,
+                    'q' => BQ_CASTLE,
+                    _ => return Err(invalid(fen, "unknown castling flag"))
+                };
+            }
+        }
+        // Drop castling rights that the pieces on the board can't back up.
+        for &(flag, king, rook) in &[(WK_CASTLE, E1, H1), (WQ_CASTLE, E1, A1), (BK_CASTLE, E8, H8), (BQ_CASTLE, E8, A8)] {
+            let color = if king == E1 { WHITE } else { BLACK };
+            if board.piece_at(king) != piece_make(color, KING) || board.piece_at(rook) != piece_make(color, ROOK) {
+                board.castling &= !flag;
+            }
+        }
+
+        board.ep_square = match fields[3] {
+            "-" => NULL_SQUARE,
+            square => square_from_str(square).ok_or_else(|| invalid(fen, "invalid en passant square"))?
+        };
+        if board.ep_square != NULL_SQUARE && !board.can_capture_en_passant(board.ep_square) {
+            board.ep_square = NULL_SQUARE;
+        }
+        board.halfmove_clock = match fields.get(4) {
+            Some(clock) => clock.parse().map_err(|_| invalid(fen, "invalid halfmove clock"))?,
+            None => 0
+        };
+        board.fullmove_number = match fields.get(5) {
+            Some(number) => number.parse().map_err(|_| invalid(fen, "invalid fullmove number"))?,
+            None => 1
+        };
+        board.fullmove_number = board.fullmove_number.max(1);
+
+        let enemy_king = board.kings[color_flip(board.to_move) as usize];
+        if board.is_attacked(enemy_king, board.to_move) {
+            return Err(invalid(fen, "the side not to move is in check"));
+        }
+        board.hash = board.compute_hash();
+        Ok(board)
+    }
+
+    pub fn to_fen(&self) -> String {
+        let mut fen = String::with_capacity(90);
+        for r in (0..8).rev() {
+            let mut empty = 0;
+            for f in 0..8 {
+                let piece = self.piece_at(square_make(f, r));
+                if piece == EMPTY {
+                    empty += 1;
+                    continue;
+                }
+                if empty > 0 {
+                    fen.push_str(&empty.to_string());
+                    empty = 0;
+                }
+                fen.push(piece_to_char(piece));
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if r > 0 {
+                fen.push('/');
+            }
+        }
+
+        fen.push_str(if self.to_move == WHITE { " w " } else { " b " });
+        if self.castling == 0 {
+            fen.push('-');
+        }
+        for &(flag, c) in &[(WK_CASTLE, 'K'), (WQ_CASTLE, 'Q'), (BK_CASTLE, 'k'), (BQ_CASTLE, 'q')] {
+            if self.castling & flag != 0 {
+                fen.push(c);
+            }
+        }
+        fen.push(' ');
+        if self.ep_square == NULL_SQUARE {
+            fen.push('-');
+        } else {
+            fen.push_str(&square_to_string(self.ep_square));
+        }
+        fen.push_str(&format!(" {} {}", self.halfmove_clock, self.fullmove_number));
+        fen
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::START_FEN;
+
+    #[test]
+    fn test_round_trip() {
+        for fen in &[
+            START_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "8/8/8/8/8/5k2/8/4K3 b - - 47 112",
+        ] {
+            assert_eq!(Board::from_fen(fen).unwrap().to_fen(), *fen);
+        }
+    }
+
+    #[test]
+    fn test_optional_counters() {
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/4K3 w - -").unwrap();
+        assert_eq!(board.to_fen(), "4k3/8/8/8/8/8/8/4K3 w - - 0 1");
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Board::from_fen("").is_err());
+        assert!(Board::from_fen("8/8/8/8/8/8/8/8 w - - 0 1").is_err());
+        assert!(Board::from_fen("4k3/8/8/8/8/8/8/4K3 x - - 0 1").is_err());
+        assert!(Board::from_fen("4k3/8/8/8/8/8/8/4K4 w - - 0 1").is_err());
+        assert!(Board::from_fen("4k3/8/8/8/8/8/8/4KK2 w - - 0 1").is_err());
+        assert!(Board::from_fen("4k3/8/8/8/8/8/8/4KX2 w - - 0 1").is_err());
+        assert!(Board::from_fen("4k3/4R3/8/8/8/8/8/4K3 w - - 0 1").is_err());
+        assert!(Board::from_fen("4k3/99999999999999999/8/8/8/8/8/4K3 w - - 0 1").is_err());
+        assert!(Board::from_fen("4k3/44/8/8/8/8/8/4K3 w - - 0 1").is_ok());
+        assert!(Board::from_fen("4k3/45/8/8/8/8/8/4K3 w - - 0 1").is_err());
+    }
+
+    #[test]
+    fn test_castling_rights_need_pieces() {
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/4K2R w KQkq - 0 1").unwrap();
+        assert_eq!(board.castling(), WK_CASTLE);
+    }
+}
@@ -0,0 +1,434 @@
+// delila - a desktop version of lila.
+//
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//--------------------------------------------------------------------------------------------------
+// Board representation
+//
+// A simple mailbox board that uses the SCID piece, square and colour encodings, so that it can be
+// used directly with the rest of the SCID compatibility layer (material signatures etc).
+//--------------------------------------------------------------------------------------------------
+pub mod fen;
+pub mod movegen;
+pub mod notation;
+pub mod zobrist;
+
+use scid::common::*;
+use scid::matsig;
+use scid::matsig::MaterialSignature;
+use self::zobrist::ZobristHash;
+
+pub const START_FEN: &'static str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+// A move from one square to another. Castling is represented as the king moving two squares, and
+// the promotion is a piece type (e.g. QUEEN), or EMPTY if the move isn't a promotion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Move {
+    pub from: Square,
+    pub to: Square,
+    pub promotion: Piece
+}
+
+impl Move {
+    pub fn new(from: Square, to: Square) -> Move {
+        Move{from: from, to: to, promotion: EMPTY}
+    }
+
+    pub fn promote(from: Square, to: Square, promotion: Piece) -> Move {
+        Move{from: from, to: to, promotion: promotion}
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Status {
+    Ongoing,
+    Checkmate,
+    Stalemate
+}
+
+// Everything make() throws away that unmake() needs to restore.
+#[derive(Debug, Clone)]
+struct Undo {
+    mv: Move,
+    captured: Piece,
+    en_passant: bool,
+    castling: UByte,
+    ep_square: Square,
+    halfmove_clock: u32
+}
+
+#[derive(Debug, Clone)]
+pub struct Board {
+    squares: [Piece; 64],
+    to_move: Color,
+    castling: UByte,
+    ep_square: Square,
+    halfmove_clock: u32,
+    fullmove_number: u32,
+    kings: [Square; 2],
+    hash: ZobristHash,
+    history: Vec<Undo>
+}
+
+impl Board {
+    pub fn empty() -> Board {
+        Board{
+            squares: [EMPTY; 64],
+            to_move: WHITE,
+            castling: 0,
+            ep_square: NULL_SQUARE,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            kings: [NULL_SQUARE, NULL_SQUARE],
+            hash: ZobristHash::default(),
+            history: Vec::new()
+        }
+    }
+
+    pub fn start() -> Board {
+        Board::from_fen(START_FEN).expect("The starting position is valid")
+    }
+
+    pub fn piece_at(&self, sq: Square) -> Piece { self.squares[sq as usize] }
+    pub fn to_move(&self) -> Color { self.to_move }
+    pub fn castling(&self) -> UByte { self.castling }
+    pub fn ep_square(&self) -> Square { self.ep_square }
+    pub fn halfmove_clock(&self) -> u32 { self.halfmove_clock }
+    pub fn fullmove_number(&self) -> u32 { self.fullmove_number }
+    pub fn king_square(&self, color: Color) -> Square { self.kings[color as usize] }
+    pub fn hash(&self) -> ZobristHash { self.hash }
+
+    // The number of plies played since the game started, with white's first move being ply 1.
+    pub fn ply(&self) -> u32 {
+        (self.fullmove_number - 1) * 2 + self.to_move as u32
+    }
+
+    fn set_piece(&mut self, sq: Square, piece: Piece) {
+        self.hash.toggle_piece(self.squares[sq as usize], sq);
+        self.hash.toggle_piece(piece, sq);
+        self.squares[sq as usize] = piece;
+        if piece_type(piece) == KING {
+            self.kings[piece_color(piece) as usize] = sq;
+        }
+    }
+
+    fn set_to_move(&mut self, color: Color) {
+        if color != self.to_move {
+            self.hash.toggle_side_to_move();
+        }
+        self.to_move = color;
+    }
+
+    fn set_castling(&mut self, castling: UByte) {
+        self.hash.toggle_castling(self.castling ^ castling);
+        self.castling = castling;
+    }
+
+    fn set_ep_square(&mut self, sq: Square) {
+        self.hash.toggle_ep_square(self.ep_square);
+        self.hash.toggle_ep_square(sq);
+        self.ep_square = sq;
+    }
+
+    // Whether a pawn of the side to move could capture en passant onto <sq>. The en passant
+    // square is only kept when this is true, so that positions which only differ by an unusable
+    // en passant square hash the same.
+    fn can_capture_en_passant(&self, sq: Square) -> bool {
+        let color = self.to_move;
+        let behind = if color == WHITE { DOWN } else { UP };
+        [LEFT, RIGHT].iter().any(|side| {
+            let from = square_move(sq, behind | *side);
+            from != NULL_SQUARE && self.piece_at(from) == piece_make(color, PAWN)
+        })
+    }
+
+    pub fn material_signature(&self) -> MaterialSignature {
+        let mut counts = [0; 16];
+        for piece in self.squares.iter() {
+            counts[*piece as usize] += 1;
+        }
+        [WQ, WR, WB, WN, WP, BQ, BR, BB, BN, BP].iter().fold(matsig::EMPTY, |signature, &piece| {
+            matsig::set_count(signature, piece, counts[piece as usize])
+        })
+    }
+
+    // Computes the hash from scratch, rather than incrementally.
+    pub fn compute_hash(&self) -> ZobristHash {
+        let mut hash = ZobristHash::default();
+        for sq in 0..64 {
+            hash.toggle_piece(self.piece_at(sq), sq);
+        }
+        if self.to_move == BLACK {
+            hash.toggle_side_to_move();
+        }
+        hash.toggle_castling(self.castling);
+        hash.toggle_ep_square(self.ep_square);
+        hash
+    }
+
+    // A copy of the board without the move history, for looking ahead a single move.
+    fn snapshot(&self) -> Board {
+        Board{
+            squares: self.squares,
+            to_move: self.to_move,
+            castling: self.castling,
+            ep_square: self.ep_square,
+            halfmove_clock: self.halfmove_clock,
+            fullmove_number: self.fullmove_number,
+            kings: self.kings,
+            hash: self.hash,
+            history: Vec::new()
+        }
+    }
+
+    //----------------------------------------------------------------------------------------------
+    // make():
+    //      Plays a move, which must be legal in the current position.
+    //
+    pub fn make(&mut self, mv: Move) {
+        let color = self.to_move;
+        let piece = self.piece_at(mv.from);
+        let mut captured = self.piece_at(mv.to);
+        let en_passant = piece_type(piece) == PAWN && mv.to == self.ep_square && captured == EMPTY;
+        if en_passant {
+            let capture_square = ep_capture_square(mv.to, color);
+            captured = self.piece_at(capture_square);
+            self.set_piece(capture_square, EMPTY);
+        }
+        self.history.push(Undo{
+            mv: mv,
+            captured: captured,
+            en_passant: en_passant,
+            castling: self.castling,
+            ep_square: self.ep_square,
+            halfmove_clock: self.halfmove_clock
+        });
+
+        self.set_piece(mv.from, EMPTY);
+        if mv.promotion != EMPTY {
+            self.set_piece(mv.to, piece_make(color, mv.promotion));
+        } else {
+            self.set_piece(mv.to, piece);
+        }
+        if let Some((rook_from, rook_to)) = castling_rook(piece, mv) {
+            let rook = self.piece_at(rook_from);
+            self.set_piece(rook_from, EMPTY);
+            self.set_piece(rook_to, rook);
+        }
+
+        let castling = self.castling & !(castling_lost(mv.from) | castling_lost(mv.to));
+        self.set_castling(castling);
+        if piece_type(piece) == PAWN || captured != EMPTY {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
+        }
+        if color == BLACK {
+            self.fullmove_number += 1;
+        }
+        self.set_to_move(color_flip(color));
+        let passed = (mv.from + mv.to) / 2;
+        if piece_type(piece) == PAWN && (mv.to - mv.from).abs() == 16 && self.can_capture_en_passant(passed) {
+            self.set_ep_square(passed);
+        } else {
+            self.set_ep_square(NULL_SQUARE);
+        }
+    }
+
+    //----------------------------------------------------------------------------------------------
+    // unmake():
+    //      Takes back the last move played with make(), returning it.
+    //
+    pub fn unmake(&mut self) -> Option<Move> {
+        let undo = match self.history.pop() {
+            Some(undo) => undo,
+            None => return None
+        };
+        let mv = undo.mv;
+        let color = color_flip(self.to_move);
+        let mut piece = self.piece_at(mv.to);
+        if mv.promotion != EMPTY {
+            piece = piece_make(color, PAWN);
+        }
+        if let Some((rook_from, rook_to)) = castling_rook(piece, mv) {
+            let rook = self.piece_at(rook_to);
+            self.set_piece(rook_to, EMPTY);
+            self.set_piece(rook_from, rook);
+        }
+        self.set_piece(mv.to, EMPTY);
+        self.set_piece(mv.from, piece);
+        if undo.en_passant {
+            self.set_piece(ep_capture_square(mv.to, color), undo.captured);
+        } else {
+            self.set_piece(mv.to, undo.captured);
+        }
+
+        self.set_castling(undo.castling);
+        self.set_ep_square(undo.ep_square);
+        self.halfmove_clock = undo.halfmove_clock;
+        if color == BLACK {
+            self.fullmove_number -= 1;
+        }
+        self.set_to_move(color);
+        Some(mv)
+    }
+
+    pub fn is_attacked(&self, sq: Square, by: Color) -> bool {
+        movegen::is_attacked(&self.squares, sq, by)
+    }
+
+    pub fn is_check(&self) -> bool {
+        let king = self.king_square(self.to_move);
+        king != NULL_SQUARE && self.is_attacked(king, color_flip(self.to_move))
+    }
+
+    pub fn status(&self) -> Status {
+        if !self.legal_moves().is_empty() {
+            Status::Ongoing
+        } else if self.is_check() {
+            Status::Checkmate
+        } else {
+            Status::Stalemate
+        }
+    }
+
+    pub fn is_checkmate(&self) -> bool {
+        self.status() == Status::Checkmate
+    }
+
+    pub fn is_stalemate(&self) -> bool {
+        self.status() == Status::Stalemate
+    }
+}
+
+// The square of the pawn captured when <color> captures en passant onto <to>.
+fn ep_capture_square(to: Square, color: Color) -> Square {
+    if color == WHITE { to - 8 } else { to + 8 }
+}
+
+// The rook's (from, to) squares if the move is a castling move.
+fn castling_rook(piece: Piece, mv: Move) -> Option<(Square, Square)> {
+    if piece_type(piece) != KING || (mv.to - mv.from).abs() != 2 {
+        return None;
+    }
+    if mv.to > mv.from {
+        Some((mv.to + 1, mv.to - 1))
+    } else {
+        Some((mv.to - 2, mv.to + 1))
+    }
+}
+
+// The castling rights that are lost when a piece moves to or from the square.
+fn castling_lost(sq: Square) -> UByte {
+    match sq {
+        A1 => WQ_CASTLE,
+        H1 => WK_CASTLE,
+        E1 => WQ_CASTLE | WK_CASTLE,
+        A8 => BQ_CASTLE,
+        H8 => BK_CASTLE,
+        E8 => BQ_CASTLE | BK_CASTLE,
+        _ => 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn perft(board: &mut Board, depth: u32) -> u64 {
+        if depth == 0 {
+            assert_eq!(board.hash(), board.compute_hash());
+            return 1;
+        }
+        let mut nodes = 0;
+        for mv in board.legal_moves() {
+            board.make(mv);
+            nodes += perft(board, depth - 1);
+            board.unmake();
+        }
+        nodes
+    }
+
+    fn perft_fen(fen: &str, depth: u32) -> u64 {
+        let mut board = Board::from_fen(fen).unwrap();
+        let nodes = perft(&mut board, depth);
+        assert_eq!(board.to_fen(), Board::from_fen(fen).unwrap().to_fen());
+        nodes
+    }
+
+    #[test]
+    fn test_perft_start() {
+        assert_eq!(perft_fen(START_FEN, 1), 20);
+        assert_eq!(perft_fen(START_FEN, 2), 400);
+        assert_eq!(perft_fen(START_FEN, 3), 8902);
+    }
+
+    #[test]
+    fn test_perft_castling_and_en_passant() {
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        assert_eq!(perft_fen(kiwipete, 1), 48);
+        assert_eq!(perft_fen(kiwipete, 2), 2039);
+        assert_eq!(perft_fen(kiwipete, 3), 97862);
+    }
+
+    #[test]
+    fn test_perft_promotions() {
+        let fen = "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1";
+        assert_eq!(perft_fen(fen, 1), 6);
+        assert_eq!(perft_fen(fen, 2), 264);
+        assert_eq!(perft_fen(fen, 3), 9467);
+        let fen = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1";
+        assert_eq!(perft_fen(fen, 4), 43238);
+    }
+
+    #[test]
+    fn test_checkmate_and_stalemate() {
+        let mut board = Board::start();
+        for san in &["f3", "e5", "g4"] {
+            let mv = board.parse_san(san).unwrap();
+            board.make(mv);
+        }
+        assert_eq!(board.status(), Status::Ongoing);
+        let mv = board.parse_san("Qh4#").unwrap();
+        board.make(mv);
+        assert!(board.is_check());
+        assert!(board.is_checkmate());
+
+        let board = Board::from_fen("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+        assert!(!board.is_check());
+        assert!(board.is_stalemate());
+    }
+
+    #[test]
+    fn test_material_signature() {
+        assert_eq!(Board::start().material_signature(), matsig::STD_START);
+        let board = Board::from_fen("4k3/pp6/8/8/8/8/3R4/4K3 w - - 0 1").unwrap();
+        assert_eq!(matsig::make_string(board.material_signature()), "R:20");
+    }
+
+    #[test]
+    fn test_make_unmake_restores_state() {
+        let fen = "r3k2r/8/8/3pP3/8/8/8/R3K2R w KQkq d6 4 20";
+        let mut board = Board::from_fen(fen).unwrap();
+        for uci in &["e5d6", "e1g1", "a1a8"] {
+            let mv = board.parse_uci(uci).unwrap();
+            board.make(mv);
+            board.unmake();
+            assert_eq!(board.to_fen(), fen);
+        }
+        assert_eq!(board.unmake(), None);
+    }
+}
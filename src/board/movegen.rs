@@ -0,0 +1,234 @@
+// delila - a desktop version of lila.
+//
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//--------------------------------------------------------------------------------------------------
+// Legal move generation
+//--------------------------------------------------------------------------------------------------
+
+use scid::common::*;
+use super::{Board, Move};
+
+const KNIGHT_JUMPS: [(Rank, File); 8] = [
+    (1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)
+];
+const ORTHOGONALS: [Direction; 4] = [UP, DOWN, LEFT, RIGHT];
+const DIAGONALS: [Direction; 4] = [UP_LEFT, UP_RIGHT, DOWN_LEFT, DOWN_RIGHT];
+const ALL_DIRECTIONS: [Direction; 8] = [
+    UP, DOWN, LEFT, RIGHT, UP_LEFT, UP_RIGHT, DOWN_LEFT, DOWN_RIGHT
+];
+const PROMOTIONS: [Piece; 4] = [QUEEN, ROOK, BISHOP, KNIGHT];
+
+fn knight_jump(sq: Square, (dr, df): (Rank, File)) -> Square {
+    let r = square_rank(sq) + dr;
+    let f = square_fyle(sq) + df;
+    if f < 0 || f > 7 || r < 0 || r > 7 {
+        return NULL_SQUARE;
+    }
+    square_make(f, r)
+}
+
+fn is_color(piece: Piece, color: Color) -> bool {
+    piece != EMPTY && piece_color(piece) == color
+}
+
+// Whether a piece of type <slider> (or a queen) of colour <by> attacks <sq> along <dir>.
+fn slider_attacks(squares: &[Piece; 64], sq: Square, dir: Direction, by: Color, slider: Piece) -> bool {
+    let mut current = square_move(sq, dir);
+    while current != NULL_SQUARE {
+        let piece = squares[current as usize];
+        if piece != EMPTY {
+            return piece == piece_make(by, slider) || piece == piece_make(by, QUEEN);
+        }
+        current = square_move(current, dir);
+    }
+    false
+}
+
+//--------------------------------------------------------------------------------------------------
+// is_attacked():
+//      Returns true if any piece of colour <by> attacks <sq>.
+//
+pub fn is_attacked(squares: &[Piece; 64], sq: Square, by: Color) -> bool {
+    let behind = if by == WHITE { DOWN } else { UP };
+    for side in &[LEFT, RIGHT] {
+        let from = square_move(sq, behind | side);
+        if from != NULL_SQUARE && squares[from as usize] == piece_make(by, PAWN) {
+            return true;
+        }
+    }
+    for jump in &KNIGHT_JUMPS {
+        let from = knight_jump(sq, *jump);
+        if from != NULL_SQUARE && squares[from as usize] == piece_make(by, KNIGHT) {
+            return true;
+        }
+    }
+    for dir in &ALL_DIRECTIONS {
+        let from = square_move(sq, *dir);
+        if from != NULL_SQUARE && squares[from as usize] == piece_make(by, KING) {
+            return true;
+        }
+    }
+    ORTHOGONALS.iter().any(|dir| slider_attacks(squares, sq, *dir, by, ROOK))
+        || DIAGONALS.iter().any(|dir| slider_attacks(squares, sq, *dir, by, BISHOP))
+}
+
+impl Board {
+    //----------------------------------------------------------------------------------------------
+    // legal_moves():
+    //      Returns every legal move in the position.
+    //
+    pub fn legal_moves(&self) -> Vec<Move> {
+        let mut moves = self.pseudo_legal_moves();
+        moves.retain(|mv| self.leaves_king_safe(*mv));
+        moves
+    }
+
+    pub fn is_legal(&self, mv: Move) -> bool {
+        self.legal_moves().contains(&mv)
+    }
+
+    // Whether the side to move would still have their king out of check after the move.
+    fn leaves_king_safe(&self, mv: Move) -> bool {
+        let color = self.to_move;
+        let piece = self.squares[mv.from as usize];
+        let mut squares = self.squares;
+        if piece_type(piece) == PAWN && mv.to == self.ep_square {
+            let captured = if color == WHITE { mv.to - 8 } else { mv.to + 8 };
+            squares[captured as usize] = EMPTY;
+        }
+        squares[mv.to as usize] = piece;
+        squares[mv.from as usize] = EMPTY;
+        let king = if piece_type(piece) == KING { mv.to } else { self.kings[color as usize] };
+        king == NULL_SQUARE || !is_attacked(&squares, king, color_flip(color))
+    }
+
+    fn pseudo_legal_moves(&self) -> Vec<Move> {
+        let mut moves = Vec::with_capacity(64);
+        let color = self.to_move;
+        for from in 0..64 {
+            let piece = self.squares[from as usize];
+            if !is_color(piece, color) {
+                continue;
+            }
+            match piece_type(piece) {
+                PAWN => self.pawn_moves(from, &mut moves),
+                KNIGHT => {
+                    for jump in &KNIGHT_JUMPS {
+                        self.step(from, knight_jump(from, *jump), &mut moves);
+                    }
+                },
+                BISHOP => self.slide(from, &DIAGONALS, &mut moves),
+                ROOK => self.slide(from, &ORTHOGONALS, &mut moves),
+                QUEEN => self.slide(from, &ALL_DIRECTIONS, &mut moves),
+                KING => {
+                    for dir in &ALL_DIRECTIONS {
+                        self.step(from, square_move(from, *dir), &mut moves);
+                    }
+                    self.castling_moves(from, &mut moves);
+                },
+                _ => {}
+            }
+        }
+        moves
+    }
+
+    fn step(&self, from: Square, to: Square, moves: &mut Vec<Move>) {
+        if to != NULL_SQUARE && !is_color(self.squares[to as usize], self.to_move) {
+            moves.push(Move::new(from, to));
+        }
+    }
+
+    fn slide(&self, from: Square, directions: &[Direction], moves: &mut Vec<Move>) {
+        for dir in directions {
+            let mut to = square_move(from, *dir);
+            while to != NULL_SQUARE {
+                let target = self.squares[to as usize];
+                if is_color(target, self.to_move) {
+                    break;
+                }
+                moves.push(Move::new(from, to));
+                if target != EMPTY {
+                    break;
+                }
+                to = square_move(to, *dir);
+            }
+        }
+    }
+
+    fn pawn_moves(&self, from: Square, moves: &mut Vec<Move>) {
+        let color = self.to_move;
+        let (forward, start_rank, last_rank) = if color == WHITE { (UP, 1, 7) } else { (DOWN, 6, 0) };
+        let push = |to: Square, moves: &mut Vec<Move>| {
+            if square_rank(to) == last_rank {
+                for promotion in &PROMOTIONS {
+                    moves.push(Move::promote(from, to, *promotion));
+                }
+            } else {
+                moves.push(Move::new(from, to));
+            }
+        };
+
+        let one = square_move(from, forward);
+        if one != NULL_SQUARE && self.squares[one as usize] == EMPTY {
+            push(one, moves);
+            let two = square_move(one, forward);
+            if square_rank(from) == start_rank && self.squares[two as usize] == EMPTY {
+                moves.push(Move::new(from, two));
+            }
+        }
+        for side in &[LEFT, RIGHT] {
+            let to = square_move(from, forward | side);
+            if to == NULL_SQUARE {
+                continue;
+            }
+            if is_color(self.squares[to as usize], color_flip(color)) || to == self.ep_square {
+                push(to, moves);
+            }
+        }
+    }
+
+    fn castling_moves(&self, from: Square, moves: &mut Vec<Move>) {
+        let color = self.to_move;
+        let (home, kingside, queenside) = if color == WHITE {
+            (E1, WK_CASTLE, WQ_CASTLE)
+        } else {
+            (E8, BK_CASTLE, BQ_CASTLE)
+        };
+        let enemy = color_flip(color);
+        if from != home || self.is_attacked(home, enemy) {
+            return;
+        }
+        let rook = piece_make(color, ROOK);
+        let empty = |squares: &[Square]| squares.iter().all(|sq| self.squares[*sq as usize] == EMPTY);
+        if self.castling & kingside != 0
+            && self.squares[(home + 3) as usize] == rook
+            && empty(&[home + 1, home + 2])
+            && !self.is_attacked(home + 1, enemy)
+            && !self.is_attacked(home + 2, enemy)
+        {
+            moves.push(Move::new(home, home + 2));
+        }
+        if self.castling & queenside != 0
+            && self.squares[(home - 4) as usize] == rook
+            && empty(&[home - 1, home - 2, home - 3])
+            && !self.is_attacked(home - 1, enemy)
+            && !self.is_attacked(home - 2, enemy)
+        {
+            moves.push(Move::new(home, home - 2));
+        }
+    }
+}
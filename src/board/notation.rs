@@ -0,0 +1,246 @@
+// delila - a desktop version of lila.
+//
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//--------------------------------------------------------------------------------------------------
+// Standard algebraic notation (SAN) and UCI long algebraic notation.
+//--------------------------------------------------------------------------------------------------
+
+use errors::*;
+use scid::common::*;
+use super::{Board, Move, Status};
+use super::fen::{piece_from_char, piece_to_char, square_from_str, square_to_string};
+
+fn illegal(notation: &str) -> Error {
+    ErrorKind::IllegalMove(notation.into()).into()
+}
+
+impl Move {
+    pub fn uci(&self) -> String {
+        let mut uci = square_to_string(self.from);
+        uci.push_str(&square_to_string(self.to));
+        if self.promotion != EMPTY {
+            uci.push(piece_to_char(piece_make(BLACK, self.promotion)));
+        }
+        uci
+    }
+}
+
+impl Board {
+    pub fn parse_uci(&self, uci: &str) -> Result<Move> {
+        let from = uci.get(0..2).and_then(square_from_str);
+        let to = uci.get(2..4).and_then(square_from_str);
+        let promotion = match uci.get(4..) {
+            None | Some("") => Some(EMPTY),
+            Some(p) if p.len() == 1 => p.chars().next().and_then(piece_from_char).map(piece_type),
+            _ => None
+        };
+        match (from, to, promotion) {
+            (Some(from), Some(to), Some(promotion)) => {
+                let mv = Move::promote(from, to, promotion);
+                if self.is_legal(mv) { Ok(mv) } else { Err(illegal(uci)) }
+            },
+            _ => Err(illegal(uci))
+        }
+    }
+
+    //----------------------------------------------------------------------------------------------
+    // parse_san():
+    //      Finds the legal move described by <san>. Check and annotation suffixes are ignored, and
+    //      a few common variations (0-0 for castling, a missing '=' before a promotion piece, an
+    //      "e.p." after an en passant capture) are accepted.
+    //
+    pub fn parse_san(&self, san: &str) -> Result<Move> {
+        let mut text = san.trim_end_matches(|c| "+#!?".contains(c));
+        if text.ends_with("e.p.") {
+            text = text[..text.len() - 4].trim_end().trim_end_matches(|c| "+#!?".contains(c));
+        }
+        let legal = self.legal_moves();
+
+        if text == "O-O" || text == "0-0" || text == "O-O-O" || text == "0-0-0" {
+            let king = self.king_square(self.to_move);
+            let to = if text.len() == 3 { king + 2 } else { king - 2 };
+            return legal.into_iter()
+                .find(|mv| mv.from == king && mv.to == to && piece_type(self.piece_at(king)) == KING)
+                .ok_or_else(|| illegal(san));
+        }
+
+        let mut chars: Vec<char> = text.chars().filter(|&c| c != 'x' && c != '-' && c != ':').collect();
+        let mut promotion = EMPTY;
+        if let Some(&last) = chars.last() {
+            if "QRBN".contains(last) && chars.len() > 2 {
+                promotion = piece_type(piece_from_char(last).unwrap_or(EMPTY));
+                chars.pop();
+                if chars.last() == Some(&'=') {
+                    chars.pop();
+                }
+            }
+        }
+        let piece = match chars.first() {
+            Some(&c) if "KQRBN".contains(c) => {
+                chars.remove(0);
+                piece_type(piece_from_char(c).unwrap_or(EMPTY))
+            },
+            _ => PAWN
+        };
+        if chars.len() < 2 {
+            return Err(illegal(san));
+        }
+        let destination: String = chars[chars.len() - 2..].iter().cloned().collect();
+        let to = square_from_str(&destination).ok_or_else(|| illegal(san))?;
+        let mut from_file = None;
+        let mut from_rank = None;
+        for c in &chars[..chars.len() - 2] {
+            match *c {
+                'a'..='h' => from_file = Some((*c as u8 - b'a') as File),
+                '1'..='8' => from_rank = Some((*c as u8 - b'1') as Rank),
+                _ => return Err(illegal(san))
+            }
+        }
+
+        let mut candidates = legal.into_iter().filter(|mv| {
+            mv.to == to
+                && mv.promotion == promotion
+                && piece_type(self.piece_at(mv.from)) == piece
+                && from_file.map_or(true, |f| square_fyle(mv.from) == f)
+                && from_rank.map_or(true, |r| square_rank(mv.from) == r)
+        });
+        match (candidates.next(), candidates.next()) {
+            (Some(mv), None) => Ok(mv),
+            _ => Err(illegal(san))
+        }
+    }
+
+    //----------------------------------------------------------------------------------------------
+    // san():
+    //      Returns the SAN for a legal move, including a check or mate suffix.
+    //
+    pub fn san(&self, mv: Move) -> String {
+        let piece = self.piece_at(mv.from);
+        let mut san = String::with_capacity(8);
+        if piece_type(piece) == KING && (mv.to - mv.from).abs() == 2 {
+            san.push_str(if mv.to > mv.from { "O-O" } else { "O-O-O" });
+        } else {
+            let capture = self.piece_at(mv.to) != EMPTY
+                || (piece_type(piece) == PAWN && mv.to == self.ep_square);
+            if piece_type(piece) == PAWN {
+                if capture {
+                    san.push((b'a' + square_fyle(mv.from) as u8) as char);
+                }
+            } else {
+                san.push(piece_to_char(piece_make(WHITE, piece)));
+                san.push_str(&self.disambiguation(mv));
+            }
+            if capture {
+                san.push('x');
+            }
+            san.push_str(&square_to_string(mv.to));
+            if mv.promotion != EMPTY {
+                san.push('=');
+                san.push(piece_to_char(piece_make(WHITE, mv.promotion)));
+            }
+        }
+
+        let mut after = self.snapshot();
+        after.make(mv);
+        if after.is_check() {
+            san.push(if after.status() == Status::Checkmate { '#' } else { '+' });
+        }
+        san
+    }
+
+    fn disambiguation(&self, mv: Move) -> String {
+        let piece = self.piece_at(mv.from);
+        let others: Vec<Move> = self.legal_moves().into_iter()
+            .filter(|other| other.to == mv.to && other.from != mv.from && self.piece_at(other.from) == piece)
+            .collect();
+        if others.is_empty() {
+            return String::new();
+        }
+        let square = square_to_string(mv.from);
+        if others.iter().all(|other| square_fyle(other.from) != square_fyle(mv.from)) {
+            square[0..1].to_string()
+        } else if others.iter().all(|other| square_rank(other.from) != square_rank(mv.from)) {
+            square[1..2].to_string()
+        } else {
+            square
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(board: &mut Board, sans: &[&str]) {
+        for san in sans {
+            let mv = board.parse_san(san).unwrap();
+            assert_eq!(board.san(mv), *san);
+            board.make(mv);
+        }
+    }
+
+    #[test]
+    fn test_san_round_trip() {
+        let mut board = Board::start();
+        play(&mut board, &[
+            "e4", "e5", "Nf3", "Nc6", "Bb5", "a6", "Ba4", "Nf6", "O-O", "Be7",
+            "Re1", "b5", "Bb3", "d6", "c3", "O-O", "h3", "Nb8", "d4", "Nbd7",
+        ]);
+        assert_eq!(board.to_fen(), "r1bq1rk1/2pnbppp/p2p1n2/1p2p3/3PP3/1BP2N1P/PP3PP1/RNBQR1K1 w - - 1 11");
+    }
+
+    #[test]
+    fn test_disambiguation() {
+        let board = Board::from_fen("4k3/8/8/8/R6R/8/8/R3K3 w - - 0 1").unwrap();
+        assert_eq!(board.san(Move::new(A4, D4)), "Rad4");
+        assert_eq!(board.san(Move::new(A1, A2)), "R1a2");
+        assert_eq!(board.san(Move::new(A4, A2)), "R4a2");
+        assert_eq!(board.parse_san("Rhd4").unwrap(), Move::new(H4, D4));
+        assert!(board.parse_san("Rd4").is_err());
+    }
+
+    #[test]
+    fn test_promotions_and_en_passant() {
+        let board = Board::from_fen("1r2k3/P7/8/3pP3/8/8/8/4K3 w - d6 0 1").unwrap();
+        assert_eq!(board.san(Move::promote(A7, B8, QUEEN)), "axb8=Q+");
+        assert_eq!(board.parse_san("axb8N").unwrap(), Move::promote(A7, B8, KNIGHT));
+        assert_eq!(board.parse_san("a8=R+").unwrap(), Move::promote(A7, A8, ROOK));
+        assert_eq!(board.san(Move::new(E5, D6)), "exd6");
+        assert_eq!(board.parse_san("exd6").unwrap(), Move::new(E5, D6));
+        assert_eq!(board.parse_san("exd6e.p.").unwrap(), Move::new(E5, D6));
+        assert_eq!(board.parse_san("exd6 e.p.").unwrap(), Move::new(E5, D6));
+        assert_eq!(board.parse_san("exd6+ e.p.").unwrap(), Move::new(E5, D6));
+        assert!(board.parse_san("e.p.").is_err());
+    }
+
+    #[test]
+    fn test_uci() {
+        let board = Board::from_fen("1r2k3/P7/8/8/8/8/8/4K2R w K - 0 1").unwrap();
+        assert_eq!(board.parse_uci("e1g1").unwrap().uci(), "e1g1");
+        assert_eq!(board.parse_uci("a7b8q").unwrap(), Move::promote(A7, B8, QUEEN));
+        assert_eq!(Move::promote(A7, B8, KNIGHT).uci(), "a7b8n");
+        assert!(board.parse_uci("a7a8").is_err());
+        assert!(board.parse_uci("e1e3").is_err());
+        assert!(board.parse_uci("zz").is_err());
+    }
+
+    #[test]
+    fn test_checkmate_suffix() {
+        let mut board = Board::start();
+        play(&mut board, &["e4", "e5", "Bc4", "Nc6", "Qh5", "Nf6", "Qxf7#"]);
+    }
+}
@@ -0,0 +1,157 @@
+// delila - a desktop version of lila.
+//
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//--------------------------------------------------------------------------------------------------
+// Zobrist hashing
+//
+// Positions are identified in the database by a 128 bit Zobrist hash, stored as two 64 bit halves
+// (position.hash_1 and position.hash_2). The keys are derived from their index with splitmix64
+// instead of being read from a table, so they are the same on every machine and in every version,
+// which matters because the hashes are persisted.
+//--------------------------------------------------------------------------------------------------
+
+use scid::common::*;
+
+const SEED_1: u64 = 0x2545_f491_4f6c_dd1d;
+const SEED_2: u64 = 0x9e6c_63d0_676a_9a99;
+
+// Key indices. Pieces use piece * 64 + square, which leaves room for every SCID piece value.
+const SIDE_TO_MOVE: u64 = 16 * 64;
+const CASTLING: u64 = SIDE_TO_MOVE + 1;
+const EN_PASSANT_FILE: u64 = CASTLING + 4;
+
+fn splitmix64(seed: u64, index: u64) -> u64 {
+    let mut z = seed.wrapping_add(index.wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ZobristHash {
+    pub hash_1: u64,
+    pub hash_2: u64
+}
+
+impl ZobristHash {
+    fn toggle(&mut self, index: u64) {
+        self.hash_1 ^= splitmix64(SEED_1, index);
+        self.hash_2 ^= splitmix64(SEED_2, index);
+    }
+
+    pub fn toggle_piece(&mut self, piece: Piece, sq: Square) {
+        if piece != EMPTY {
+            self.toggle(piece as u64 * 64 + sq as u64);
+        }
+    }
+
+    pub fn toggle_side_to_move(&mut self) {
+        self.toggle(SIDE_TO_MOVE);
+    }
+
+    pub fn toggle_castling(&mut self, castling: UByte) {
+        for bit in 0..4 {
+            if castling & (1 << bit) != 0 {
+                self.toggle(CASTLING + bit as u64);
+            }
+        }
+    }
+
+    pub fn toggle_ep_square(&mut self, sq: Square) {
+        if sq != NULL_SQUARE {
+            self.toggle(EN_PASSANT_FILE + square_fyle(sq) as u64);
+        }
+    }
+
+    // The two halves as they are stored in the position table.
+    pub fn to_columns(&self) -> (i64, i64) {
+        (self.hash_1 as i64, self.hash_2 as i64)
+    }
+
+    pub fn from_columns(hash_1: i64, hash_2: i64) -> ZobristHash {
+        ZobristHash{hash_1: hash_1 as u64, hash_2: hash_2 as u64}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Board;
+
+    fn play(board: &mut Board, sans: &[&str]) {
+        for san in sans {
+            let mv = board.parse_san(san).unwrap();
+            board.make(mv);
+            assert_eq!(board.hash(), board.compute_hash());
+        }
+    }
+
+    #[test]
+    fn test_keys_are_stable() {
+        // These are persisted, so they must never change.
+        assert_eq!(splitmix64(0, 0), 0xe220_a839_7b1d_cdaf);
+        let start = Board::start().hash();
+        assert_eq!(start, Board::start().hash());
+        assert!(start.hash_1 != start.hash_2);
+    }
+
+    #[test]
+    fn test_transpositions() {
+        let mut a = Board::start();
+        play(&mut a, &["e4", "e6", "d4", "d5"]);
+        let mut b = Board::start();
+        play(&mut b, &["d4", "e6", "e4", "d5"]);
+        assert_eq!(a.hash(), b.hash());
+
+        // The same pieces with a different side to move are different positions.
+        let mut c = Board::start();
+        play(&mut c, &["Nf3", "Nf6", "Ng1", "Ng8"]);
+        assert_eq!(c.hash(), Board::start().hash());
+        play(&mut c, &["Nf3", "Nf6", "Ng1"]);
+        assert!(c.hash() != Board::start().hash());
+    }
+
+    #[test]
+    fn test_castling_and_en_passant_matter() {
+        let with = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        let without = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w Kkq - 0 1").unwrap();
+        assert!(with.hash() != without.hash());
+
+        // A double push only counts as a different position when it can be captured.
+        let mut a = Board::start();
+        play(&mut a, &["e4", "Nf6", "e5", "d5"]);
+        let mut b = Board::start();
+        play(&mut b, &["e4", "d5", "e5", "Nf6"]);
+        assert!(a.hash() != b.hash());
+        let mut c = Board::start();
+        play(&mut c, &["e4", "e5"]);
+        assert_eq!(c.ep_square(), NULL_SQUARE);
+    }
+
+    #[test]
+    fn test_incremental_unmake() {
+        let mut board = Board::from_fen("r3k2r/1P6/8/3pP3/8/8/8/R3K2R w KQkq d6 0 1").unwrap();
+        let start = board.hash();
+        for uci in &["e5d6", "e1c1", "b7a8q", "h1h8"] {
+            let mv = board.parse_uci(uci).unwrap();
+            board.make(mv);
+            assert_eq!(board.hash(), board.compute_hash());
+            board.unmake();
+            assert_eq!(board.hash(), start);
+        }
+    }
+}
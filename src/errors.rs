@@ -1,4 +1,5 @@
 use std::io;
+use diesel;
 use hyper;
 use serde_json;
 
@@ -7,5 +8,85 @@ error_chain! {
         IO(io::Error);
         Hyper(hyper::error::Error);
         SerdeJson(serde_json::Error);
+        Diesel(diesel::result::Error);
+    }
+
+    errors {
+        PgnSyntax(line: usize, message: String) {
+            description("invalid PGN")
+            display("Invalid PGN on line {}: {}", line, message)
+        }
+        InvalidFen(fen: String, reason: String) {
+            description("invalid FEN")
+            display("Invalid FEN '{}': {}", fen, reason)
+        }
+        IllegalMove(notation: String) {
+            description("illegal move")
+            display("Illegal or ambiguous move: {}", notation)
+        }
+        InvalidMaterialPattern(pattern: String, reason: String) {
+            description("invalid material pattern")
+            display("Invalid material pattern '{}': {}", pattern, reason)
+        }
+        ScidFormat(reason: String) {
+            description("invalid SCID data")
+            display("Invalid SCID data: {}", reason)
+        }
+        InvalidEcoFile(line: usize, reason: String) {
+            description("invalid ECO file")
+            display("Invalid ECO file on line {}: {}", line, reason)
+        }
+        InvalidSpellingFile(line: usize, reason: String) {
+            description("invalid spelling file")
+            display("Invalid spelling file on line {}: {}", line, reason)
+        }
+        MalformedMessage {
+            description("malformed message")
+            display("Unable to parse the incoming message")
+        }
+        UnknownCommand(name: String) {
+            description("unknown command")
+            display("Unknown command: {}", name)
+        }
+        InvalidArguments(name: String) {
+            description("invalid arguments")
+            display("Invalid arguments for {}", name)
+        }
+        VersionMismatch(client_version: String, server_version: String) {
+            description("version mismatch")
+            display("Client version {} doesn't match server version {}", client_version, server_version)
+        }
+        UnknownSetting(name: String) {
+            description("unknown setting")
+            display("Unknown setting: {}", name)
+        }
+        InvalidSetting(name: String, value: String) {
+            description("invalid setting")
+            display("Invalid value '{}' for {}", value, name)
+        }
+        InvalidDatabaseId(id: String) {
+            description("invalid database id")
+            display("Invalid database id '{}': use letters, digits, spaces, '-' and '_'", id)
+        }
+        UnknownDatabase(id: String) {
+            description("unknown database")
+            display("There is no database called '{}'", id)
+        }
+        DatabaseExists(id: String) {
+            description("database exists")
+            display("There is already a database called '{}'", id)
+        }
+        Unauthorized {
+            description("unauthorized")
+            display("This connection hasn't presented the server's token")
+        }
+        UnknownSession {
+            description("unknown session")
+            display("There is no session with requests to resume")
+        }
+        Cancelled {
+            description("cancelled")
+            display("The request was cancelled")
+        }
     }
 }
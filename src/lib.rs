@@ -24,12 +24,15 @@
 #[macro_use] extern crate diesel_infer_schema;
 #[macro_use] extern crate diesel_migrations;
              extern crate hyper;
+             extern crate rand;
+             extern crate rmp_serde;
              extern crate serde;
              extern crate serde_json;
 #[macro_use] extern crate serde_derive;
 #[macro_use] extern crate slog;
              extern crate slog_async;
              extern crate slog_term;
+             extern crate toml;
              extern crate ws;
 
 #[macro_use] extern crate error_chain;
@@ -38,15 +41,26 @@ use diesel::prelude::*;
 use diesel::sqlite::SqliteConnection;
 use std::env;
 
+no_arg_sql_function!(last_insert_rowid, diesel::sql_types::Integer,
+    "The rowid of the most recent successful INSERT on this connection.");
+
 pub mod app_info;
+pub mod auth;
+pub mod board;
 pub mod errors;
 pub mod models;
 pub mod pathsettings;
+pub mod pgn;
 pub mod schema;
 pub mod scid;
+pub mod settings;
 pub mod tasks;
 
 pub fn establish_connection(database_url: &str) -> SqliteConnection {
     SqliteConnection::establish(&database_url)
         .expect(&format!("Error connecting to {}", database_url))
 }
+
+pub fn last_insert_id(conn: &SqliteConnection) -> QueryResult<i32> {
+    diesel::select(last_insert_rowid).get_result(conn)
+}
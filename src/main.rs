@@ -41,23 +41,28 @@
 use chrono::Local;
 use std::collections::HashMap;
 use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+use std::str;
 use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
 
-use futures::{Async, Future};
-use futures_cpupool::{CpuPool, CpuFuture};
+use futures_cpupool::CpuPool;
 
 use slog::Drain;
 
 use diesel_migrations::setup_database;
 
 // Ours
-use delila::tasks::{Message, Request, RequestDispatch, JSONDispatch};
-use delila::tasks::{
-    initialize,
-    import,
-};
+use delila::auth::{Authenticated, Credentials, Token, handshake_token, origin_allowed};
+use delila::tasks::{Connection, Message, Request, RequestDispatch, PROTOCOL_VERSION};
+use delila::tasks::{decode_binary, decode_text, send_error};
+use delila::tasks::commands;
+use delila::tasks::task::{TaskRegistry, TaskState};
 use delila::app_info::{DELILA_VERSION};
 use delila::pathsettings::{PathSettings};
+use delila::scid::eco::EcoBook;
+use delila::scid::spelling::Spelling;
+use delila::settings::Settings;
 use delila::establish_connection;
 
 pub mod errors;
@@ -70,53 +75,174 @@ macro_rules! today {
 
 struct Server
 {
-    out: ws::Sender,
+    connection: Connection,
     commands: HashMap<String, Arc<RequestDispatch + Send + Sync>>,
     pool: CpuPool,
-    futures: std::vec::Vec<CpuFuture<(), Error>>,
     path_settings: PathSettings,
+    tasks: Arc<TaskRegistry>,
+    token: Arc<Token>,
+    eco: Option<Arc<EcoBook>>,
+    spelling: Option<Arc<Spelling>>,
+    allowed_origins: Vec<String>,
+    // Nothing but auth::authenticate is dispatched until the connection has shown the token.
+    authenticated: bool,
     log: slog::Logger
 }
 
 
+impl Server {
+    fn request(&self, incoming: &Message, cancelled: Arc<AtomicBool>) -> Request {
+        Request{
+            id: incoming.id,
+            name: incoming.name.clone(),
+            connection: self.connection.clone(),
+            session: self.connection.session(),
+            log: self.log.new(o!(
+                "name" => incoming.name.clone(),
+                "id" => incoming.id
+            )),
+            path_settings: self.path_settings.clone(),
+            tasks: self.tasks.clone(),
+            cancelled: cancelled,
+            database: incoming.database.clone().or_else(|| self.connection.database()),
+            eco: self.eco.clone(),
+            spelling: self.spelling.clone()
+        }
+    }
+
+    // Answers auth::authenticate, which is the only message read before the token is shown.
+    fn authenticate(&mut self, incoming: Message) {
+        let id = incoming.id;
+        let token = if incoming.name == "auth::authenticate" {
+            serde_json::from_value::<Credentials>(incoming.args).ok().map(|credentials| credentials.token)
+        } else {
+            None
+        };
+        match token {
+            Some(ref token) if self.token.matches(token) => {
+                self.authenticated = true;
+                let reply = serde_json::to_value(&Authenticated{authenticated: true})
+                    .chain_err(|| "Unable to serialize outoing args")
+                    .and_then(|args| self.connection.send(
+                        &Message{name: "response".into(), id: id, args: args, database: None}
+                    ));
+                if let Err(e) = reply {
+                    error!(self.log, "Unable to send the final reply: {}", e);
+                }
+            },
+            _ => {
+                let error: Error = ErrorKind::Unauthorized.into();
+                self.reply_with_error(Some(id), &error);
+            }
+        }
+    }
+
+    fn reply_with_error(&self, request_id: Option<u32>, error: &Error) {
+        warn!(self.log, "Rejected a message: {}", error);
+        if let Err(e) = send_error(&self.connection, request_id, error) {
+            error!(self.log, "Unable to send the error reply: {}", e);
+        }
+    }
+}
+
 impl ws::Handler for Server {
 
-    fn on_open(&mut self, _: ws::Handshake) -> ws::Result<()> {
-        // We have a new connection, so we increment the connection counter
+    fn on_open(&mut self, handshake: ws::Handshake) -> ws::Result<()> {
+        // Browsers send the page's origin, so other sites can't talk to the server from
+        // their pages. Clients that aren't browsers don't send one.
+        let origin = handshake.request.origin()?;
+        if !origin_allowed(origin, &self.allowed_origins) {
+            warn!(self.log, "Refused a connection from {}", origin.unwrap_or(""));
+            if let Err(e) = self.connection.refuse("Origin not allowed") {
+                error!(self.log, "{}", e);
+            }
+            return Ok(());
+        }
+        // The token may come with the handshake, or else in an auth::authenticate message.
+        let authorization = handshake.request.header("Authorization")
+            .and_then(|value| str::from_utf8(value).ok());
+        self.authenticated = handshake_token(handshake.request.resource(), authorization)
+            .map_or(false, |token| self.token.matches(token));
         Ok(())
     }
 
     fn on_message(&mut self, msg: ws::Message) -> ws::Result<()> {
-        match msg {
+        let (envelope, protocol) = match msg {
             ws::Message::Text(txt) => {
-                for i in self.futures.len()..0 {
-                    match self.futures[i].poll() {
-                        Ok(Async::NotReady) => { },
-                        Ok(Async::Ready(_)) | Err(_) => { self.futures.swap_remove(i); }
-                    }
-                }
-                let incoming: Message = serde_json::from_str(&txt).unwrap();
-                let dispatcher = self.commands[&incoming.name].clone();
-                let request: Request = Request{
-                    id: incoming.id,
-                    name: incoming.name.clone(),
-                    out: self.out.clone(),
-                    log: self.log.new(o!(
-                        "name" => incoming.name,
-                        "id" => incoming.id
-                    )),
-                    path_settings: self.path_settings.clone()
-                };
-                let args = incoming.args.clone();
-                let future = self.pool.spawn_fn(move || {
-                    dispatcher.dispatch(&request, args)
-                });
-                self.futures.push(future);
+                self.connection.set_binary(false);
+                (decode_text(&txt), self.connection.protocol())
             },
-            ws::Message::Binary(_) => {
-                println!("Unable to handle binary messages!");
+            ws::Message::Binary(bytes) => {
+                self.connection.set_binary(true);
+                // Binary frames came after the legacy protocol.
+                (decode_binary(&bytes), PROTOCOL_VERSION)
+            }
+        };
+        let envelope = match envelope {
+            Ok(envelope) => envelope,
+            Err(e) => {
+                self.reply_with_error(None, &e);
+                return Ok(());
             }
+        };
+        // A message that doesn't fit may still say which request it was.
+        let id = envelope.get("id").and_then(|id| id.as_u64()).map(|id| id as u32);
+        let incoming = match Message::from_envelope(envelope, protocol) {
+            Ok(incoming) => incoming,
+            Err(e) => {
+                self.reply_with_error(id, &e);
+                return Ok(());
+            }
+        };
+        if !self.authenticated || incoming.name == "auth::authenticate" {
+            self.authenticate(incoming);
+            return Ok(());
         }
+        let dispatcher = match self.commands.get(&incoming.name) {
+            Some(dispatcher) => dispatcher.clone(),
+            None => {
+                let error: Error = ErrorKind::UnknownCommand(incoming.name.clone()).into();
+                self.reply_with_error(Some(incoming.id), &error);
+                return Ok(());
+            }
+        };
+        // Task commands only look at the requests that are running, and mustn't wait in
+        // the pool behind the ones that they are meant to cancel.
+        if incoming.name.starts_with("task::") {
+            let request = self.request(&incoming, Arc::new(AtomicBool::new(false)));
+            if let Err(e) = dispatcher.dispatch(&request, incoming.args) {
+                self.reply_with_error(Some(incoming.id), &e);
+            }
+            return Ok(());
+        }
+        let cancelled = self.tasks.start(&self.connection, incoming.id, &incoming.name);
+        let request = self.request(&incoming, cancelled);
+        let args = incoming.args;
+        let future = self.pool.spawn_fn(move || {
+            let reply = match dispatcher.dispatch(&request, args) {
+                Err(Error(ErrorKind::Cancelled, _)) => {
+                    info!(request.log, "{} was cancelled", request.name);
+                    request.tasks.finish(&request.session, request.id, TaskState::Cancelled);
+                    request.send_cancelled()
+                },
+                Err(ref e) => {
+                    error!(request.log, "{} failed: {}", request.name, e);
+                    request.tasks.finish(&request.session, request.id, TaskState::Failed);
+                    request.send_error(e)
+                },
+                Ok(()) => {
+                    request.tasks.finish(&request.session, request.id, TaskState::Finished);
+                    Ok(())
+                }
+            };
+            if let Err(e) = reply {
+                error!(request.log, "Unable to send the final reply: {}", e);
+            }
+            Ok::<(), Error>(())
+        });
+        // Dropping the future would cancel the request, and its outcome is kept in the
+        // task registry instead.
+        future.forget();
 
         Ok(()) 
     }
@@ -160,12 +286,15 @@ fn main() {
 
 fn run() -> Result<()> {
     configure_directories()
-    .and_then(|path_settings| {
-        configure_logging(&path_settings.logging_path)
-        .and_then(|log| {
-            ensure_database_exists(&path_settings.database_path)
-            .and_then(|_| {
-                run_server(&path_settings, &log)
+    .and_then(|mut path_settings| {
+        configure_settings(&mut path_settings)
+        .and_then(|settings| {
+            configure_logging(&path_settings.logging_path, &settings)
+            .and_then(|log| {
+                ensure_database_exists(&path_settings.database_path)
+                .and_then(|_| {
+                    run_server(&path_settings, &settings, &log)
+                })
             })
         })
     })
@@ -184,36 +313,96 @@ fn ensure_database_exists(db_path: &std::path::PathBuf) -> Result<()> {
 }
 
 //--------------------------------------------------------------------------------------------------
-fn run_server(path_settings: &PathSettings, log: &slog::Logger) -> Result<()> {
+fn run_server(path_settings: &PathSettings, settings: &Settings, log: &slog::Logger) -> Result<()> {
     info!(log, "Starting Server");
-    ws::listen("127.0.0.1:3012", |out| {
-        info!(log, "Listening on 127.0.0.1:3012");
-
-        let mut commands: HashMap<String, Arc<RequestDispatch + Send + Sync>> = HashMap::new();
-        commands.insert("import::importFile".into(),
-            Arc::new(JSONDispatch{handler: Arc::new(import::import_file)})
-        );
-        commands.insert("initialize::initialize".into(),
-            Arc::new(JSONDispatch{handler: Arc::new(initialize::initialize)})
-        );
+    let tasks = Arc::new(TaskRegistry::new());
+    let pool = match settings.workers {
+        0 => CpuPool::new_num_cpus(),
+        workers => CpuPool::new(workers)
+    };
+    let token = Token::generate()?;
+    let token_path = token.write(&path_settings.settings_database_path)?;
+    info!(log, "Wrote the connection token to {}", token_path.display());
+    let token = Arc::new(token);
+    let eco = load_optional(
+        "opening file", &settings.eco_path, path_settings.settings_database_path.join("scid.eco"),
+        log, EcoBook::load
+    ).map(Arc::new);
+    let spelling = load_optional(
+        "spelling file", &settings.spelling_path, path_settings.settings_database_path.join("spelling.ssp"),
+        log, Spelling::load
+    ).map(Arc::new);
+    let address = settings.listen_address();
+    ws::listen(address.as_str(), |out| {
+        info!(log, "Listening on {}", address);
+
+        let commands: HashMap<String, Arc<RequestDispatch + Send + Sync>> = commands::all()
+            .into_iter()
+            .map(|command| (command.name.to_string(), command.dispatch))
+            .collect();
         Server {
-            out: out,
+            connection: Connection::new(out),
             commands: commands,
-            pool: CpuPool::new_num_cpus(),
-            futures: std::vec::Vec::new(),
+            pool: pool.clone(),
             path_settings: path_settings.clone(),
+            tasks: tasks.clone(),
+            token: token.clone(),
+            eco: eco.clone(),
+            spelling: spelling.clone(),
+            allowed_origins: settings.allowed_origins.clone(),
+            authenticated: false,
             log: log.clone()
         }
     }).chain_err(|| "Unable to start server")
 }
 
+//--------------------------------------------------------------------------------------------------
+// Loads one of the optional data files, from its setting or else from the settings directory.
+// Games are imported without ECO codes or corrected names if the files aren't there, so a missing
+// or broken one is logged rather than stopping the server.
+fn load_optional<T, F>(what: &str, configured: &Option<PathBuf>, default: PathBuf, log: &slog::Logger,
+                       load: F) -> Option<T>
+    where F: Fn(&Path) -> Result<T>
+{
+    let path = match *configured {
+        Some(ref path) => path.clone(),
+        None if default.exists() => default,
+        None => {
+            info!(log, "No {} at {}", what, default.display());
+            return None;
+        }
+    };
+    match load(&path) {
+        Ok(loaded) => {
+            info!(log, "Loaded the {} from {}", what, path.display());
+            Some(loaded)
+        },
+        Err(error) => {
+            warn!(log, "Unable to load the {}: {}", what, error);
+            None
+        }
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 fn configure_directories() -> Result<PathSettings> {
     PathSettings::new()
 }
 
 //--------------------------------------------------------------------------------------------------
-fn configure_logging(logging_path: &std::path::PathBuf) -> Result<slog::Logger> {
+fn configure_settings(path_settings: &mut PathSettings) -> Result<Settings> {
+    let mut settings = Settings::load(&path_settings.settings_database_path)?;
+    settings.apply_environment(std::env::vars())?;
+    settings.apply_arguments(std::env::args().skip(1))?;
+    if let Some(ref database_path) = settings.database_path {
+        path_settings.database_path = database_path.clone();
+    }
+    Ok(settings)
+}
+
+//--------------------------------------------------------------------------------------------------
+fn configure_logging(logging_path: &std::path::PathBuf, settings: &Settings) -> Result<slog::Logger> {
+    let level = settings.log_level()?;
     let mut log_directory = logging_path.clone();
     log_directory.push(format!("delila.{}.log", today!()));
     OpenOptions::new()
@@ -230,6 +419,7 @@ fn configure_logging(logging_path: &std::path::PathBuf) -> Result<slog::Logger>
             let async_console_drain = slog_async::Async::new(console_drain).build().fuse();
 
             let drain = slog::Duplicate::new(async_console_drain, async_file_drain).fuse();
+            let drain = slog::LevelFilter::new(drain, level).fuse();
             let _log = slog::Logger::root(drain, o!("version" => DELILA_VERSION));
             Ok(_log)
         }).chain_err(|| "Unable to open log file")
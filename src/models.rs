@@ -1,27 +1,75 @@
-use chrono::prelude::*;
+use super::schema::*;
 
 #[derive(Queryable,Serialize,Deserialize)]
 pub struct Position {
-    pub id: i64,
+    pub id: i32,
+    pub hash_1: i64,
+    pub hash_2: i64,
+}
+
+#[derive(Insertable)]
+#[table_name="position"]
+pub struct NewPosition {
     pub hash_1: i64,
     pub hash_2: i64,
 }
 
 #[derive(Queryable,Serialize,Deserialize)]
 pub struct _Move {
-    pub id: i64,
+    pub id: i32,
     pub uci: String,
-    pub starting_position_id: i64,
-    pub ending_position_id: i64,
+    pub starting_position_id: i32,
+    pub ending_position_id: i32,
+}
+
+#[derive(Insertable)]
+#[table_name="_move"]
+pub struct NewMove<'a> {
+    pub uci: &'a str,
+    pub starting_position_id: i32,
+    pub ending_position_id: i32,
 }
 
 #[derive(Queryable,Serialize,Deserialize)]
 pub struct LineMove {
-    pub id: i64,
-    pub move_id: i64,
-    pub tag_id: i64,
-    pub line_id: i64,
-    pub ply: i16,
+    pub id: i32,
+    pub move_id: i32,
+    pub line_id: i32,
+    pub ply: i32,
+    pub san: String,
+    pub comment: Option<String>,
+    pub nags: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name="line_move"]
+pub struct NewLineMove<'a> {
+    pub move_id: i32,
+    pub line_id: i32,
+    pub ply: i32,
+    pub san: &'a str,
+    pub comment: Option<&'a str>,
+    pub nags: Option<String>,
+}
+
+#[derive(Queryable,Serialize,Deserialize)]
+pub struct Line {
+    pub id: i32,
+    pub starting_position_id: i32,
+    pub parent_line_id: Option<i32>,
+    pub game_id: Option<i32>,
+    pub parent_ply: Option<i32>,
+    pub comment: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name="line"]
+pub struct NewLine<'a> {
+    pub starting_position_id: i32,
+    pub parent_line_id: Option<i32>,
+    pub game_id: Option<i32>,
+    pub parent_ply: Option<i32>,
+    pub comment: Option<&'a str>,
 }
 
 #[derive(Queryable,Serialize,Deserialize)]
@@ -29,7 +77,17 @@ pub struct Player {
     pub id: i32,
     pub first_name: String,
     pub last_name: String,
-    pub middlename: Option<String>,
+    pub middle_name: Option<String>,
+    pub fide_id: Option<i32>,
+}
+
+#[derive(Insertable)]
+#[table_name="player"]
+pub struct NewPlayer<'a> {
+    pub first_name: &'a str,
+    pub last_name: &'a str,
+    pub middle_name: Option<&'a str>,
+    pub fide_id: Option<i32>,
 }
 
 #[derive(Queryable,Serialize,Deserialize)]
@@ -38,7 +96,16 @@ pub struct Event {
     pub name: String,
     pub city: String,
     pub country: String,
-    pub year: i16
+    pub year: i32
+}
+
+#[derive(Insertable)]
+#[table_name="event"]
+pub struct NewEvent<'a> {
+    pub name: &'a str,
+    pub city: &'a str,
+    pub country: &'a str,
+    pub year: i32
 }
 
 #[derive(Queryable,Serialize,Deserialize)]
@@ -47,16 +114,50 @@ pub struct Site {
     pub name: String
 }
 
+#[derive(Insertable)]
+#[table_name="site"]
+pub struct NewSite<'a> {
+    pub name: &'a str
+}
+
+// Dates are stored the way PGN writes them (YYYY.MM.DD, with ?? for unknown parts), so that they
+// still sort and compare correctly as strings.
 #[derive(Queryable,Serialize,Deserialize)]
 pub struct Game {
     pub id: i32,
     pub white_player_id: i32,
-    pub white_player_rating: i16,
+    pub white_player_rating: i32,
+    pub black_player_id: i32,
+    pub black_player_rating: i32,
+    pub event_id: Option<i32>,
+    pub site_id: Option<i32>,
+    pub date: String,
+    pub round: Option<i32>,
+    pub result: String,
+    pub pgn: String,
+    pub line_id: i32,
+    pub matsig: i32,
+    pub promotions: bool,
+    pub underpromotions: bool,
+    pub eco: Option<String>
+}
+
+#[derive(Insertable)]
+#[table_name="game"]
+pub struct NewGame<'a> {
+    pub white_player_id: i32,
+    pub white_player_rating: i32,
     pub black_player_id: i32,
-    pub black_player_rating: i16,
-    pub event_id: i32,
-    pub site_id: i32,
-    pub date: NaiveDate,
-    pub round: i16,
-    pub result: String
+    pub black_player_rating: i32,
+    pub event_id: Option<i32>,
+    pub site_id: Option<i32>,
+    pub date: &'a str,
+    pub round: Option<i32>,
+    pub result: &'a str,
+    pub pgn: &'a str,
+    pub line_id: i32,
+    pub matsig: i32,
+    pub promotions: bool,
+    pub underpromotions: bool,
+    pub eco: Option<&'a str>
 }
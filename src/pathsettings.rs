@@ -3,11 +3,25 @@ use app_dirs::{app_dir, AppDataType};
 use super::app_info::{DELILA_INFO};
 use super::errors::*;
 
+pub const DEFAULT_DATABASE: &'static str = "delila";
+
+// Database ids become file names, so they are kept to letters, digits, spaces, '-' and '_'.
+pub fn valid_database_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && !id.starts_with(' ')
+        && !id.ends_with(' ')
+        && id.chars().all(|c| c.is_alphanumeric() || c == ' ' || c == '-' || c == '_')
+}
+
 #[derive(Clone)]
 pub struct PathSettings
 {
     pub logging_path: PathBuf,
     pub settings_database_path: PathBuf,
+    // The directory that holds the named game databases, as <id>.db.
+    pub databases_path: PathBuf,
+    // The database that requests use when they don't name one.
     pub database_path: PathBuf,
 }
 impl PathSettings {
@@ -16,16 +30,30 @@ impl PathSettings {
             .chain_err(|| "Unable to create/find a logging directory")?;
         let settings_path = app_dir(AppDataType::UserConfig, &DELILA_INFO, "settings")
             .chain_err(|| "Unable to create/find a settings directory")?;
-        let database_path = app_dir(AppDataType::UserData, &DELILA_INFO, "dbs")
-            .and_then(|mut database_path| {
-                database_path.push("delila.db");
-                Ok(database_path)
-            })
+        let databases_path = app_dir(AppDataType::UserData, &DELILA_INFO, "dbs")
             .chain_err(|| "Unable to create/find a database directory")?;
+        let mut database_path = databases_path.clone();
+        database_path.push(format!("{}.db", DEFAULT_DATABASE));
         Ok(PathSettings {
             logging_path: logging_path,
             settings_database_path: settings_path,
+            databases_path: databases_path,
             database_path: database_path
         })
     }
+
+    // The file of the database with the given id, or of the default database.
+    pub fn database(&self, id: Option<&str>) -> Result<PathBuf> {
+        match id {
+            None => Ok(self.database_path.clone()),
+            Some(id) => {
+                if !valid_database_id(id) {
+                    bail!(ErrorKind::InvalidDatabaseId(id.into()));
+                }
+                let mut path = self.databases_path.clone();
+                path.push(format!("{}.db", id));
+                Ok(path)
+            }
+        }
+    }
 }
@@ -0,0 +1,90 @@
+// delila - a desktop version of lila.
+//
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//--------------------------------------------------------------------------------------------------
+// Portable Game Notation
+//
+// The reader splits a (potentially huge) PGN stream into the raw text of individual games, and
+// the parser turns the raw text of a single game into tags and a tree of moves.
+//--------------------------------------------------------------------------------------------------
+pub mod parser;
+pub mod reader;
+pub mod writer;
+
+pub use self::parser::parse_game;
+pub use self::reader::{GameReader, RawGame};
+pub use self::writer::write_game;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tag {
+    pub name: String,
+    pub value: String
+}
+
+// A single move in SAN, along with the annotations that follow it. Variations are alternatives
+// to this move, so they start from the position before it was played.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Move {
+    pub san: String,
+    pub nags: Vec<u8>,
+    pub comment: Option<String>,
+    pub variations: Vec<Line>
+}
+
+impl Move {
+    pub fn new(san: String) -> Move {
+        Move{san: san, nags: Vec::new(), comment: None, variations: Vec::new()}
+    }
+}
+
+// A sequence of moves. The comment is the one that appears before the first move.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Line {
+    pub comment: Option<String>,
+    pub moves: Vec<Move>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Game {
+    pub tags: Vec<Tag>,
+    pub line: Line,
+    pub result: String
+}
+
+impl Default for Game {
+    fn default() -> Game {
+        Game{tags: Vec::new(), line: Line::default(), result: "*".into()}
+    }
+}
+
+impl Game {
+    // Returns the value of the first tag with the given name, if any.
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter()
+            .find(|tag| tag.name == name)
+            .map(|tag| tag.value.as_str())
+    }
+
+    // The ply of the first move, counting white's first move of the game as ply 1. Games set up
+    // from a FEN start part way in.
+    pub fn first_ply(&self) -> u32 {
+        let fields: Vec<&str> = self.tag("FEN").map_or(Vec::new(), |fen| fen.split_whitespace().collect());
+        let black_to_move = fields.get(1) == Some(&"b");
+        let fullmove = fields.get(5).and_then(|n| n.parse::<u32>().ok()).unwrap_or(1).max(1);
+        (fullmove - 1) * 2 + if black_to_move { 2 } else { 1 }
+    }
+}
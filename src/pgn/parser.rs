@@ -0,0 +1,374 @@
+// delila - a desktop version of lila.
+//
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//--------------------------------------------------------------------------------------------------
+// Parses the text of a single PGN game into tags and a tree of moves. Moves are kept as SAN; no
+// attempt is made to check that they are legal.
+//--------------------------------------------------------------------------------------------------
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+use errors::*;
+use super::{Game, Line, Move, Tag};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LeftBracket,
+    RightBracket,
+    LeftParen,
+    RightParen,
+    Str(String),
+    Comment(String),
+    Nag(u8),
+    Symbol(String),
+    Result(String)
+}
+
+fn syntax_error(line: usize, message: &str) -> Error {
+    ErrorKind::PgnSyntax(line, message.into()).into()
+}
+
+//--------------------------------------------------------------------------------------------------
+struct Tokenizer<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize
+}
+
+impl<'a> Tokenizer<'a> {
+    fn new(text: &'a str) -> Tokenizer<'a> {
+        Tokenizer{chars: text.chars().peekable(), line: 1}
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if c == Some('\n') {
+            self.line += 1;
+        }
+        c
+    }
+
+    fn take_while<F>(&mut self, mut predicate: F) -> String where F: FnMut(char) -> bool {
+        let mut taken = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if !predicate(c) {
+                break;
+            }
+            taken.push(c);
+            self.bump();
+        }
+        taken
+    }
+
+    fn string(&mut self) -> Result<Token> {
+        let mut value = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => return Ok(Token::Str(value)),
+                Some('\\') => match self.bump() {
+                    Some(c) => value.push(c),
+                    None => break
+                },
+                Some(c) => value.push(c),
+                None => break
+            }
+        }
+        Err(syntax_error(self.line, "unterminated string"))
+    }
+
+    fn comment(&mut self) -> Result<Token> {
+        let start = self.line;
+        let comment = self.take_while(|c| c != '}');
+        match self.bump() {
+            Some('}') => Ok(Token::Comment(comment.trim().into())),
+            _ => Err(syntax_error(start, "unterminated comment"))
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Option<(Token, usize)>> {
+        loop {
+            let c = match self.chars.peek() {
+                Some(&c) => c,
+                None => return Ok(None)
+            };
+            let line = self.line;
+            let token = match c {
+                c if c.is_whitespace() || c == '.' => {
+                    self.bump();
+                    continue;
+                },
+                '[' => { self.bump(); Token::LeftBracket },
+                ']' => { self.bump(); Token::RightBracket },
+                '(' => { self.bump(); Token::LeftParen },
+                ')' => { self.bump(); Token::RightParen },
+                '*' => { self.bump(); Token::Result("*".into()) },
+                '"' => { self.bump(); self.string()? },
+                '{' => { self.bump(); self.comment()? },
+                ';' => {
+                    self.bump();
+                    let comment = self.take_while(|c| c != '\n');
+                    Token::Comment(comment.trim().into())
+                },
+                '%' => {
+                    self.take_while(|c| c != '\n');
+                    continue;
+                },
+                '$' => {
+                    self.bump();
+                    let digits = self.take_while(|c| c.is_digit(10));
+                    match digits.parse::<u8>() {
+                        Ok(nag) => Token::Nag(nag),
+                        Err(_) => return Err(syntax_error(line, "invalid NAG"))
+                    }
+                },
+                '!' | '?' => {
+                    let suffix = self.take_while(|c| c == '!' || c == '?');
+                    match suffix_nag(&suffix) {
+                        Some(nag) => Token::Nag(nag),
+                        None => return Err(syntax_error(line, "invalid move suffix"))
+                    }
+                },
+                c if is_symbol_start(c) => {
+                    let mut symbol = self.take_while(is_symbol_continuation);
+                    // En passant captures are sometimes marked "e.p.", with or without a space.
+                    if symbol.ends_with('e') && self.chars.clone().take(3).eq(".p.".chars()) {
+                        symbol.pop();
+                        for _ in 0..3 {
+                            self.bump();
+                        }
+                        if symbol.is_empty() {
+                            continue;
+                        }
+                    }
+                    match symbol.as_str() {
+                        // Move numbers carry no information that the moves themselves don't.
+                        _ if symbol.chars().all(|c| c.is_digit(10)) => continue,
+                        "1-0" | "0-1" | "1/2-1/2" => Token::Result(symbol.clone()),
+                        "0-0" => Token::Symbol("O-O".into()),
+                        "0-0-0" => Token::Symbol("O-O-O".into()),
+                        _ => Token::Symbol(symbol.clone())
+                    }
+                },
+                c => return Err(syntax_error(line, &format!("unexpected character '{}'", c)))
+            };
+            return Ok(Some((token, line)));
+        }
+    }
+}
+
+fn is_symbol_start(c: char) -> bool {
+    c.is_alphanumeric() || c == '-'
+}
+
+fn is_symbol_continuation(c: char) -> bool {
+    c.is_alphanumeric() || "_+#=:-/".contains(c)
+}
+
+// Maps traditional move suffix annotations onto their NAG equivalent.
+fn suffix_nag(suffix: &str) -> Option<u8> {
+    match suffix {
+        "!" => Some(1),
+        "?" => Some(2),
+        "!!" => Some(3),
+        "??" => Some(4),
+        "!?" => Some(5),
+        "?!" => Some(6),
+        _ => None
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+    result: Option<String>
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<(Token, usize)> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|&(ref token, _)| token)
+    }
+
+    fn last_line(&self) -> usize {
+        self.tokens.last().map_or(1, |&(_, line)| line)
+    }
+
+    fn tag(&mut self) -> Result<Tag> {
+        match (self.next(), self.next(), self.next()) {
+            (Some((Token::Symbol(name), _)), Some((Token::Str(value), _)), Some((Token::RightBracket, _))) =>
+                Ok(Tag{name: name, value: value}),
+            (_, _, Some((_, line))) => Err(syntax_error(line, "malformed tag pair")),
+            _ => Err(syntax_error(self.last_line(), "unterminated tag pair"))
+        }
+    }
+
+    fn line(&mut self, depth: usize) -> Result<Line> {
+        let mut line = Line::default();
+        while let Some((token, line_number)) = self.next() {
+            match token {
+                Token::Symbol(san) => line.moves.push(Move::new(san)),
+                Token::Nag(nag) => {
+                    // A NAG before the first move has nothing to annotate, so it is dropped.
+                    if let Some(last) = line.moves.last_mut() {
+                        last.nags.push(nag);
+                    }
+                },
+                Token::Comment(comment) => match line.moves.last_mut() {
+                    Some(last) => append_comment(&mut last.comment, comment),
+                    None => append_comment(&mut line.comment, comment)
+                },
+                Token::LeftParen => {
+                    let variation = self.line(depth + 1)?;
+                    match line.moves.last_mut() {
+                        Some(last) => last.variations.push(variation),
+                        None => return Err(syntax_error(line_number, "variation before the first move"))
+                    }
+                },
+                Token::RightParen => {
+                    if depth == 0 {
+                        return Err(syntax_error(line_number, "unbalanced ')'"));
+                    }
+                    return Ok(line);
+                },
+                Token::Result(result) => {
+                    // Some editors put a result at the end of a variation; it means nothing there.
+                    if depth == 0 {
+                        self.result = Some(result);
+                        return Ok(line);
+                    }
+                },
+                Token::LeftBracket | Token::RightBracket | Token::Str(_) =>
+                    return Err(syntax_error(line_number, "unexpected tag pair in movetext"))
+            }
+        }
+        if depth > 0 {
+            return Err(syntax_error(self.last_line(), "unterminated variation"));
+        }
+        Ok(line)
+    }
+}
+
+fn append_comment(target: &mut Option<String>, comment: String) {
+    if comment.is_empty() {
+        return;
+    }
+    *target = Some(match target.take() {
+        Some(existing) => format!("{} {}", existing, comment),
+        None => comment
+    });
+}
+
+//--------------------------------------------------------------------------------------------------
+// parse_game():
+//      Parses the tag pairs and movetext of a single game.
+//
+pub fn parse_game(text: &str) -> Result<Game> {
+    let mut tokenizer = Tokenizer::new(text);
+    let mut tokens = Vec::new();
+    while let Some(token) = tokenizer.next_token()? {
+        tokens.push(token);
+    }
+    let mut parser = Parser{tokens: tokens, position: 0, result: None};
+
+    let mut game = Game::default();
+    while parser.peek() == Some(&Token::LeftBracket) {
+        parser.next();
+        game.tags.push(parser.tag()?);
+    }
+    game.line = parser.line(0)?;
+    game.result = match (parser.result, game.tag("Result")) {
+        (Some(result), _) => result,
+        (None, Some(result)) => result.into(),
+        (None, None) => "*".into()
+    };
+    Ok(game)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sans(line: &Line) -> Vec<&str> {
+        line.moves.iter().map(|m| m.san.as_str()).collect()
+    }
+
+    #[test]
+    fn test_tags() {
+        let game = parse_game("[Event \"Tata \\\"Steel\\\"\"]\n[White \"Carlsen, Magnus\"]\n\n*").unwrap();
+        assert_eq!(game.tags.len(), 2);
+        assert_eq!(game.tag("Event"), Some("Tata \"Steel\""));
+        assert_eq!(game.tag("White"), Some("Carlsen, Magnus"));
+        assert_eq!(game.tag("Black"), None);
+        assert_eq!(game.result, "*");
+    }
+
+    #[test]
+    fn test_movetext() {
+        let game = parse_game("1. e4 e5 2.Nf3 Nc6 3...a6 4. 0-0 1/2-1/2").unwrap();
+        assert_eq!(sans(&game.line), vec!["e4", "e5", "Nf3", "Nc6", "a6", "O-O"]);
+        assert_eq!(game.result, "1/2-1/2");
+    }
+
+    #[test]
+    fn test_en_passant_marks() {
+        let game = parse_game("1. e4 d5 2. e5 f5 3. exf6e.p. Nxf6 4. d4 c5 5. d5 e5 6. dxe6 e.p. *").unwrap();
+        assert_eq!(sans(&game.line), vec!["e4", "d5", "e5", "f5", "exf6", "Nxf6", "d4", "c5", "d5", "e5", "dxe6"]);
+    }
+
+    #[test]
+    fn test_comments_and_nags() {
+        let game = parse_game("{Start} 1. e4! $14 {Best by test} ; rest of line\ne5?! $2 *").unwrap();
+        assert_eq!(game.line.comment, Some("Start".into()));
+        assert_eq!(game.line.moves[0].nags, vec![1, 14]);
+        assert_eq!(game.line.moves[0].comment, Some("Best by test rest of line".into()));
+        assert_eq!(game.line.moves[1].nags, vec![6, 2]);
+        assert_eq!(game.line.moves[1].comment, None);
+    }
+
+    #[test]
+    fn test_nested_variations() {
+        let game = parse_game("1. e4 (1. d4 d5 (1... Nf6 2. c4) 2. c4) (1. c4) e5 0-1").unwrap();
+        assert_eq!(sans(&game.line), vec!["e4", "e5"]);
+        let variations = &game.line.moves[0].variations;
+        assert_eq!(variations.len(), 2);
+        assert_eq!(sans(&variations[0]), vec!["d4", "d5", "c4"]);
+        assert_eq!(sans(&variations[0].moves[1].variations[0]), vec!["Nf6", "c4"]);
+        assert_eq!(sans(&variations[1]), vec!["c4"]);
+        assert_eq!(game.result, "0-1");
+    }
+
+    #[test]
+    fn test_result_falls_back_to_tag() {
+        let game = parse_game("[Result \"1-0\"]\n1. e4").unwrap();
+        assert_eq!(game.result, "1-0");
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        assert!(parse_game("1. e4 (1. d4").is_err());
+        assert!(parse_game("1. e4 ) e5").is_err());
+        assert!(parse_game("1. e4 {unterminated").is_err());
+        assert!(parse_game("[Event \"A\"\n1. e4").is_err());
+        assert!(parse_game("(1. d4) 1. e4").is_err());
+    }
+}
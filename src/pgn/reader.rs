@@ -0,0 +1,199 @@
+// delila - a desktop version of lila.
+//
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//--------------------------------------------------------------------------------------------------
+// Splits a PGN stream into games one line at a time, so that only a single game is ever held in
+// memory. Lines that aren't valid UTF-8 are treated as Latin-1, which is what most older PGN
+// files (including TWIC) actually use.
+//--------------------------------------------------------------------------------------------------
+
+use std::io::BufRead;
+
+use errors::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawGame {
+    pub text: String,
+    // Byte offsets of the game within the stream.
+    pub start: u64,
+    pub end: u64
+}
+
+pub struct GameReader<R> {
+    reader: R,
+    offset: u64,
+    buffer: Vec<u8>,
+    // A tag line that was read while looking for the end of the previous game.
+    pending: Option<(String, u64)>
+}
+
+impl<R: BufRead> GameReader<R> {
+    pub fn new(reader: R) -> GameReader<R> {
+        GameReader{reader: reader, offset: 0, buffer: Vec::new(), pending: None}
+    }
+
+    // The number of bytes consumed from the underlying stream so far.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    fn read_line(&mut self) -> Result<Option<String>> {
+        self.buffer.clear();
+        let read = self.reader.read_until(b'\n', &mut self.buffer)?;
+        if read == 0 {
+            return Ok(None);
+        }
+        self.offset += read as u64;
+        let line = match String::from_utf8(self.buffer.clone()) {
+            Ok(line) => line,
+            Err(_) => self.buffer.iter().map(|&b| b as char).collect()
+        };
+        Ok(Some(line.trim_start_matches('\u{feff}').to_string()))
+    }
+
+    fn next_game(&mut self) -> Result<Option<RawGame>> {
+        let mut text = String::new();
+        let mut start = self.offset;
+        let mut in_movetext = false;
+        let mut in_comment = false;
+        if let Some((line, line_start)) = self.pending.take() {
+            text.push_str(&line);
+            start = line_start;
+        }
+        loop {
+            let line_start = self.offset;
+            let line = match self.read_line()? {
+                Some(line) => line,
+                None => break
+            };
+            let finished = {
+                let trimmed = line.trim();
+                if !in_comment {
+                    // The PGN escape mechanism; these lines are meant to be ignored.
+                    if trimmed.starts_with('%') {
+                        continue;
+                    }
+                    if trimmed.starts_with('[') && in_movetext {
+                        self.pending = Some((line, line_start));
+                        break;
+                    }
+                }
+                if text.is_empty() {
+                    if trimmed.is_empty() {
+                        continue;
+                    }
+                    start = line_start;
+                }
+                if in_comment || !trimmed.starts_with('[') {
+                    in_movetext = in_movetext || !trimmed.is_empty();
+                    in_comment = ends_in_comment(trimmed, in_comment);
+                    !in_comment && ends_with_result(trimmed)
+                } else {
+                    false
+                }
+            };
+            text.push_str(&line);
+            if finished {
+                break;
+            }
+        }
+        if text.trim().is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(RawGame{text: text, start: start, end: self.offset}))
+    }
+}
+
+impl<R: BufRead> Iterator for GameReader<R> {
+    type Item = Result<RawGame>;
+
+    fn next(&mut self) -> Option<Result<RawGame>> {
+        match self.next_game() {
+            Ok(Some(game)) => Some(Ok(game)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e))
+        }
+    }
+}
+
+// Returns whether we are inside a brace comment at the end of the given movetext line.
+fn ends_in_comment(line: &str, in_comment: bool) -> bool {
+    let mut in_comment = in_comment;
+    for c in line.chars() {
+        match c {
+            '}' if in_comment => in_comment = false,
+            '{' if !in_comment => in_comment = true,
+            ';' if !in_comment => break,
+            _ => {}
+        }
+    }
+    in_comment
+}
+
+fn ends_with_result(line: &str) -> bool {
+    ["1-0", "0-1", "1/2-1/2", "*"].iter().any(|result| {
+        line.ends_with(result) && line[..line.len() - result.len()]
+            .chars().last().map_or(true, |c| c.is_whitespace() || c == ')' || c == '}')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn read_all(pgn: &[u8]) -> Vec<RawGame> {
+        GameReader::new(Cursor::new(pgn)).map(|game| game.unwrap()).collect()
+    }
+
+    #[test]
+    fn test_splits_games() {
+        let pgn = b"[Event \"A\"]\n\n1. e4 e5 1-0\n\n[Event \"B\"]\n\n1. d4 d5 0-1\n";
+        let games = read_all(pgn);
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].text, "[Event \"A\"]\n\n1. e4 e5 1-0\n");
+        assert_eq!(games[0].start, 0);
+        assert_eq!(games[1].text, "[Event \"B\"]\n\n1. d4 d5 0-1\n");
+        assert_eq!(games[1].start, 27);
+        assert_eq!(games[1].end, pgn.len() as u64);
+    }
+
+    #[test]
+    fn test_splits_games_without_results() {
+        let pgn = b"[Event \"A\"]\n1. e4 e5\n[Event \"B\"]\n1. d4 d5\n";
+        let games = read_all(pgn);
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].text, "[Event \"A\"]\n1. e4 e5\n");
+        assert_eq!(games[1].text, "[Event \"B\"]\n1. d4 d5\n");
+    }
+
+    #[test]
+    fn test_tag_like_lines_in_comments() {
+        let pgn = b"[Event \"A\"]\n1. e4 {a comment\n[that looks like a tag]} e5 *\n";
+        let games = read_all(pgn);
+        assert_eq!(games.len(), 1);
+    }
+
+    #[test]
+    fn test_escape_lines_and_latin1() {
+        let pgn = b"% generated\n[White \"Nepomniachtchi, \xe9\"]\n1. e4 *\n";
+        let games = read_all(pgn);
+        assert_eq!(games.len(), 1);
+        assert_eq!(games[0].text, "[White \"Nepomniachtchi, \u{e9}\"]\n1. e4 *\n");
+        assert_eq!(games[0].start, 12);
+    }
+}
@@ -0,0 +1,187 @@
+// delila - a desktop version of lila.
+//
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//--------------------------------------------------------------------------------------------------
+// Writes games in the PGN export format: one tag pair per line, then the movetext wrapped so that
+// no line is longer than 79 characters.
+//--------------------------------------------------------------------------------------------------
+
+use super::{Game, Line};
+
+const MAX_LINE_LENGTH: usize = 79;
+
+// The Seven Tag Roster, which must come first and in this order.
+pub const SEVEN_TAG_ROSTER: [&'static str; 7] = ["Event", "Site", "Date", "Round", "White", "Black", "Result"];
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Movetext is built up as a list of words that can be wrapped at any point between them. Opening
+// parentheses are glued to the word that follows them, and closing ones to the word before.
+struct Movetext {
+    words: Vec<String>,
+    glue_next: bool
+}
+
+impl Movetext {
+    fn push(&mut self, word: &str) {
+        if self.glue_next {
+            if let Some(last) = self.words.last_mut() {
+                last.push_str(word);
+                self.glue_next = false;
+                return;
+            }
+        }
+        self.words.push(word.to_string());
+        self.glue_next = false;
+    }
+
+    fn open_variation(&mut self) {
+        self.push("(");
+        self.glue_next = true;
+    }
+
+    fn close_variation(&mut self) {
+        match self.words.last_mut() {
+            Some(last) => last.push(')'),
+            None => self.words.push(")".into())
+        }
+    }
+
+    fn comment(&mut self, comment: &str) {
+        let words: Vec<&str> = comment.split_whitespace().collect();
+        if words.is_empty() {
+            return;
+        }
+        let last = words.len() - 1;
+        for (index, word) in words.iter().enumerate() {
+            let mut word = word.replace('}', "");
+            if index == 0 {
+                word.insert(0, '{');
+            }
+            if index == last {
+                word.push('}');
+            }
+            self.push(&word);
+        }
+    }
+
+    fn line(&mut self, line: &Line, first_ply: u32) {
+        if let Some(ref comment) = line.comment {
+            self.comment(comment);
+        }
+        let mut needs_number = true;
+        for (index, m) in line.moves.iter().enumerate() {
+            let ply = first_ply + index as u32;
+            let number = (ply + 1) / 2;
+            if ply % 2 == 1 {
+                self.push(&format!("{}.", number));
+            } else if needs_number {
+                self.push(&format!("{}...", number));
+            }
+            self.push(&m.san);
+            for nag in &m.nags {
+                self.push(&format!("${}", nag));
+            }
+            needs_number = false;
+            if let Some(ref comment) = m.comment {
+                self.comment(comment);
+                needs_number = true;
+            }
+            for variation in &m.variations {
+                self.open_variation();
+                self.line(variation, ply);
+                self.close_variation();
+                needs_number = true;
+            }
+        }
+    }
+
+    fn wrap(&self) -> String {
+        let mut text = String::new();
+        let mut line_length = 0;
+        for word in &self.words {
+            if line_length > 0 && line_length + 1 + word.len() > MAX_LINE_LENGTH {
+                text.push('\n');
+                line_length = 0;
+            } else if line_length > 0 {
+                text.push(' ');
+                line_length += 1;
+            }
+            text.push_str(word);
+            line_length += word.len();
+        }
+        text
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// write_game():
+//      Writes a game's tags in the order given, followed by the movetext and the result.
+//
+pub fn write_game(game: &Game) -> String {
+    let mut text = String::new();
+    for tag in &game.tags {
+        text.push_str(&format!("[{} \"{}\"]\n", tag.name, escape(&tag.value)));
+    }
+    text.push('\n');
+
+    let mut movetext = Movetext{words: Vec::new(), glue_next: false};
+    movetext.line(&game.line, game.first_ply());
+    movetext.push(&game.result);
+    text.push_str(&movetext.wrap());
+    text.push_str("\n\n");
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{Move, Tag, parse_game};
+
+    #[test]
+    fn test_round_trip() {
+        let pgn = "[Event \"Tata \\\"Steel\\\"\"]\n[White \"Carlsen, Magnus\"]\n\n\
+                   {Start} 1. e4 $1 {Best} 1... e5 (1... c5 2. Nf3 (2. c3) 2... d6) 2. Nf3 Nc6 1-0\n\n";
+        let game = parse_game(pgn).unwrap();
+        assert_eq!(write_game(&game), pgn);
+        assert_eq!(parse_game(&write_game(&game)).unwrap(), game);
+    }
+
+    #[test]
+    fn test_starts_from_fen() {
+        let mut game = Game::default();
+        game.tags.push(Tag{name: "FEN".into(), value: "4k3/8/8/8/8/8/4P3/4K3 b - - 0 40".into()});
+        game.line.moves.push(Move::new("Kd7".into()));
+        game.line.moves.push(Move::new("e4".into()));
+        assert!(write_game(&game).ends_with("\n\n40... Kd7 41. e4 *\n\n"));
+    }
+
+    #[test]
+    fn test_wraps_long_lines() {
+        let mut game = Game::default();
+        for _ in 0..60 {
+            game.line.moves.push(Move::new("Nf3".into()));
+        }
+        game.line.moves[3].comment = Some("a rather long comment that is going to need wrapping".into());
+        let text = write_game(&game);
+        assert!(text.lines().all(|line| line.len() <= MAX_LINE_LENGTH));
+        assert!(text.lines().count() > 4);
+        assert_eq!(parse_game(&text).unwrap().line, game.line);
+    }
+}
@@ -0,0 +1,214 @@
+// delila - a desktop version of lila.
+//
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//------------------------------------------------------------------------------
+// SCID databases
+//
+// A base is three files that share a name: the index, the names and the games,
+// as .si4/.sn4/.sg4 or, in newer versions of SCID, .si5/.sn5/.sg5. The index
+// and names are read up front, and games are read from the game file as they're
+// asked for. Both versions encode the games themselves the same way.
+//------------------------------------------------------------------------------
+
+use std::ffi::OsString;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use super::bytebuf::ByteBuffer;
+use super::game::decode_game;
+use super::index::*;
+use super::namebase::*;
+use pgn;
+use errors::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Scid4,
+    Scid5
+}
+
+impl Format {
+    // The extensions of the index, name and game files.
+    pub fn extensions(&self) -> [&'static str; 3] {
+        match *self {
+            Format::Scid4 => ["si4", "sn4", "sg4"],
+            Format::Scid5 => ["si5", "sn5", "sg5"]
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    // detect():
+    //      The format of the base named by the path: the one its extension
+    //      belongs to, or else the one whose index exists, preferring the newer.
+    //
+    pub fn detect(path: &Path) -> Format {
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        if Format::Scid5.extensions().contains(&extension) {
+            Format::Scid5
+        } else if Format::Scid4.extensions().contains(&extension) {
+            Format::Scid4
+        } else if base_file(path, "si5").exists() {
+            Format::Scid5
+        } else {
+            Format::Scid4
+        }
+    }
+}
+
+pub struct ScidBase {
+    pub format: Format,
+    pub description: String,
+    entries: Vec<IndexEntry>,
+    names: NameBase,
+    games: fs::File,
+}
+
+//------------------------------------------------------------------------------
+// base_file():
+//      The file of the base with the given extension. The base may be named by
+//      any of its files, or without an extension.
+//
+pub fn base_file(path: &Path, extension: &str) -> PathBuf {
+    let known = ["si4", "sn4", "sg4", "si5", "sn5", "sg5"];
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(e) if known.contains(&e) => path.with_extension(extension),
+        _ => {
+            let mut name: OsString = path.as_os_str().into();
+            name.push(".");
+            name.push(extension);
+            PathBuf::from(name)
+        }
+    }
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    fs::File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut bytes))
+        .chain_err(|| format!("Unable to read {}", path.display()))?;
+    Ok(bytes)
+}
+
+// The description and entries of an index. Version 5 indexes have no header, so they're only as
+// long as their entries and have no description.
+fn read_index(format: Format, index: &[u8]) -> Result<(String, Vec<IndexEntry>)> {
+    let mut buf = ByteBuffer::new(index);
+    match format {
+        Format::Scid4 => {
+            let header = IndexHeader::read(&mut buf)?;
+            let mut entries = Vec::with_capacity(header.num_games as usize);
+            for _ in 0..header.num_games {
+                entries.push(IndexEntry::read(&mut buf)?);
+            }
+            Ok((header.description, entries))
+        },
+        Format::Scid5 => {
+            if index.len() % SCID5_INDEX_ENTRY_SIZE != 0 {
+                bail!(ErrorKind::ScidFormat("the index has a partial entry".into()));
+            }
+            let mut entries = Vec::with_capacity(index.len() / SCID5_INDEX_ENTRY_SIZE);
+            while !buf.is_empty() {
+                entries.push(IndexEntry::read_scid5(&mut buf)?);
+            }
+            Ok((String::new(), entries))
+        }
+    }
+}
+
+impl ScidBase {
+    pub fn open(path: &Path) -> Result<ScidBase> {
+        let format = Format::detect(path);
+        let extensions = format.extensions();
+
+        let index_path = base_file(path, extensions[0]);
+        let index = read_file(&index_path)?;
+        let (description, entries) = read_index(format, &index)
+            .chain_err(|| format!("Unable to read {}", index_path.display()))?;
+
+        let names_path = base_file(path, extensions[1]);
+        let names = read_file(&names_path)?;
+        let names = match format {
+            Format::Scid4 => NameBase::read(&mut ByteBuffer::new(&names)),
+            Format::Scid5 => NameBase::read_scid5(&mut ByteBuffer::new(&names))
+        }.chain_err(|| format!("Unable to read {}", names_path.display()))?;
+
+        let games_path = base_file(path, extensions[2]);
+        let games = fs::File::open(&games_path)
+            .chain_err(|| format!("Unable to open {}", games_path.display()))?;
+        Ok(ScidBase{format: format, description: description, entries: entries, names: names, games: games})
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn is_deleted(&self, number: usize) -> bool {
+        self.entries.get(number).map_or(false, |entry| entry.is_deleted())
+    }
+
+    //--------------------------------------------------------------------------
+    // game():
+    //      Reads a game, with the tags from the index and name files before the
+    //      ones from its record.
+    //
+    pub fn game(&mut self, number: usize) -> Result<pgn::Game> {
+        let entry = match self.entries.get(number) {
+            Some(entry) => entry.clone(),
+            None => bail!(ErrorKind::ScidFormat(format!("there is no game {}", number + 1)))
+        };
+        let mut record = vec![0; entry.length as usize];
+        self.games.seek(SeekFrom::Start(entry.offset))
+            .and_then(|_| self.games.read_exact(&mut record))
+            .chain_err(|| format!("Unable to read game {}", number + 1))?;
+        let mut game = decode_game(&record).chain_err(|| format!("Unable to decode game {}", number + 1))?;
+
+        let result = result_to_string(entry.result);
+        let mut tags = vec![
+            ("Event", self.names.name(NAME_EVENT, entry.event_id).to_string()),
+            ("Site", self.names.name(NAME_SITE, entry.site_id).to_string()),
+            ("Date", date_to_string(entry.date)),
+            ("Round", self.names.name(NAME_ROUND, entry.round_id).to_string()),
+            ("White", self.names.name(NAME_PLAYER, entry.white_id).to_string()),
+            ("Black", self.names.name(NAME_PLAYER, entry.black_id).to_string()),
+            ("Result", result.to_string()),
+        ];
+        if entry.white_elo > 0 {
+            tags.push(("WhiteElo", entry.white_elo.to_string()));
+        }
+        if entry.black_elo > 0 {
+            tags.push(("BlackElo", entry.black_elo.to_string()));
+        }
+        if let Some(eco) = eco_to_string(entry.eco) {
+            tags.push(("ECO", eco));
+        }
+        if entry.event_date != ZERO_DATE && game.tag("EventDate").is_none() {
+            tags.push(("EventDate", date_to_string(entry.event_date)));
+        }
+        let mut all: Vec<pgn::Tag> = tags.into_iter()
+            .map(|(name, value)| pgn::Tag{name: name.into(), value: value})
+            .collect();
+        all.extend(game.tags.drain(..));
+        game.tags = all;
+        game.result = result.into();
+        Ok(game)
+    }
+}
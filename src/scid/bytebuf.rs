@@ -0,0 +1,162 @@
+// delila - a desktop version of lila.
+//
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//------------------------------------------------------------------------------
+// Byte buffers
+//
+// SCID writes every number in its files big-endian, in however many bytes the
+// field needs (often three). The version 5 files are little-endian, with
+// variable length numbers in the name file.
+//------------------------------------------------------------------------------
+
+use errors::*;
+
+pub struct ByteBuffer<'a> {
+    bytes: &'a [u8],
+    pos: usize
+}
+
+fn truncated() -> Error {
+    ErrorKind::ScidFormat("unexpected end of data".into()).into()
+}
+
+//------------------------------------------------------------------------------
+// decode_text():
+//      SCID stores strings as bytes, in UTF-8 in newer bases and Latin-1 in
+//      older ones. Anything that isn't UTF-8 is read as Latin-1.
+//
+pub fn decode_text(bytes: &[u8]) -> String {
+    match ::std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => bytes.iter().map(|&b| b as char).collect()
+    }
+}
+
+impl<'a> ByteBuffer<'a> {
+    pub fn new(bytes: &'a [u8]) -> ByteBuffer<'a> {
+        ByteBuffer{bytes: bytes, pos: 0}
+    }
+
+    pub fn position(&self) -> usize { self.pos }
+    pub fn is_empty(&self) -> bool { self.pos >= self.bytes.len() }
+
+    pub fn get_byte(&mut self) -> Result<u8> {
+        let b = *self.bytes.get(self.pos).ok_or_else(truncated)?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    // Reads an unsigned big-endian number of 1 to 4 bytes.
+    pub fn get_unsigned(&mut self, size: usize) -> Result<u32> {
+        let mut value = 0u32;
+        for _ in 0..size {
+            value = (value << 8) | self.get_byte()? as u32;
+        }
+        Ok(value)
+    }
+
+    pub fn get_two_bytes(&mut self) -> Result<u32> { self.get_unsigned(2) }
+    pub fn get_three_bytes(&mut self) -> Result<u32> { self.get_unsigned(3) }
+    pub fn get_four_bytes(&mut self) -> Result<u32> { self.get_unsigned(4) }
+
+    pub fn get_u32_le(&mut self) -> Result<u32> {
+        let bytes = self.get_fixed_bytes(4)?;
+        Ok(bytes.iter().rev().fold(0, |value, &b| (value << 8) | b as u32))
+    }
+
+    pub fn get_u64_le(&mut self) -> Result<u64> {
+        let bytes = self.get_fixed_bytes(8)?;
+        Ok(bytes.iter().rev().fold(0, |value, &b| (value << 8) | b as u64))
+    }
+
+    // A number in seven bit groups, lowest first, with the top bit set on all but the last.
+    pub fn get_varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        let mut shift = 0;
+        loop {
+            let b = self.get_byte()?;
+            if shift > 56 {
+                bail!(ErrorKind::ScidFormat("variable length number is too long".into()));
+            }
+            value |= ((b & 0x7F) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+            shift += 7;
+        }
+    }
+
+    pub fn get_fixed_bytes(&mut self, length: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() - self.pos < length {
+            return Err(truncated());
+        }
+        let bytes = &self.bytes[self.pos..self.pos + length];
+        self.pos += length;
+        Ok(bytes)
+    }
+
+    pub fn get_fixed_string(&mut self, length: usize) -> Result<String> {
+        self.get_fixed_bytes(length).map(decode_text)
+    }
+
+    // A string padded with zeros to a fixed length.
+    pub fn get_padded_string(&mut self, length: usize) -> Result<String> {
+        let bytes = self.get_fixed_bytes(length)?;
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(length);
+        Ok(decode_text(&bytes[..end]))
+    }
+
+    pub fn get_terminated_string(&mut self) -> Result<String> {
+        let length = self.bytes[self.pos..].iter().position(|&b| b == 0).ok_or_else(truncated)?;
+        let text = self.get_fixed_string(length)?;
+        self.pos += 1;
+        Ok(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numbers_are_big_endian() {
+        let mut buf = ByteBuffer::new(&[0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+        assert_eq!(buf.get_two_bytes().unwrap(), 0x0102);
+        assert_eq!(buf.get_three_bytes().unwrap(), 0x030405);
+        assert_eq!(buf.get_byte().unwrap(), 0x06);
+        assert!(buf.is_empty());
+        assert!(buf.get_byte().is_err());
+    }
+
+    #[test]
+    fn test_little_endian_numbers() {
+        let mut buf = ByteBuffer::new(&[0x04, 0x03, 0x02, 0x01, 0x08, 0x07, 0x06, 0x05, 0, 0, 0, 0, 0xAC, 0x02]);
+        assert_eq!(buf.get_u32_le().unwrap(), 0x01020304);
+        assert_eq!(buf.get_u64_le().unwrap(), 0x05060708);
+        assert_eq!(buf.get_varint().unwrap(), 300);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_strings() {
+        let mut buf = ByteBuffer::new(b"ab\0\0cd\0M\xfcller\0");
+        assert_eq!(buf.get_padded_string(4).unwrap(), "ab");
+        assert_eq!(buf.get_terminated_string().unwrap(), "cd");
+        assert_eq!(buf.get_terminated_string().unwrap(), "M\u{fc}ller");
+        assert!(buf.get_terminated_string().is_err());
+    }
+}
@@ -82,3 +82,83 @@ pub const BP: Piece = 14;
 // Minor piece definitions, used for searching by material only:
 pub const WM: Piece = 16;
 pub const BM: Piece = 17;
+
+// PIECE HELPERS
+
+pub fn piece_type(p: Piece) -> Piece { p & 0x7 }
+pub fn piece_color(p: Piece) -> Color { (p & 0x8) >> 3 }
+pub fn piece_make(c: Color, p: Piece) -> Piece { (c << 3) | (p & 0x7) }
+
+// COLORS
+
+pub const WHITE: Color = 0;
+pub const BLACK: Color = 1;
+pub const NOCOLOR: Color = 2;
+
+pub fn color_flip(c: Color) -> Color { 1 - c }
+
+// SQUARES:
+//   A1 is 0, B1 is 1, ... H1 is 7, A2 is 8, ... H8 is 63.
+
+pub const A1: Square =  0; pub const B1: Square =  1; pub const C1: Square =  2; pub const D1: Square =  3;
+pub const E1: Square =  4; pub const F1: Square =  5; pub const G1: Square =  6; pub const H1: Square =  7;
+pub const A2: Square =  8; pub const B2: Square =  9; pub const C2: Square = 10; pub const D2: Square = 11;
+pub const E2: Square = 12; pub const F2: Square = 13; pub const G2: Square = 14; pub const H2: Square = 15;
+pub const A3: Square = 16; pub const B3: Square = 17; pub const C3: Square = 18; pub const D3: Square = 19;
+pub const E3: Square = 20; pub const F3: Square = 21; pub const G3: Square = 22; pub const H3: Square = 23;
+pub const A4: Square = 24; pub const B4: Square = 25; pub const C4: Square = 26; pub const D4: Square = 27;
+pub const E4: Square = 28; pub const F4: Square = 29; pub const G4: Square = 30; pub const H4: Square = 31;
+pub const A5: Square = 32; pub const B5: Square = 33; pub const C5: Square = 34; pub const D5: Square = 35;
+pub const E5: Square = 36; pub const F5: Square = 37; pub const G5: Square = 38; pub const H5: Square = 39;
+pub const A6: Square = 40; pub const B6: Square = 41; pub const C6: Square = 42; pub const D6: Square = 43;
+pub const E6: Square = 44; pub const F6: Square = 45; pub const G6: Square = 46; pub const H6: Square = 47;
+pub const A7: Square = 48; pub const B7: Square = 49; pub const C7: Square = 50; pub const D7: Square = 51;
+pub const E7: Square = 52; pub const F7: Square = 53; pub const G7: Square = 54; pub const H7: Square = 55;
+pub const A8: Square = 56; pub const B8: Square = 57; pub const C8: Square = 58; pub const D8: Square = 59;
+pub const E8: Square = 60; pub const F8: Square = 61; pub const G8: Square = 62; pub const H8: Square = 63;
+
+pub const COLOR_SQUARE: Square = 64;
+pub const NULL_SQUARE: Square = 65;
+
+pub fn square_make(f: File, r: Rank) -> Square { (r << 3) | f }
+pub fn square_fyle(sq: Square) -> File { sq & 0x7 }
+pub fn square_rank(sq: Square) -> Rank { (sq >> 3) & 0x7 }
+
+// DIRECTIONS:
+//   Up = 1, Down = 2, Left = 4, Right = 8, UpLeft = 5, UpRight = 9,
+//   DownLeft = 6, DownRight = 10
+
+pub const NULL_DIR: Direction = 0;
+pub const UP: Direction = 1;
+pub const DOWN: Direction = 2;
+pub const LEFT: Direction = 4;
+pub const RIGHT: Direction = 8;
+pub const UP_LEFT: Direction = UP | LEFT;
+pub const UP_RIGHT: Direction = UP | RIGHT;
+pub const DOWN_LEFT: Direction = DOWN | LEFT;
+pub const DOWN_RIGHT: Direction = DOWN | RIGHT;
+
+//------------------------------------------------------------------------------
+// square_move():
+//      Returns the square one step from <sq> in direction <dir>, or
+//      NULL_SQUARE if that would leave the board.
+//
+pub fn square_move(sq: Square, dir: Direction) -> Square {
+    let mut f = square_fyle(sq);
+    let mut r = square_rank(sq);
+    if dir & UP != 0 { r += 1; }
+    if dir & DOWN != 0 { r -= 1; }
+    if dir & LEFT != 0 { f -= 1; }
+    if dir & RIGHT != 0 { f += 1; }
+    if f < 0 || f > 7 || r < 0 || r > 7 {
+        return NULL_SQUARE;
+    }
+    square_make(f, r)
+}
+
+// CASTLING FLAGS
+
+pub const WQ_CASTLE: UByte = 1;
+pub const WK_CASTLE: UByte = 2;
+pub const BQ_CASTLE: UByte = 4;
+pub const BK_CASTLE: UByte = 8;
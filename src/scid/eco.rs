@@ -0,0 +1,200 @@
+// delila - a desktop version of lila.
+//
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//------------------------------------------------------------------------------
+// ECO classification
+//
+// SCID's opening file (scid.eco) lists each opening as a code, a quoted name
+// and the moves that reach it, ending with '*':
+//
+//     B90a "Sicilian: Najdorf"  1.e4 c5 2.Nf3 d6 3.d4 cxd4 4.Nxd4 Nf6 5.Nc3 a6 *
+//
+// Openings are found by the position they reach rather than by their moves, so
+// that games which transpose into them are still classified. A game gets the
+// code of the last position in its main line that the book knows.
+//------------------------------------------------------------------------------
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use board::Board;
+use board::zobrist::ZobristHash;
+use errors::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Opening {
+    pub code: String,
+    pub name: String,
+}
+
+#[derive(Debug, Default)]
+pub struct EcoBook {
+    openings: HashMap<ZobristHash, Opening>,
+}
+
+fn invalid(line: usize, reason: String) -> Error {
+    ErrorKind::InvalidEcoFile(line, reason).into()
+}
+
+// A basic code is a letter from A to E and two digits. SCID adds its own
+// extensions after that (e.g. "B90a1").
+fn valid_code(code: &str) -> bool {
+    let chars: Vec<char> = code.chars().take(3).collect();
+    chars.len() == 3
+        && chars[0] >= 'A' && chars[0] <= 'E'
+        && chars[1].is_digit(10)
+        && chars[2].is_digit(10)
+}
+
+enum Token<'a> {
+    Word(&'a str),
+    Quoted(&'a str),
+}
+
+//------------------------------------------------------------------------------
+// tokens():
+//      Splits the file into words and quoted strings, with the line each one
+//      is on. Everything after a '#' on a line is a comment.
+//
+fn tokens(text: &str) -> Result<Vec<(usize, Token)>> {
+    let mut tokens = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        let mut rest = line.trim_start();
+        while !rest.is_empty() {
+            if rest.starts_with('"') {
+                let end = rest[1..].find('"').ok_or_else(|| invalid(number + 1, "unterminated name".into()))?;
+                tokens.push((number + 1, Token::Quoted(&rest[1..end + 1])));
+                rest = &rest[end + 2..];
+            } else {
+                let end = rest.find(|c: char| c.is_whitespace() || c == '"').unwrap_or(rest.len());
+                tokens.push((number + 1, Token::Word(&rest[..end])));
+                rest = &rest[end..];
+            }
+            rest = rest.trim_start();
+        }
+    }
+    Ok(tokens)
+}
+
+impl EcoBook {
+    pub fn load(path: &Path) -> Result<EcoBook> {
+        let mut text = String::new();
+        fs::File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut text))
+            .chain_err(|| format!("Unable to read {}", path.display()))?;
+        EcoBook::parse(&text).chain_err(|| format!("Unable to read {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<EcoBook> {
+        let mut book = EcoBook::default();
+        let mut tokens = tokens(text)?.into_iter();
+        while let Some((line, token)) = tokens.next() {
+            let code = match token {
+                Token::Word(code) if valid_code(code) => code,
+                _ => return Err(invalid(line, "expected an ECO code".into()))
+            };
+            let name = match tokens.next() {
+                Some((_, Token::Quoted(name))) => name,
+                _ => return Err(invalid(line, format!("expected the name of {}", code)))
+            };
+            let mut board = Board::start();
+            loop {
+                let (line, word) = match tokens.next() {
+                    Some((line, Token::Word(word))) => (line, word),
+                    _ => return Err(invalid(line, format!("the moves of {} don't end with '*'", code)))
+                };
+                if word == "*" {
+                    break;
+                }
+                // Move numbers may be written on their own ("1.") or joined to the move ("1.e4").
+                let san = word.trim_start_matches(|c: char| c.is_digit(10) || c == '.');
+                if san.is_empty() {
+                    continue;
+                }
+                let mv = board.parse_san(san).map_err(|e| invalid(line, e.to_string()))?;
+                board.make(mv);
+            }
+            // Later entries refine earlier ones, so they win when they reach the same position.
+            book.openings.insert(board.hash(), Opening{code: code.into(), name: name.into()});
+        }
+        Ok(book)
+    }
+
+    pub fn len(&self) -> usize {
+        self.openings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.openings.is_empty()
+    }
+
+    // The opening of the last of the positions that the book knows, if any.
+    pub fn classify<I>(&self, positions: I) -> Option<&Opening>
+        where I: IntoIterator<Item=ZobristHash>
+    {
+        positions.into_iter()
+            .filter_map(|hash| self.openings.get(&hash))
+            .last()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOOK: &'static str = "
+        # A few openings
+        C20 \"King's Pawn\" 1.e4 e5 *
+        C40 \"King's Knight\" 1.e4 e5 2.Nf3 *
+        C44 \"Scotch\" 1. e4 e5 2. Nf3 Nc6 3. d4
+            exd4 *
+    ";
+
+    fn positions(moves: &[&str]) -> Vec<ZobristHash> {
+        let mut board = Board::start();
+        let mut hashes = vec![board.hash()];
+        for san in moves {
+            let mv = board.parse_san(san).unwrap();
+            board.make(mv);
+            hashes.push(board.hash());
+        }
+        hashes
+    }
+
+    #[test]
+    fn test_classify_deepest() {
+        let book = EcoBook::parse(BOOK).unwrap();
+        assert_eq!(book.len(), 3);
+        let code = |moves: &[&str]| book.classify(positions(moves)).map(|opening| opening.code.clone());
+        assert_eq!(code(&["e4", "e5", "Nf3", "Nf6"]), Some("C40".into()));
+        assert_eq!(code(&["d4"]), None);
+        // The Scotch, reached by a different move order.
+        assert_eq!(code(&["e4", "e5", "d4", "exd4", "Nf3", "Nc6", "Bc4"]), Some("C44".into()));
+    }
+
+    #[test]
+    fn test_errors_have_lines() {
+        match EcoBook::parse("C20 \"King's Pawn\" 1.e4 e5 *\nC40 \"Bad\" 1.e4 e5 2.Nf6 *").map(|_| ()) {
+            Err(Error(ErrorKind::InvalidEcoFile(line, _), _)) => assert_eq!(line, 2),
+            other => panic!("unexpected {:?}", other)
+        }
+        assert!(EcoBook::parse("C20 \"King's Pawn\" 1.e4 e5").is_err());
+    }
+}
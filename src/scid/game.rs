@@ -0,0 +1,435 @@
+// delila - a desktop version of lila.
+//
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//------------------------------------------------------------------------------
+// Game records (.sg4)
+//
+// A record holds the tags that aren't in the index, the starting position if
+// it isn't the usual one, and then the moves. Each move is one byte (queen
+// diagonals take two): the top four bits pick a piece from the mover's piece
+// list and the bottom four bits say where it goes. Comments come after all of
+// the moves, in the order that they're marked in the move stream.
+//------------------------------------------------------------------------------
+
+use super::bytebuf::ByteBuffer;
+use super::common::*;
+use board::{Board, Move};
+use pgn;
+use errors::*;
+
+pub const MAX_TAG_LEN: u8 = 240;
+
+// Tags that are common enough to be stored as a single byte, starting at 241.
+const COMMON_TAGS: [&'static str; 10] = [
+    "WhiteCountry", "BlackCountry", "Annotator", "PlyCount", "EventDate",
+    "Opening", "Variation", "Setup", "Source", "SetUp",
+];
+
+// A piece number of 0 is always the king, and these king "moves" are markers.
+const ENCODE_NAG: u8 = 11;
+const ENCODE_COMMENT: u8 = 12;
+const ENCODE_START_MARKER: u8 = 13;
+const ENCODE_END_MARKER: u8 = 14;
+const ENCODE_END_GAME: u8 = 15;
+
+// Game flags
+const GAME_FLAG_NONSTANDARD_START: u8 = 1;
+
+fn format_error(reason: String) -> Error {
+    ErrorKind::ScidFormat(reason).into()
+}
+
+//------------------------------------------------------------------------------
+// A board along with SCID's list of where each side's pieces are. The king is
+// always first, a captured piece's slot is taken by the last piece in the list,
+// and a piece keeps its slot when it moves or promotes.
+//------------------------------------------------------------------------------
+#[derive(Clone)]
+pub struct Position {
+    board: Board,
+    list: [[Square; 16]; 2],
+    count: [usize; 2],
+}
+
+impl Position {
+    pub fn start() -> Position {
+        let board = Board::start();
+        let mut list = [[NULL_SQUARE; 16]; 2];
+        let order = [E1, A1, B1, C1, D1, F1, G1, H1, A2, B2, C2, D2, E2, F2, G2, H2];
+        for (i, &sq) in order.iter().enumerate() {
+            list[WHITE as usize][i] = sq;
+            // The same squares, mirrored onto black's side.
+            list[BLACK as usize][i] = sq ^ 56;
+        }
+        Position{board: board, list: list, count: [16, 16]}
+    }
+
+    // The pieces are listed in the order they appear in the FEN, with each king
+    // swapped to the front.
+    pub fn from_fen(fen: &str) -> Result<Position> {
+        let board = Board::from_fen(fen)?;
+        let mut position = Position{board: board, list: [[NULL_SQUARE; 16]; 2], count: [0, 0]};
+        for r in (0..8).rev() {
+            for f in 0..8 {
+                let sq = square_make(f, r);
+                let piece = position.board.piece_at(sq);
+                if piece != EMPTY {
+                    position.add(piece, sq)?;
+                }
+            }
+        }
+        Ok(position)
+    }
+
+    fn add(&mut self, piece: Piece, sq: Square) -> Result<()> {
+        let c = piece_color(piece) as usize;
+        let n = self.count[c];
+        if n == 16 {
+            return Err(format_error("more than 16 pieces of one colour".into()));
+        }
+        if piece_type(piece) == KING {
+            self.list[c][n] = self.list[c][0];
+            self.list[c][0] = sq;
+        } else {
+            self.list[c][n] = sq;
+        }
+        self.count[c] += 1;
+        Ok(())
+    }
+
+    fn index_of(&self, color: Color, sq: Square) -> Option<usize> {
+        let c = color as usize;
+        self.list[c][..self.count[c]].iter().position(|&s| s == sq)
+    }
+
+    fn remove(&mut self, color: Color, sq: Square) {
+        if let Some(i) = self.index_of(color, sq) {
+            let c = color as usize;
+            self.count[c] -= 1;
+            self.list[c][i] = self.list[c][self.count[c]];
+        }
+    }
+
+    fn relocate(&mut self, color: Color, from: Square, to: Square) {
+        if let Some(i) = self.index_of(color, from) {
+            self.list[color as usize][i] = to;
+        }
+    }
+
+    pub fn board(&self) -> &Board { &self.board }
+
+    pub fn make(&mut self, mv: Move) {
+        let color = self.board.to_move();
+        let enemy = color_flip(color);
+        let piece = piece_type(self.board.piece_at(mv.from));
+        if self.board.piece_at(mv.to) != EMPTY {
+            self.remove(enemy, mv.to);
+        } else if piece == PAWN && square_fyle(mv.from) != square_fyle(mv.to) {
+            let captured = square_make(square_fyle(mv.to), square_rank(mv.from));
+            self.remove(enemy, captured);
+        }
+        self.relocate(color, mv.from, mv.to);
+        if piece == KING && (square_fyle(mv.to) - square_fyle(mv.from)).abs() == 2 {
+            let rank = square_rank(mv.from);
+            if square_fyle(mv.to) > square_fyle(mv.from) {
+                self.relocate(color, square_make(7, rank), square_make(5, rank));
+            } else {
+                self.relocate(color, square_make(0, rank), square_make(3, rank));
+            }
+        }
+        self.board.make(mv);
+    }
+
+    //--------------------------------------------------------------------------
+    // decode_move():
+    //      Turns a move byte (and the byte after it, for queen diagonals) into
+    //      a legal move.
+    //
+    pub fn decode_move(&self, byte: u8, buf: &mut ByteBuffer) -> Result<Move> {
+        let color = self.board.to_move();
+        let index = (byte >> 4) as usize;
+        let val = (byte & 15) as i8;
+        if index >= self.count[color as usize] {
+            return Err(format_error(format!("there is no piece number {}", index)));
+        }
+        let from = self.list[color as usize][index];
+        let from_file = square_fyle(from);
+        let from_rank = square_rank(from);
+        let mut promotion = EMPTY;
+        let to = match piece_type(self.board.piece_at(from)) {
+            KING => {
+                if val == 0 {
+                    bail!(ErrorKind::ScidFormat("null moves aren't supported".into()));
+                }
+                const DIFF: [i8; 11] = [0, -9, -8, -7, -1, 1, 7, 8, 9, -2, 2];
+                from + *DIFF.get(val as usize).ok_or_else(|| format_error("bad king move".into()))?
+            },
+            QUEEN if val == from_file => buf.get_byte()?.wrapping_sub(64) as i8,
+            QUEEN | ROOK => {
+                if val < 8 { square_make(val, from_rank) } else { square_make(from_file, val - 8) }
+            },
+            BISHOP => {
+                if val < 8 {
+                    square_make(val, from_rank + (val - from_file))
+                } else {
+                    square_make(val - 8, from_rank - (val - 8 - from_file))
+                }
+            },
+            KNIGHT => {
+                const DIFF: [i8; 9] = [0, -17, -15, -10, -6, 6, 10, 15, 17];
+                if val == 0 || val > 8 {
+                    bail!(ErrorKind::ScidFormat("bad knight move".into()));
+                }
+                from + DIFF[val as usize]
+            },
+            PAWN => {
+                const DIFF: [i8; 16] = [7, 8, 9, 7, 8, 9, 7, 8, 9, 7, 8, 9, 7, 8, 9, 16];
+                const PROMOTION: [Piece; 16] = [
+                    EMPTY, EMPTY, EMPTY, QUEEN, QUEEN, QUEEN, ROOK, ROOK, ROOK,
+                    BISHOP, BISHOP, BISHOP, KNIGHT, KNIGHT, KNIGHT, EMPTY
+                ];
+                promotion = PROMOTION[val as usize];
+                if color == WHITE { from + DIFF[val as usize] } else { from - DIFF[val as usize] }
+            },
+            _ => bail!(ErrorKind::ScidFormat(format!("piece number {} is missing", index)))
+        };
+        let mv = Move::promote(from, to, promotion);
+        if to < A1 || to > H8 || !self.board.is_legal(mv) {
+            bail!(ErrorKind::IllegalMove(format!("piece {} value {} at ply {}", index, val, self.board.ply() + 1)));
+        }
+        Ok(mv)
+    }
+}
+
+//------------------------------------------------------------------------------
+// decode_tags():
+//      Reads the tags that aren't kept in the index, up to the zero byte that
+//      ends them.
+//
+pub fn decode_tags(buf: &mut ByteBuffer) -> Result<Vec<pgn::Tag>> {
+    let mut tags = Vec::new();
+    loop {
+        let b = buf.get_byte()?;
+        if b == 0 {
+            break;
+        }
+        if b == 255 {
+            // A binary event date, which the index already has.
+            buf.get_three_bytes()?;
+            continue;
+        }
+        let name = if b > MAX_TAG_LEN {
+            match COMMON_TAGS.get((b - MAX_TAG_LEN - 1) as usize) {
+                Some(name) => name.to_string(),
+                None => bail!(ErrorKind::ScidFormat(format!("unknown common tag {}", b)))
+            }
+        } else {
+            buf.get_fixed_string(b as usize)?
+        };
+        let length = buf.get_byte()? as usize;
+        tags.push(pgn::Tag{name: name, value: buf.get_fixed_string(length)?});
+    }
+    Ok(tags)
+}
+
+// How a line of the move stream ended.
+#[derive(PartialEq)]
+enum Ending {
+    Variation,
+    Game
+}
+
+//------------------------------------------------------------------------------
+// decode_line():
+//      Reads moves until the end of a variation or of the game. Comments are
+//      only marked here, with an empty comment, and filled in afterwards.
+//
+fn decode_line(buf: &mut ByteBuffer, position: &mut Position) -> Result<(pgn::Line, Ending)> {
+    let mut line = pgn::Line::default();
+    // The position before the last move, which is where its variations start.
+    let mut before: Option<Position> = None;
+    loop {
+        let byte = buf.get_byte()?;
+        if byte >> 4 == 0 && byte & 15 >= ENCODE_NAG {
+            match byte & 15 {
+                ENCODE_NAG => {
+                    let nag = buf.get_byte()?;
+                    if let Some(last) = line.moves.last_mut() {
+                        last.nags.push(nag);
+                    }
+                },
+                ENCODE_COMMENT => {
+                    match line.moves.last_mut() {
+                        Some(last) => last.comment = Some(String::new()),
+                        None => line.comment = Some(String::new())
+                    }
+                },
+                ENCODE_START_MARKER => {
+                    let mut start = before.clone().ok_or_else(
+                        || format_error("a variation before any move".into())
+                    )?;
+                    let (variation, ending) = decode_line(buf, &mut start)?;
+                    if ending != Ending::Variation {
+                        bail!(ErrorKind::ScidFormat("the game ended inside a variation".into()));
+                    }
+                    if let Some(last) = line.moves.last_mut() {
+                        last.variations.push(variation);
+                    }
+                },
+                ENCODE_END_MARKER => return Ok((line, Ending::Variation)),
+                ENCODE_END_GAME => return Ok((line, Ending::Game)),
+                _ => unreachable!()
+            }
+            continue;
+        }
+        let mv = position.decode_move(byte, buf)?;
+        let san = position.board().san(mv);
+        before = Some(position.clone());
+        position.make(mv);
+        line.moves.push(pgn::Move::new(san));
+    }
+}
+
+// Fills in the comments that decode_line() marked, in the order they were marked.
+fn fill_comments(line: &mut pgn::Line, buf: &mut ByteBuffer) -> Result<()> {
+    fn fill(comment: &mut Option<String>, buf: &mut ByteBuffer) -> Result<()> {
+        if comment.is_some() {
+            let text = buf.get_terminated_string()?;
+            *comment = if text.is_empty() { None } else { Some(text) };
+        }
+        Ok(())
+    }
+    fill(&mut line.comment, buf)?;
+    for m in line.moves.iter_mut() {
+        fill(&mut m.comment, buf)?;
+        for variation in m.variations.iter_mut() {
+            fill_comments(variation, buf)?;
+        }
+    }
+    Ok(())
+}
+
+//------------------------------------------------------------------------------
+// decode_game():
+//      Reads a game record into a game with its own tags, its starting
+//      position and its moves. The tags that live in the index and name files
+//      are added by the caller.
+//
+pub fn decode_game(bytes: &[u8]) -> Result<pgn::Game> {
+    let mut buf = ByteBuffer::new(bytes);
+    let mut game = pgn::Game::default();
+    game.tags = decode_tags(&mut buf)?;
+    let flags = buf.get_byte()?;
+    let mut position = if flags & GAME_FLAG_NONSTANDARD_START != 0 {
+        let fen = buf.get_terminated_string()?;
+        let position = Position::from_fen(&fen)?;
+        game.tags.retain(|tag| tag.name != "FEN" && tag.name != "SetUp");
+        game.tags.push(pgn::Tag{name: "SetUp".into(), value: "1".into()});
+        game.tags.push(pgn::Tag{name: "FEN".into(), value: fen});
+        position
+    } else {
+        Position::start()
+    };
+    let (mut line, ending) = decode_line(&mut buf, &mut position)?;
+    if ending != Ending::Game {
+        bail!(ErrorKind::ScidFormat("a variation ended outside of any variation".into()));
+    }
+    fill_comments(&mut line, &mut buf)?;
+    game.line = line;
+    Ok(game)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sans(line: &pgn::Line) -> Vec<&str> {
+        line.moves.iter().map(|m| m.san.as_str()).collect()
+    }
+
+    #[test]
+    fn test_start_piece_list() {
+        let position = Position::start();
+        assert_eq!(position.list[BLACK as usize][0], E8);
+        assert_eq!(position.list[BLACK as usize][15], H7);
+    }
+
+    #[test]
+    fn test_decode_moves_variations_and_comments() {
+        let mut bytes = vec![
+            // Tags: Annotator, and a tag with its name spelled out.
+            243, 3, b'A', b'B', b'C',
+            4, b'T', b'e', b's', b't', 2, b'o', b'k',
+            0,
+            // Flags
+            0,
+            // A comment before the first move.
+            ENCODE_COMMENT,
+            // 1.e4 (the e2 pawn is piece 12, two squares forward)
+            (12 << 4) | 15,
+            ENCODE_NAG, 1,
+            // (1.d4 {comment} d5)
+            ENCODE_START_MARKER,
+            (11 << 4) | 15, ENCODE_COMMENT,
+            (11 << 4) | 15,
+            ENCODE_END_MARKER,
+            // 1...e5 2.Nf3 (piece 6, the g1 knight, to f3 is +15)
+            (12 << 4) | 15,
+            (6 << 4) | 7,
+            ENCODE_END_GAME,
+        ];
+        bytes.extend_from_slice(b"Before\0After d4\0");
+        let game = decode_game(&bytes).unwrap();
+        assert_eq!(game.tag("Annotator"), Some("ABC"));
+        assert_eq!(game.tag("Test"), Some("ok"));
+        assert_eq!(game.line.comment, Some("Before".into()));
+        assert_eq!(sans(&game.line), vec!["e4", "e5", "Nf3"]);
+        assert_eq!(game.line.moves[0].nags, vec![1]);
+        let variation = &game.line.moves[0].variations[0];
+        assert_eq!(sans(variation), vec!["d4", "d5"]);
+        assert_eq!(variation.moves[0].comment, Some("After d4".into()));
+    }
+
+    #[test]
+    fn test_captures_reuse_slots() {
+        // 1.e4 d5 2.exd5 Qxd5 3.Nc3 h6: black's last piece, the h7 pawn, takes the captured
+        // d5 pawn's slot.
+        let bytes = vec![
+            0, 0,
+            (12 << 4) | 15,
+            (11 << 4) | 15,
+            (12 << 4) | 0,
+            (4 << 4) | 12,
+            (2 << 4) | 8,
+            (11 << 4) | 1,
+            ENCODE_END_GAME,
+        ];
+        let game = decode_game(&bytes).unwrap();
+        assert_eq!(sans(&game.line), vec!["e4", "d5", "exd5", "Qxd5", "Nc3", "h6"]);
+    }
+
+    #[test]
+    fn test_nonstandard_start() {
+        let mut bytes = vec![0, GAME_FLAG_NONSTANDARD_START];
+        bytes.extend_from_slice(b"4k3/8/8/8/8/8/4P3/4K3 w - - 0 1\0");
+        // The white pawn is listed after the king.
+        bytes.extend_from_slice(&[(1 << 4) | 1, ENCODE_END_GAME]);
+        let game = decode_game(&bytes).unwrap();
+        assert_eq!(game.tag("FEN"), Some("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"));
+        assert_eq!(sans(&game.line), vec!["e3"]);
+    }
+}
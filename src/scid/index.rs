@@ -0,0 +1,315 @@
+// delila - a desktop version of lila.
+//
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//------------------------------------------------------------------------------
+// Index files (.si4 and .si5)
+//
+// The index holds a fixed size entry for each game, with where its record is
+// in the game file, the ids of its names in the name file, and the header
+// fields that SCID searches on. Version 5 indexes have no header, and their
+// entries are wider, with 64 bit offsets and 28 bit name ids.
+//------------------------------------------------------------------------------
+
+use super::bytebuf::ByteBuffer;
+use super::common::*;
+use errors::*;
+
+pub const INDEX_MAGIC: &'static [u8] = b"Scid.si\0";
+pub const SCID_VERSION: u32 = 400;
+pub const INDEX_HEADER_SIZE: usize = 182;
+pub const INDEX_ENTRY_SIZE: usize = 47;
+pub const SCID5_INDEX_ENTRY_SIZE: usize = 56;
+
+const SCID_DESC_LENGTH: usize = 108;
+const CUSTOM_FLAG_DESC_LENGTH: usize = 9;
+const CUSTOM_FLAG_COUNT: usize = 6;
+
+// Index flags
+pub const IDX_FLAG_START: u32 = 1 << 0;
+pub const IDX_FLAG_DELETE: u32 = 1 << 3;
+
+pub type Date = u32;
+pub const ZERO_DATE: Date = 0;
+
+pub fn date_year(date: Date) -> u32 { date >> 9 }
+pub fn date_month(date: Date) -> u32 { (date >> 5) & 15 }
+pub fn date_day(date: Date) -> u32 { date & 31 }
+
+//------------------------------------------------------------------------------
+// date_to_string():
+//      Formats a date the way PGN does, with question marks for the parts that
+//      are unknown.
+//
+pub fn date_to_string(date: Date) -> String {
+    let part = |value: u32, width: usize| if value == 0 {
+        "?".repeat(width)
+    } else {
+        format!("{:0width$}", value, width = width)
+    };
+    format!("{}.{}.{}", part(date_year(date), 4), part(date_month(date), 2), part(date_day(date), 2))
+}
+
+//------------------------------------------------------------------------------
+// eco_to_string():
+//      The basic code (e.g. "B90") of an ECO number. SCID also numbers its own
+//      extensions (e.g. "B90a1"), which PGN has no room for.
+//
+pub fn eco_to_string(eco: ECO) -> Option<String> {
+    if eco == ECO_NONE {
+        return None;
+    }
+    let basic = (eco as u32 - 1) / 131;
+    let letter = (b'A' + (basic / 100) as u8) as char;
+    Some(format!("{}{:02}", letter, basic % 100))
+}
+
+pub fn result_to_string(result: u32) -> &'static str {
+    match result {
+        1 => "1-0",
+        2 => "0-1",
+        3 => "1/2-1/2",
+        _ => "*"
+    }
+}
+
+pub struct IndexHeader {
+    pub version: u32,
+    pub base_type: u32,
+    pub num_games: u32,
+    pub auto_load: u32,
+    pub description: String,
+}
+
+impl IndexHeader {
+    pub fn read(buf: &mut ByteBuffer) -> Result<IndexHeader> {
+        if buf.get_fixed_bytes(INDEX_MAGIC.len())? != INDEX_MAGIC {
+            bail!(ErrorKind::ScidFormat("not a SCID index file".into()));
+        }
+        let version = buf.get_two_bytes()?;
+        if version != SCID_VERSION {
+            bail!(ErrorKind::ScidFormat(format!("unsupported index version {}", version)));
+        }
+        let header = IndexHeader{
+            version: version,
+            base_type: buf.get_four_bytes()?,
+            num_games: buf.get_three_bytes()?,
+            auto_load: buf.get_three_bytes()?,
+            description: buf.get_padded_string(SCID_DESC_LENGTH)?
+        };
+        buf.get_fixed_bytes(CUSTOM_FLAG_DESC_LENGTH * CUSTOM_FLAG_COUNT)?;
+        Ok(header)
+    }
+}
+
+// The parts of an index entry that are needed to rebuild a game's headers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexEntry {
+    pub offset: u64,
+    pub length: u32,
+    pub flags: u32,
+    pub white_id: u32,
+    pub black_id: u32,
+    pub event_id: u32,
+    pub site_id: u32,
+    pub round_id: u32,
+    pub result: u32,
+    pub eco: ECO,
+    pub date: Date,
+    pub event_date: Date,
+    pub white_elo: ELO,
+    pub black_elo: ELO,
+    pub num_half_moves: u32,
+}
+
+impl IndexEntry {
+    pub fn read(buf: &mut ByteBuffer) -> Result<IndexEntry> {
+        let offset = buf.get_four_bytes()?;
+        let length_low = buf.get_two_bytes()?;
+        let length_high = buf.get_byte()? as u32;
+        let flags = buf.get_two_bytes()?;
+
+        let white_black_high = buf.get_byte()? as u32;
+        let white_id_low = buf.get_two_bytes()?;
+        let black_id_low = buf.get_two_bytes()?;
+
+        let event_site_round_high = buf.get_byte()? as u32;
+        let event_id_low = buf.get_two_bytes()?;
+        let site_id_low = buf.get_two_bytes()?;
+        let round_id_low = buf.get_two_bytes()?;
+
+        let var_counts = buf.get_two_bytes()?;
+        let eco = buf.get_two_bytes()? as ECO;
+        let dates = buf.get_four_bytes()?;
+        let white_elo = buf.get_two_bytes()?;
+        let black_elo = buf.get_two_bytes()?;
+        let _final_matsig = buf.get_four_bytes()?;
+        let num_half_moves_low = buf.get_byte()? as u32;
+        let home_pawn_data = buf.get_fixed_bytes(9)?;
+
+        let date = dates & 0xFFFFF;
+        Ok(IndexEntry{
+            offset: offset as u64,
+            length: length_low + ((length_high & 0x80) << 9),
+            flags: flags,
+            white_id: ((white_black_high & 0xF0) << 12) | white_id_low,
+            black_id: ((white_black_high & 0x0F) << 16) | black_id_low,
+            event_id: ((event_site_round_high >> 5) << 16) | event_id_low,
+            site_id: (((event_site_round_high >> 2) & 7) << 16) | site_id_low,
+            round_id: ((event_site_round_high & 3) << 16) | round_id_low,
+            result: var_counts >> 12,
+            eco: eco,
+            date: date,
+            event_date: event_date(date, dates >> 20),
+            // The top four bits are the kind of rating.
+            white_elo: (white_elo & 0xFFF) as ELO,
+            black_elo: (black_elo & 0xFFF) as ELO,
+            num_half_moves: num_half_moves_low | ((home_pawn_data[0] as u32 >> 6) << 8)
+        })
+    }
+
+    //--------------------------------------------------------------------------
+    // read_scid5():
+    //      Reads a version 5 entry, which is fourteen little-endian words:
+    //        0-4   white, black, event, site and round ids, in 28 bits each
+    //        5     white elo and rating type, then black's, in 16 bits each
+    //        6     final material signature, and the low 8 bits of the ply count
+    //        7     date, and the event date packed as in version 4
+    //        8-9   offset in the game file (47 bits), then record length (17 bits)
+    //        10    flags, and the high bits of the ply count from bit 24
+    //        11    ECO code, then the variation counts with the result on top
+    //        12-13 home pawn data
+    //
+    pub fn read_scid5(buf: &mut ByteBuffer) -> Result<IndexEntry> {
+        let mut words = [0u32; 8];
+        for word in words.iter_mut() {
+            *word = buf.get_u32_le()?;
+        }
+        let location = buf.get_u64_le()?;
+        let flags = buf.get_u32_le()?;
+        let eco_counts = buf.get_u32_le()?;
+        buf.get_fixed_bytes(8)?;
+
+        let id = |word: u32| word & 0x0FFFFFFF;
+        let date = words[7] & 0xFFFFF;
+        Ok(IndexEntry{
+            offset: location & ((1 << 47) - 1),
+            length: (location >> 47) as u32,
+            flags: flags & 0xFFFFFF,
+            white_id: id(words[0]),
+            black_id: id(words[1]),
+            event_id: id(words[2]),
+            site_id: id(words[3]),
+            round_id: id(words[4]),
+            result: eco_counts >> 28,
+            eco: (eco_counts & 0xFFFF) as ECO,
+            date: date,
+            event_date: event_date(date, words[7] >> 20),
+            white_elo: (words[5] & 0xFFF) as ELO,
+            black_elo: ((words[5] >> 16) & 0xFFF) as ELO,
+            num_half_moves: (words[6] >> 24) | ((flags >> 24) << 8)
+        })
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.flags & IDX_FLAG_DELETE != 0
+    }
+}
+
+//------------------------------------------------------------------------------
+// event_date():
+//      The event date is squeezed into 12 bits, with its year stored as an
+//      offset of -3 to +3 from the year of the game.
+//
+fn event_date(date: Date, packed: u32) -> Date {
+    let offset = (packed >> 9) & 7;
+    if offset == 0 || date_year(date) == 0 {
+        return ZERO_DATE;
+    }
+    let year = date_year(date) + offset - 4;
+    (year << 9) | (packed & 0x1FF)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_date_to_string() {
+        assert_eq!(date_to_string((1997 << 9) | (5 << 5) | 11), "1997.05.11");
+        assert_eq!(date_to_string(1997 << 9), "1997.??.??");
+        assert_eq!(date_to_string(ZERO_DATE), "????.??.??");
+    }
+
+    #[test]
+    fn test_eco_to_string() {
+        assert_eq!(eco_to_string(ECO_NONE), None);
+        assert_eq!(eco_to_string(1), Some("A00".into()));
+        assert_eq!(eco_to_string((190 * 131 + 3 + 1) as ECO), Some("B90".into()));
+    }
+
+    #[test]
+    fn test_read_entry() {
+        let bytes = [
+            0, 0, 1, 0,         // offset
+            0x12, 0x34, 0x80,   // length
+            0, 1 << 3,          // flags
+            0x12, 0, 5, 0, 6,   // white and black
+            0b101_010_11, 0, 7, 0, 8, 0, 9,  // event, site and round
+            0x30, 0,            // result
+            0, 1,               // eco
+            0x8A, 0x1F, 0x9A, 0xAB, // dates
+            0x10, 0x64, 0, 0,   // ratings
+            0, 0, 0, 0,         // final material
+            10,                 // half moves
+            0x40, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        let entry = IndexEntry::read(&mut ByteBuffer::new(&bytes)).unwrap();
+        assert_eq!(entry.offset, 256);
+        assert_eq!(entry.length, 0x11234);
+        assert!(entry.is_deleted());
+        assert_eq!((entry.white_id, entry.black_id), (0x10005, 0x20006));
+        assert_eq!((entry.event_id, entry.site_id, entry.round_id), (0x50007, 0x20008, 0x30009));
+        assert_eq!(result_to_string(entry.result), "1/2-1/2");
+        assert_eq!(date_to_string(entry.date), "1997.05.11");
+        assert_eq!(date_to_string(entry.event_date), "1997.05.01");
+        assert_eq!((entry.white_elo, entry.black_elo), (100, 0));
+        assert_eq!(entry.num_half_moves, 266);
+    }
+
+    #[test]
+    fn test_read_scid5_entry() {
+        let mut words: Vec<u32> = vec![0x10000005, 6, 0x0FFFFFFF, 8, 9, (2100 << 16) | 0x1000 | 2000, 10 << 24,
+                                       (2209 << 20) | (1997 << 9) | (5 << 5) | 11];
+        let location: u64 = (300 << 47) | (1 << 40);
+        words.push(location as u32);
+        words.push((location >> 32) as u32);
+        words.extend_from_slice(&[(1 << 24) | IDX_FLAG_DELETE, (2 << 28) | 1, 0, 0]);
+        let bytes: Vec<u8> = words.iter()
+            .flat_map(|w| (0..4).map(move |i| (w >> (8 * i)) as u8))
+            .collect();
+        assert_eq!(bytes.len(), SCID5_INDEX_ENTRY_SIZE);
+        let entry = IndexEntry::read_scid5(&mut ByteBuffer::new(&bytes)).unwrap();
+        assert_eq!((entry.offset, entry.length), (1 << 40, 300));
+        assert!(entry.is_deleted());
+        assert_eq!((entry.white_id, entry.black_id, entry.event_id), (5, 6, 0x0FFFFFFF));
+        assert_eq!((entry.white_elo, entry.black_elo), (2000, 2100));
+        assert_eq!(result_to_string(entry.result), "0-1");
+        assert_eq!(eco_to_string(entry.eco), Some("A00".into()));
+        assert_eq!(date_to_string(entry.event_date), "1997.05.01");
+        assert_eq!(entry.num_half_moves, 266);
+    }
+}
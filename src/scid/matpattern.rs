@@ -0,0 +1,268 @@
+// delila - a desktop version of lila.
+// 
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+// 
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// 
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+// 
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//------------------------------------------------------------------------------
+// Material Patterns
+//------------------------------------------------------------------------------
+
+// Parses the material descriptions people actually type into a pair of
+// material signatures: the least and the most of each piece that a position
+// may have. Some examples:
+//
+//   KRPKR           Rook and pawn against a rook (the K's split the sides).
+//   RR vs R+B       Two rooks against a rook and a bishop.
+//   R+2P v R        Counts may be written in front of a piece.
+//   R P:0-2 vs R    A rook and up to two pawns against a rook.
+//   Q P:1- vs Q     Queen and at least one pawn against a queen.
+//   QRR20:R10       The output of matsig::make_string.
+//
+// Pieces that aren't mentioned must be absent.
+
+use super::common::*;
+use super::matsig;
+use super::matsig::MaterialSignature;
+use errors::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MaterialPattern {
+    pub min: MaterialSignature,
+    pub max: MaterialSignature
+}
+
+const PIECES: [Piece; 10] = [WQ, WR, WB, WN, WP, BQ, BR, BB, BN, BP];
+
+fn invalid(pattern: &str, reason: &str) -> Error {
+    ErrorKind::InvalidMaterialPattern(pattern.into(), reason.into()).into()
+}
+
+// The most of a piece that a signature can record.
+fn max_count(p: Piece) -> UInt {
+    if p == WP || p == BP { 8 } else { 3 }
+}
+
+impl MaterialPattern {
+    pub fn exact(signature: MaterialSignature) -> MaterialPattern {
+        MaterialPattern{min: signature, max: signature}
+    }
+
+    pub fn is_exact(&self) -> bool {
+        self.min == self.max
+    }
+
+    pub fn matches(&self, signature: MaterialSignature) -> bool {
+        PIECES.iter().all(|&p| {
+            let count = matsig::get_count(signature, p);
+            matsig::get_count(self.min, p) <= count && count <= matsig::get_count(self.max, p)
+        })
+    }
+
+    //--------------------------------------------------------------------------
+    // parse():
+    //      Parses a material description, see the top of this file.
+    //
+    pub fn parse(pattern: &str) -> Result<MaterialPattern> {
+        let (white, black) = split_sides(pattern)?;
+        let mut result = MaterialPattern{min: matsig::EMPTY, max: matsig::EMPTY};
+        parse_side(pattern, white, WHITE, &mut result)?;
+        parse_side(pattern, black, BLACK, &mut result)?;
+        Ok(result)
+    }
+}
+
+// The most of a piece that a range may ask for, by the piece's letter.
+fn letter_max(letter: char) -> Option<UInt> {
+    let piece = match letter.to_ascii_uppercase() {
+        'Q' => QUEEN,
+        'R' => ROOK,
+        'B' => BISHOP,
+        'N' => KNIGHT,
+        'P' => PAWN,
+        _ => return None
+    };
+    Some(max_count(piece_make(WHITE, piece)))
+}
+
+fn split_sides(pattern: &str) -> Result<(&str, &str)> {
+    let trimmed = pattern.trim();
+    for separator in &[" vs ", " v "] {
+        if let Some(index) = trimmed.find(separator) {
+            return Ok((&trimmed[..index], &trimmed[index + separator.len()..]));
+        }
+    }
+    // P:0-2 style ranges also use ':'. A ':' is only a range between a piece and a count that
+    // the piece can have. matsig::make_string writes pawn counts with a trailing '0', so the
+    // ':' in R:10 or Q:20 separates the sides.
+    for (index, _) in trimmed.match_indices(':') {
+        let count: String = trimmed[index + 1..].chars().take_while(|c| c.is_digit(10)).collect();
+        let is_range = match (trimmed[..index].chars().last().and_then(letter_max), count.parse::<UInt>()) {
+            (Some(most), Ok(count)) => count <= most,
+            _ => false
+        };
+        if !is_range {
+            return Ok((&trimmed[..index], &trimmed[index + 1..]));
+        }
+    }
+    // KRPKR style: the second king starts black's material.
+    if trimmed.starts_with('K') {
+        if let Some(index) = trimmed[1..].find('K') {
+            return Ok((&trimmed[..index + 1], &trimmed[index + 1..]));
+        }
+    }
+    Err(invalid(pattern, "expected two sides, like KRPKR or R vs B"))
+}
+
+fn parse_number(pattern: &str, chars: &[char], index: &mut usize) -> Result<Option<UInt>> {
+    let mut digits = String::new();
+    while *index < chars.len() && chars[*index].is_digit(10) {
+        digits.push(chars[*index]);
+        *index += 1;
+    }
+    if digits.is_empty() {
+        return Ok(None);
+    }
+    digits.parse().map(Some).map_err(|_| invalid(pattern, "number is too large"))
+}
+
+fn parse_side(pattern: &str, side: &str, color: Color, result: &mut MaterialPattern) -> Result<()> {
+    let chars: Vec<char> = side.chars().filter(|c| !c.is_whitespace() && *c != '+').collect();
+    let mut index = 0;
+    while index < chars.len() {
+        let count = parse_number(pattern, &chars, &mut index)?;
+        if index == chars.len() {
+            // A bare number at the end is a pawn count as written by matsig::make_string, which
+            // follows the count with a '0'.
+            match count {
+                Some(count) if count >= 10 && count % 10 == 0 => {
+                    add(pattern, result, piece_make(color, PAWN), count / 10, count / 10)?;
+                    break;
+                },
+                _ => return Err(invalid(pattern, "expected a piece after a count"))
+            }
+        }
+        let letter = chars[index];
+        index += 1;
+        let piece = match letter.to_ascii_uppercase() {
+            'K' => continue,
+            'Q' => QUEEN,
+            'R' => ROOK,
+            'B' => BISHOP,
+            'N' => KNIGHT,
+            'P' => PAWN,
+            _ => return Err(invalid(pattern, &format!("unknown piece '{}'", letter)))
+        };
+        let piece = piece_make(color, piece);
+
+        if index < chars.len() && chars[index] == ':' {
+            if count.is_some() {
+                return Err(invalid(pattern, "a piece can't have both a count and a range"));
+            }
+            index += 1;
+            let min = parse_number(pattern, &chars, &mut index)?
+                .ok_or_else(|| invalid(pattern, "expected a number after ':'"))?;
+            let max = if index < chars.len() && chars[index] == '-' {
+                index += 1;
+                parse_number(pattern, &chars, &mut index)?.unwrap_or(max_count(piece))
+            } else {
+                min
+            };
+            add(pattern, result, piece, min, max)?;
+        } else {
+            let count = count.unwrap_or(1);
+            add(pattern, result, piece, count, count)?;
+        }
+    }
+    Ok(())
+}
+
+// Adds to the counts of a piece, so that RR and 2R both mean two rooks.
+fn add(pattern: &str, result: &mut MaterialPattern, piece: Piece, min: UInt, max: UInt) -> Result<()> {
+    let min = matsig::get_count(result.min, piece) + min;
+    let max = matsig::get_count(result.max, piece) + max;
+    if min > max {
+        return Err(invalid(pattern, "a range can't end before it starts"));
+    }
+    if max > max_count(piece) {
+        return Err(invalid(pattern, "too many of one piece"));
+    }
+    result.min = matsig::set_count(result.min, piece, min);
+    result.max = matsig::set_count(result.max, piece, max);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exact(pattern: &str) -> String {
+        let parsed = MaterialPattern::parse(pattern).unwrap();
+        assert!(parsed.is_exact());
+        matsig::make_string(parsed.min)
+    }
+
+    #[test]
+    fn test_exact_patterns() {
+        assert_eq!(exact("KRPKR"), "R10:R");
+        assert_eq!(exact("KK"), ":");
+        assert_eq!(exact("KQKRB"), "Q:RB");
+        assert_eq!(exact("RR vs R+B"), "RR:RB");
+        assert_eq!(exact("R+2P v R"), "R20:R");
+        assert_eq!(exact("N vs 3P"), "N:30");
+        assert_eq!(exact("RP:1-1:R"), "R10:R");
+    }
+
+    #[test]
+    fn test_make_string_round_trip() {
+        for signature in &[matsig::STD_START, 0b0000_0000_0101_0101_0101_0101_0101_0101] {
+            let text = matsig::make_string(*signature);
+            assert_eq!(MaterialPattern::parse(&text).unwrap(), MaterialPattern::exact(*signature));
+        }
+    }
+
+    #[test]
+    fn test_lopsided_round_trip() {
+        assert_eq!(exact("KRKP"), "R:10");
+        assert_eq!(exact("KQKPP"), "Q:20");
+        for pattern in &["KRKP", "KQKPP", "KPKR", "KRPPKP", "KNKPPPPPPPP", "KKP"] {
+            let text = exact(pattern);
+            assert_eq!(MaterialPattern::parse(&text).unwrap(), MaterialPattern::parse(pattern).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_ranges() {
+        let pattern = MaterialPattern::parse("R P:0-2 vs R").unwrap();
+        assert!(!pattern.is_exact());
+        assert!(pattern.matches(MaterialPattern::parse("KRKR").unwrap().min));
+        assert!(pattern.matches(MaterialPattern::parse("KRPPKR").unwrap().min));
+        assert!(!pattern.matches(MaterialPattern::parse("KRPPPKR").unwrap().min));
+        assert!(!pattern.matches(MaterialPattern::parse("KRPKRP").unwrap().min));
+
+        let pattern = MaterialPattern::parse("Q P:1- vs Q").unwrap();
+        assert_eq!(matsig::count_wp(pattern.min), 1);
+        assert_eq!(matsig::count_wp(pattern.max), 8);
+    }
+
+    #[test]
+    fn test_invalid_patterns() {
+        assert!(MaterialPattern::parse("").is_err());
+        assert!(MaterialPattern::parse("KRX vs R").is_err());
+        assert!(MaterialPattern::parse("RRRR vs R").is_err());
+        assert!(MaterialPattern::parse("R P:2-1 vs R").is_err());
+        assert!(MaterialPattern::parse("R 2P:0-1 vs R").is_err());
+        assert!(MaterialPattern::parse("R vs 2").is_err());
+    }
+}
@@ -19,4 +19,14 @@
 // SCID compatibility layer
 //------------------------------------------------------------------------------
 pub mod matsig;
+pub mod matpattern;
 pub mod common;
+pub mod bytebuf;
+pub mod index;
+pub mod namebase;
+pub mod game;
+pub mod base;
+pub mod eco;
+pub mod spelling;
+
+pub use self::base::ScidBase;
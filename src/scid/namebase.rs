@@ -0,0 +1,149 @@
+// delila - a desktop version of lila.
+//
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//------------------------------------------------------------------------------
+// Name files (.sn4 and .sn5)
+//
+// The players, events, sites and rounds of a base, each stored once and
+// referred to by id from the index. In version 4 names are sorted, and each
+// one only stores what differs from the name before it. Version 5 names are
+// kept in the order they were added, which is the order of their ids.
+//------------------------------------------------------------------------------
+
+use super::bytebuf::{ByteBuffer, decode_text};
+use errors::*;
+
+pub const NAMEBASE_MAGIC: &'static [u8] = b"Scid.sn\0";
+
+pub type NameType = usize;
+pub const NAME_PLAYER: NameType = 0;
+pub const NAME_EVENT: NameType = 1;
+pub const NAME_SITE: NameType = 2;
+pub const NAME_ROUND: NameType = 3;
+pub const NUM_NAME_TYPES: usize = 4;
+
+#[derive(Debug, Default)]
+pub struct NameBase {
+    names: [Vec<String>; NUM_NAME_TYPES],
+}
+
+impl NameBase {
+    pub fn read(buf: &mut ByteBuffer) -> Result<NameBase> {
+        if buf.get_fixed_bytes(NAMEBASE_MAGIC.len())? != NAMEBASE_MAGIC {
+            bail!(ErrorKind::ScidFormat("not a SCID name file".into()));
+        }
+        let _timestamp = buf.get_four_bytes()?;
+        let mut num_names = [0; NUM_NAME_TYPES];
+        for count in num_names.iter_mut() {
+            *count = buf.get_three_bytes()?;
+        }
+        let mut max_frequency = [0; NUM_NAME_TYPES];
+        for frequency in max_frequency.iter_mut() {
+            *frequency = buf.get_three_bytes()?;
+        }
+
+        let mut namebase = NameBase::default();
+        for t in 0..NUM_NAME_TYPES {
+            let id_size = if num_names[t] >= 65536 { 3 } else { 2 };
+            let frequency_size = match max_frequency[t] {
+                f if f >= 65536 => 3,
+                f if f >= 256 => 2,
+                _ => 1
+            };
+            let names = &mut namebase.names[t];
+            names.resize(num_names[t] as usize, String::new());
+            let mut previous: Vec<u8> = Vec::new();
+            for i in 0..num_names[t] {
+                let id = buf.get_unsigned(id_size)? as usize;
+                let _frequency = buf.get_unsigned(frequency_size)?;
+                let length = buf.get_byte()? as usize;
+                let prefix = if i > 0 { buf.get_byte()? as usize } else { 0 };
+                if prefix > length || prefix > previous.len() {
+                    bail!(ErrorKind::ScidFormat(format!("bad name prefix at name {}", i)));
+                }
+                let mut name = previous[..prefix].to_vec();
+                name.extend_from_slice(buf.get_fixed_bytes(length - prefix)?);
+                match names.get_mut(id) {
+                    Some(slot) => *slot = decode_text(&name),
+                    None => bail!(ErrorKind::ScidFormat(format!("name id {} is out of range", id)))
+                }
+                previous = name;
+            }
+        }
+        Ok(namebase)
+    }
+
+    //--------------------------------------------------------------------------
+    // read_scid5():
+    //      Each name is a variable length number, with the name type in its
+    //      bottom two bits and the length above them, and then the name.
+    //
+    pub fn read_scid5(buf: &mut ByteBuffer) -> Result<NameBase> {
+        let mut namebase = NameBase::default();
+        while !buf.is_empty() {
+            let header = buf.get_varint()?;
+            let name = buf.get_fixed_bytes((header >> 2) as usize)?;
+            namebase.names[(header & 3) as usize].push(decode_text(name));
+        }
+        Ok(namebase)
+    }
+
+    // The name with the given id, or "?" for ids that don't exist.
+    pub fn name(&self, name_type: NameType, id: u32) -> &str {
+        self.names[name_type].get(id as usize).map_or("?", |name| name.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_names() {
+        let mut bytes = b"Scid.sn\0".to_vec();
+        bytes.extend_from_slice(&[0, 0, 0, 0]);
+        bytes.extend_from_slice(&[0, 0, 2, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(&[0, 0, 3, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+        // Players, sorted, with ids in the other order.
+        bytes.extend_from_slice(&[0, 1, 2, 7]);
+        bytes.extend_from_slice(b"Carlsen");
+        bytes.extend_from_slice(&[0, 0, 1, 7, 5]);
+        bytes.extend_from_slice(b"on");
+        bytes.extend_from_slice(&[0, 0, 1, 4]);
+        bytes.extend_from_slice(b"Oslo");
+        let namebase = NameBase::read(&mut ByteBuffer::new(&bytes)).unwrap();
+        assert_eq!(namebase.name(NAME_PLAYER, 0), "Carlson");
+        assert_eq!(namebase.name(NAME_PLAYER, 1), "Carlsen");
+        assert_eq!(namebase.name(NAME_EVENT, 0), "Oslo");
+        assert_eq!(namebase.name(NAME_SITE, 0), "?");
+    }
+
+    #[test]
+    fn test_read_scid5_names() {
+        let mut bytes = vec![(7 << 2) | NAME_PLAYER as u8];
+        bytes.extend_from_slice(b"Carlsen");
+        bytes.push((4 << 2) | NAME_SITE as u8);
+        bytes.extend_from_slice(b"Oslo");
+        bytes.push((5 << 2) | NAME_PLAYER as u8);
+        bytes.extend_from_slice(b"Carlo");
+        let namebase = NameBase::read_scid5(&mut ByteBuffer::new(&bytes)).unwrap();
+        assert_eq!(namebase.name(NAME_PLAYER, 0), "Carlsen");
+        assert_eq!(namebase.name(NAME_PLAYER, 1), "Carlo");
+        assert_eq!(namebase.name(NAME_SITE, 0), "Oslo");
+        assert_eq!(namebase.name(NAME_ROUND, 0), "?");
+    }
+}
@@ -0,0 +1,246 @@
+// delila - a desktop version of lila.
+//
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//------------------------------------------------------------------------------
+// Spelling files (.ssp)
+//
+// SCID's spelling files list the correct spelling of each player, event, site
+// and round, each followed by the spellings that should be corrected to it.
+// A line starting with '@' begins the names of one type:
+//
+//     @PLAYER
+//     Carlsen, Magnus            #gm NOR [1990.11.30]
+//     %ID 1503014
+//     %Elo 2004:2484,2552 2005:2570
+//     = Carlsen, M
+//     = Carlsen, M.
+//
+// Anything after a '#' on a name is information about it, and lines that
+// start with '#' are comments. Players may have a FIDE id and their ratings by
+// year, each year listing the ratings of its rating periods in order. Other
+// '%' lines (%Bio, %Render and so on) are skipped.
+//------------------------------------------------------------------------------
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use super::bytebuf::decode_text;
+use super::namebase::*;
+use errors::*;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlayerInfo {
+    pub fide_id: Option<i32>,
+    // (year, rating), in the order they're listed.
+    pub ratings: Vec<(i32, i32)>,
+}
+
+impl PlayerInfo {
+    // The first rating listed for the year.
+    pub fn rating(&self, year: i32) -> Option<i32> {
+        self.ratings.iter().find(|&&(y, _)| y == year).map(|&(_, rating)| rating)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Spelling {
+    // From the key of each spelling to the correct one.
+    names: [HashMap<String, String>; NUM_NAME_TYPES],
+    // By the key of the correct spelling.
+    players: HashMap<String, PlayerInfo>,
+}
+
+fn invalid(line: usize, reason: String) -> Error {
+    ErrorKind::InvalidSpellingFile(line, reason).into()
+}
+
+// Spellings are matched regardless of case and spacing, so "carlsen,magnus" is "Carlsen, Magnus".
+fn key(name: &str) -> String {
+    name.replace(',', ", ").split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+fn section(name: &str) -> Option<NameType> {
+    match name.trim().to_uppercase().as_str() {
+        "PLAYER" => Some(NAME_PLAYER),
+        "EVENT" => Some(NAME_EVENT),
+        "SITE" => Some(NAME_SITE),
+        "ROUND" => Some(NAME_ROUND),
+        _ => None
+    }
+}
+
+// "2004:2484,2552 2005:2570", with 0 for periods without a rating.
+fn parse_ratings(text: &str) -> Option<Vec<(i32, i32)>> {
+    let mut ratings = Vec::new();
+    for year in text.split_whitespace() {
+        let mut parts = year.splitn(2, ':');
+        let year = match parts.next().and_then(|y| y.parse::<i32>().ok()) {
+            Some(year) => year,
+            None => return None
+        };
+        for rating in parts.next().unwrap_or("").split(',').filter(|r| !r.is_empty()) {
+            match rating.parse::<i32>() {
+                Ok(0) => continue,
+                Ok(rating) => ratings.push((year, rating)),
+                Err(_) => return None
+            }
+        }
+    }
+    Some(ratings)
+}
+
+impl Spelling {
+    pub fn load(path: &Path) -> Result<Spelling> {
+        let mut bytes = Vec::new();
+        fs::File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut bytes))
+            .chain_err(|| format!("Unable to read {}", path.display()))?;
+        Spelling::parse(&decode_text(&bytes))
+            .chain_err(|| format!("Unable to read {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<Spelling> {
+        let mut spelling = Spelling::default();
+        let mut name_type = None;
+        let mut current: Option<String> = None;
+        for (number, line) in text.lines().enumerate() {
+            let number = number + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('@') {
+                let found = section(&line[1..]);
+                name_type = Some(found.ok_or_else(|| invalid(number, format!("unknown section {}", line)))?);
+                current = None;
+                continue;
+            }
+            let name_type = name_type
+                .ok_or_else(|| invalid(number, "names must follow a section, such as @PLAYER".into()))?;
+            if line.starts_with('=') || line.starts_with('%') {
+                let correct = current.as_ref()
+                    .ok_or_else(|| invalid(number, format!("{} doesn't follow a name", line)))?;
+                if line.starts_with('=') {
+                    let alias = line[1..].split('#').next().unwrap_or("").trim();
+                    if !alias.is_empty() {
+                        spelling.names[name_type].insert(key(alias), correct.clone());
+                    }
+                    continue;
+                }
+                let mut words = line[1..].splitn(2, char::is_whitespace);
+                let directive = words.next().unwrap_or("");
+                let rest = words.next().unwrap_or("").trim();
+                if name_type != NAME_PLAYER {
+                    continue;
+                }
+                let info = spelling.players.entry(key(correct)).or_insert_with(PlayerInfo::default);
+                match directive {
+                    "ID" => {
+                        let id = rest.split_whitespace().next().and_then(|id| id.parse().ok())
+                            .ok_or_else(|| invalid(number, format!("bad FIDE id {}", rest)))?;
+                        info.fide_id = Some(id);
+                    },
+                    "Elo" => {
+                        let ratings = parse_ratings(rest)
+                            .ok_or_else(|| invalid(number, format!("bad ratings {}", rest)))?;
+                        info.ratings.extend(ratings);
+                    },
+                    _ => {}
+                }
+                continue;
+            }
+            let name = line.split('#').next().unwrap_or("").trim().to_string();
+            spelling.names[name_type].insert(key(&name), name.clone());
+            current = Some(name);
+        }
+        Ok(spelling)
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.iter().map(|names| names.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // The correct spelling of the name, if the file knows it.
+    pub fn correct(&self, name_type: NameType, name: &str) -> Option<&str> {
+        self.names[name_type].get(&key(name)).map(|name| name.as_str())
+    }
+
+    // What the file knows about the player, by any of their spellings.
+    pub fn player(&self, name: &str) -> Option<&PlayerInfo> {
+        let correct = self.correct(NAME_PLAYER, name).unwrap_or(name);
+        self.players.get(&key(correct))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPELLING: &'static str = "
+# Players
+@PLAYER
+Carlsen, Magnus            #gm NOR [1990.11.30]
+%ID 1503014
+%Bio Born in Tonsberg
+%Elo 2004:0,2484 2005:2570,2581
+= Carlsen, M
+= Carlsen,M.
+
+@SITE
+Wijk aan Zee NED
+= Wijk aan Zee
+";
+
+    #[test]
+    fn test_corrections() {
+        let spelling = Spelling::parse(SPELLING).unwrap();
+        assert_eq!(spelling.len(), 5);
+        assert_eq!(spelling.correct(NAME_PLAYER, "Carlsen, M"), Some("Carlsen, Magnus"));
+        assert_eq!(spelling.correct(NAME_PLAYER, "carlsen,  m."), Some("Carlsen, Magnus"));
+        assert_eq!(spelling.correct(NAME_PLAYER, "Carlsen, Magnus"), Some("Carlsen, Magnus"));
+        assert_eq!(spelling.correct(NAME_SITE, "Wijk aan Zee"), Some("Wijk aan Zee NED"));
+        assert_eq!(spelling.correct(NAME_SITE, "Carlsen, M"), None);
+        assert_eq!(spelling.correct(NAME_PLAYER, "Caruana, F"), None);
+    }
+
+    #[test]
+    fn test_player_info() {
+        let spelling = Spelling::parse(SPELLING).unwrap();
+        let info = spelling.player("Carlsen, M.").unwrap();
+        assert_eq!(info.fide_id, Some(1503014));
+        assert_eq!(info.rating(2004), Some(2484));
+        assert_eq!(info.rating(2005), Some(2570));
+        assert_eq!(info.rating(2006), None);
+    }
+
+    #[test]
+    fn test_errors_have_lines() {
+        match Spelling::parse("@PLAYER\nCarlsen, Magnus\n%Elo 2004:x\n").map(|_| ()) {
+            Err(Error(ErrorKind::InvalidSpellingFile(line, _), _)) => assert_eq!(line, 3),
+            other => panic!("unexpected {:?}", other)
+        }
+        assert!(Spelling::parse("= Carlsen, M").is_err());
+        assert!(Spelling::parse("@PLAYERS\n").is_err());
+        assert!(Spelling::parse("@PLAYER\n= Carlsen, M").is_err());
+    }
+}
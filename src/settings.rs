@@ -0,0 +1,230 @@
+// delila - a desktop version of lila.
+//
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//--------------------------------------------------------------------------------------------------
+// Server settings.
+//
+// Settings are read from settings.toml in the settings directory, which is written with the
+// defaults the first time the server runs. Each one can be overridden for a single run by an
+// environment variable (DELILA_PORT=3013) or a command line flag (--port 3013 or --port=3013),
+// with flags taking precedence.
+//--------------------------------------------------------------------------------------------------
+
+use slog::Level;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use toml;
+
+use errors::*;
+
+const SETTINGS_FILE: &'static str = "settings.toml";
+const ENVIRONMENT_PREFIX: &'static str = "DELILA_";
+const NAMES: [&'static str; 8] = [
+    "bind_address", "port", "database_path", "log_level", "workers", "allowed_origins", "eco_path",
+    "spelling_path"
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Settings {
+    pub bind_address: String,
+    pub port: u16,
+    // Defaults to delila.db in the user's data directory.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub database_path: Option<PathBuf>,
+    pub log_level: String,
+    // The number of requests that can run at once. 0 runs one per CPU.
+    pub workers: usize,
+    // The web pages that may connect, as scheme://host. Overrides are separated by commas.
+    pub allowed_origins: Vec<String>,
+    // The opening file games are classified with. Defaults to scid.eco in the settings directory.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eco_path: Option<PathBuf>,
+    // The spelling file names are corrected with. Defaults to spelling.ssp in the settings directory.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spelling_path: Option<PathBuf>,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings{
+            bind_address: "127.0.0.1".into(),
+            port: 3012,
+            database_path: None,
+            log_level: "info".into(),
+            workers: 0,
+            allowed_origins: vec!["file://".into(), "http://localhost".into(), "http://127.0.0.1".into()],
+            eco_path: None,
+            spelling_path: None
+        }
+    }
+}
+
+fn parse_level(level: &str) -> Option<Level> {
+    match level.to_lowercase().as_str() {
+        "critical" => Some(Level::Critical),
+        "error" => Some(Level::Error),
+        "warning" | "warn" => Some(Level::Warning),
+        "info" => Some(Level::Info),
+        "debug" => Some(Level::Debug),
+        "trace" => Some(Level::Trace),
+        _ => None
+    }
+}
+
+impl Settings {
+    pub fn load(directory: &Path) -> Result<Settings> {
+        let path = directory.join(SETTINGS_FILE);
+        if !path.exists() {
+            let settings = Settings::default();
+            settings.save(directory)?;
+            return Ok(settings);
+        }
+        let mut text = String::new();
+        fs::File::open(&path)?.read_to_string(&mut text)?;
+        toml::from_str(&text).chain_err(|| format!("Unable to read the settings in {}", path.display()))
+    }
+
+    pub fn save(&self, directory: &Path) -> Result<()> {
+        let text = toml::to_string(self).chain_err(|| "Unable to write the settings")?;
+        fs::File::create(directory.join(SETTINGS_FILE))?.write_all(text.as_bytes())?;
+        Ok(())
+    }
+
+    fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        let invalid = || ErrorKind::InvalidSetting(name.into(), value.into());
+        match name {
+            "bind_address" => self.bind_address = value.into(),
+            "port" => self.port = value.parse().chain_err(invalid)?,
+            "database_path" => self.database_path = Some(PathBuf::from(value)),
+            "log_level" => {
+                if parse_level(value).is_none() {
+                    bail!(invalid());
+                }
+                self.log_level = value.into();
+            },
+            "workers" => self.workers = value.parse().chain_err(invalid)?,
+            "allowed_origins" => {
+                self.allowed_origins = value.split(',')
+                    .map(|origin| origin.trim().to_string())
+                    .filter(|origin| !origin.is_empty())
+                    .collect();
+            },
+            "eco_path" => self.eco_path = Some(PathBuf::from(value)),
+            "spelling_path" => self.spelling_path = Some(PathBuf::from(value)),
+            _ => bail!(ErrorKind::UnknownSetting(name.into()))
+        }
+        Ok(())
+    }
+
+    // Applies the DELILA_ variables, leaving any others alone.
+    pub fn apply_environment<I>(&mut self, variables: I) -> Result<()>
+        where I: IntoIterator<Item=(String, String)>
+    {
+        for (key, value) in variables {
+            if !key.starts_with(ENVIRONMENT_PREFIX) {
+                continue;
+            }
+            let name = key[ENVIRONMENT_PREFIX.len()..].to_lowercase();
+            if NAMES.contains(&name.as_str()) {
+                self.set(&name, &value)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Applies command line flags, without the program name.
+    pub fn apply_arguments<I>(&mut self, arguments: I) -> Result<()>
+        where I: IntoIterator<Item=String>
+    {
+        let mut arguments = arguments.into_iter();
+        while let Some(argument) = arguments.next() {
+            if !argument.starts_with("--") {
+                bail!(ErrorKind::UnknownSetting(argument));
+            }
+            let (name, value) = match argument.find('=') {
+                Some(index) => (argument[2..index].to_string(), argument[index + 1..].to_string()),
+                None => {
+                    let value = arguments.next()
+                        .ok_or_else(|| ErrorKind::InvalidSetting(argument[2..].into(), "".into()))?;
+                    (argument[2..].to_string(), value)
+                }
+            };
+            self.set(&name.replace('-', "_"), &value)?;
+        }
+        Ok(())
+    }
+
+    pub fn listen_address(&self) -> String {
+        format!("{}:{}", self.bind_address, self.port)
+    }
+
+    pub fn log_level(&self) -> Result<Level> {
+        parse_level(&self.log_level)
+            .ok_or_else(|| ErrorKind::InvalidSetting("log_level".into(), self.log_level.clone()).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn test_overrides() {
+        let mut settings = Settings::default();
+        settings.apply_environment(vec![
+            ("DELILA_PORT".to_string(), "4000".to_string()),
+            ("DELILA_WORKERS".to_string(), "2".to_string()),
+            ("DELILA_HOME".to_string(), "ignored".to_string()),
+            ("PORT".to_string(), "ignored".to_string()),
+        ]).unwrap();
+        settings.apply_arguments(strings(&["--port", "4001", "--log-level=debug", "--database-path", "/tmp/a.db"])).unwrap();
+        assert_eq!(settings.port, 4001);
+        assert_eq!(settings.workers, 2);
+        assert_eq!(settings.log_level().unwrap(), Level::Debug);
+        assert_eq!(settings.database_path, Some(PathBuf::from("/tmp/a.db")));
+        assert_eq!(settings.listen_address(), "127.0.0.1:4001");
+        settings.apply_arguments(strings(&["--allowed-origins", "file://, http://localhost"])).unwrap();
+        assert_eq!(settings.allowed_origins, strings(&["file://", "http://localhost"]));
+    }
+
+    #[test]
+    fn test_rejects_bad_values() {
+        let mut settings = Settings::default();
+        assert!(settings.apply_arguments(strings(&["--port", "lots"])).is_err());
+        assert!(settings.apply_arguments(strings(&["--log-level", "loud"])).is_err());
+        assert!(settings.apply_arguments(strings(&["--colour", "blue"])).is_err());
+        assert!(settings.apply_arguments(strings(&["--workers"])).is_err());
+        assert!(settings.apply_arguments(strings(&["3012"])).is_err());
+        assert_eq!(settings, Settings::default());
+    }
+
+    #[test]
+    fn test_file_round_trip() {
+        let mut settings = Settings::default();
+        settings.port = 5000;
+        settings.database_path = Some(PathBuf::from("/data/chess.db"));
+        let text = toml::to_string(&settings).unwrap();
+        assert_eq!(toml::from_str::<Settings>(&text).unwrap(), settings);
+        assert_eq!(toml::from_str::<Settings>("port = 5001").unwrap().bind_address, "127.0.0.1");
+    }
+}
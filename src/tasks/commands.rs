@@ -0,0 +1,140 @@
+// delila - a desktop version of lila.
+//
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+//--------------------------------------------------------------------------------------------------
+// The commands that the server answers to.
+//
+// Each command is declared once, with the handler that runs it, the type of its arguments and the
+// type of its response, and of its progress messages if it sends any. The handler has to take and
+// return those types, so they can't drift apart, and all of them are described for clients by
+// commands::list.
+//--------------------------------------------------------------------------------------------------
+
+use serde_json::Value;
+use std::sync::Arc;
+
+use super::{database, explorer, export, import, initialize, maintenance, search, task, variations};
+use super::describe::schema_of;
+use super::{JSONDispatch, ProgressDispatch, Request, RequestDispatch};
+use ::errors::*;
+
+pub struct Command {
+    pub name: &'static str,
+    pub dispatch: Arc<RequestDispatch + Send + Sync>,
+    pub args: fn() -> Value,
+    pub response: fn() -> Value,
+    // Only for commands that report their progress.
+    pub progress: Option<fn() -> Value>,
+}
+
+macro_rules! dispatch {
+    ($handler:path, $args:ty, $response:ty) => (
+        Arc::new(JSONDispatch::<$args, $response>{handler: Arc::new($handler)})
+    );
+    ($handler:path, $args:ty, $response:ty, $progress:ty) => (
+        Arc::new(ProgressDispatch::<$args, $response, $progress>{handler: Arc::new($handler)})
+    );
+}
+
+macro_rules! progress_schema {
+    () => (None);
+    ($progress:ty) => (Some(schema_of::<$progress>));
+}
+
+macro_rules! commands {
+    ($($name:expr => $module:ident::$handler:ident($args:ty) -> $response:ty $(, progress $progress:ty)*;)*) => (
+        pub fn all() -> Vec<Command> {
+            vec![$(
+                Command{
+                    name: $name,
+                    dispatch: dispatch!($module::$handler, $args, $response $(, $progress)*),
+                    args: schema_of::<$args>,
+                    response: schema_of::<$response>,
+                    progress: progress_schema!($($progress)*)
+                },
+            )*]
+        }
+    )
+}
+
+commands! {
+    "initialize::initialize" => initialize::initialize(initialize::Version) -> initialize::InitializeFinished,
+        progress initialize::InitializeProgress;
+    "database::list" => database::list(database::ListDatabases) -> database::DatabaseList;
+    "database::create" => database::create(database::DatabaseId) -> database::DatabaseInfo;
+    "database::open" => database::open(database::DatabaseId) -> database::DatabaseInfo;
+    "database::rename" => database::rename(database::Rename) -> database::DatabaseInfo;
+    "database::delete" => database::delete(database::DatabaseId) -> database::DatabaseId;
+    "import::importFile" => import::import_file(import::File) -> import::Summary,
+        progress import::Progress;
+    "import::importScid" => import::import_scid(import::File) -> import::Summary,
+        progress import::Progress;
+    "explorer::moveStatistics" => explorer::move_statistics(explorer::Position) -> explorer::PositionStatistics;
+    "explorer::ecoStatistics" => explorer::eco_statistics(explorer::EcoFilter) -> explorer::EcoStatisticsList;
+    "search::byPosition" => search::by_position(search::PositionQuery) -> search::GamePage;
+    "search::byMaterial" => search::by_material(search::MaterialQuery) -> search::MaterialPage;
+    "search::games" => search::games(search::GameFilter) -> search::GamePage;
+    "export::pgn" => export::pgn(export::PgnExport) -> export::Summary,
+        progress import::Progress;
+    "variations::tree" => variations::tree(variations::GameRef) -> variations::Tree;
+    "variations::add" => variations::add(variations::NewVariation) -> variations::Tree;
+    "variations::promote" => variations::promote(variations::LineRef) -> variations::Tree;
+    "variations::delete" => variations::delete(variations::LineRef) -> variations::Tree;
+    "maintenance::findDuplicates" => maintenance::find_duplicates(maintenance::FindDuplicates) -> maintenance::DuplicateGroups,
+        progress import::Progress;
+    "maintenance::mergeDuplicates" => maintenance::merge_duplicates(maintenance::Merge) -> maintenance::Merged;
+    "maintenance::rename" => maintenance::rename(maintenance::Rename) -> maintenance::Renamed;
+    "maintenance::applySpelling" => maintenance::apply_spelling(maintenance::ApplySpelling) -> maintenance::SpellingApplied,
+        progress import::Progress;
+    "task::cancel" => task::cancel(task::TaskId) -> task::Cancellation;
+    "task::list" => task::list(task::TaskFilter) -> task::TaskList;
+    "task::status" => task::status(task::TaskId) -> task::TaskStatusReply;
+    "task::resume" => task::resume(task::Session) -> task::TaskList;
+    "commands::list" => self::list(ListCommands) -> CommandList;
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ListCommands {}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CommandDescription {
+    pub name: String,
+    pub args: Value,
+    pub response: Value,
+    pub progress: Option<Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CommandList {
+    pub commands: Vec<CommandDescription>,
+}
+
+pub fn describe() -> CommandList {
+    CommandList{
+        commands: all().into_iter().map(|command| CommandDescription{
+            name: command.name.into(),
+            args: (command.args)(),
+            response: (command.response)(),
+            progress: command.progress.map(|progress| progress())
+        }).collect()
+    }
+}
+
+pub fn list(_request: &Request, _args: ListCommands) -> Result<CommandList> {
+    Ok(describe())
+}
@@ -0,0 +1,170 @@
+// delila - a desktop version of lila.
+//
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+//--------------------------------------------------------------------------------------------------
+// Requests for managing the named game databases, each of which is a <id>.db file in the dbs
+// directory.
+//--------------------------------------------------------------------------------------------------
+
+use std::fs;
+use std::path::Path;
+
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use diesel_migrations::{run_pending_migrations, setup_database};
+
+use super::super::pathsettings::{PathSettings, DEFAULT_DATABASE, valid_database_id};
+use super::Request;
+use ::errors::*;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ListDatabases {}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DatabaseId {
+    pub id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Rename {
+    pub id: String,
+    pub new_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DatabaseInfo {
+    pub id: String,
+    // The size of the file, in bytes.
+    pub size: u64,
+    // Whether requests that don't name a database use this one.
+    pub default: bool,
+    // Whether this connection opened it with database::open.
+    pub open: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DatabaseList {
+    pub databases: Vec<DatabaseInfo>,
+}
+
+// Connects to an existing database. Requests never create one by naming it.
+pub fn connect(path_settings: &PathSettings, id: Option<&str>) -> Result<SqliteConnection> {
+    let path = path_settings.database(id)?;
+    if !path.exists() {
+        bail!(ErrorKind::UnknownDatabase(id.unwrap_or(DEFAULT_DATABASE).into()));
+    }
+    let url = path.to_str().ok_or_else(|| Error::from("The database path isn't valid UTF-8"))?;
+    SqliteConnection::establish(url).chain_err(|| format!("Error connecting to {}", url))
+}
+
+fn is_default(request: &Request, path: &Path) -> bool {
+    path == request.path_settings.database_path.as_path()
+}
+
+fn database_info(request: &Request, id: &str) -> Result<DatabaseInfo> {
+    let path = request.path_settings.database(Some(id))?;
+    let metadata = fs::metadata(&path).chain_err(|| ErrorKind::UnknownDatabase(id.into()))?;
+    Ok(DatabaseInfo{
+        id: id.into(),
+        size: metadata.len(),
+        default: is_default(request, &path),
+        open: request.connection.database().as_ref().map(|open| open.as_str()) == Some(id)
+    })
+}
+
+// The default database is created at startup and used when nothing else is named, so it can't
+// be renamed or deleted out from under the server.
+fn check_not_default(request: &Request, id: &str) -> Result<()> {
+    if is_default(request, &request.path_settings.database(Some(id))?) {
+        bail!("The default database can't be renamed or deleted");
+    }
+    Ok(())
+}
+
+pub fn list(request: &Request, _args: ListDatabases) -> Result<DatabaseList> {
+    let entries = fs::read_dir(&request.path_settings.databases_path)
+        .chain_err(|| "Unable to read the database directory")?;
+    let mut databases = Vec::new();
+    for entry in entries {
+        let path = entry.chain_err(|| "Unable to read the database directory")?.path();
+        if path.extension().map_or(true, |extension| extension != "db") {
+            continue;
+        }
+        let id = match path.file_stem().and_then(|stem| stem.to_str()) {
+            Some(id) if valid_database_id(id) => id.to_string(),
+            _ => continue
+        };
+        databases.push(database_info(request, &id)?);
+    }
+    databases.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(DatabaseList{databases: databases})
+}
+
+pub fn create(request: &Request, args: DatabaseId) -> Result<DatabaseInfo> {
+    let path = request.path_settings.database(Some(&args.id))?;
+    if path.exists() {
+        bail!(ErrorKind::DatabaseExists(args.id));
+    }
+    let url = path.to_str().ok_or_else(|| Error::from("The database path isn't valid UTF-8"))?;
+    let conn = SqliteConnection::establish(url).chain_err(|| format!("Error connecting to {}", url))?;
+    setup_database(&conn).chain_err(|| "Unable to setup the database")?;
+    run_pending_migrations(&conn).chain_err(|| "Unable to run database migrations")?;
+    info!(request.log, "Created database {}", args.id);
+    database_info(request, &args.id)
+}
+
+// Makes the database the one used by this connection's requests that don't name one, bringing
+// its tables up to date first.
+pub fn open(request: &Request, args: DatabaseId) -> Result<DatabaseInfo> {
+    let conn = connect(&request.path_settings, Some(&args.id))?;
+    run_pending_migrations(&conn).chain_err(|| "Unable to run database migrations")?;
+    request.connection.set_database(Some(args.id.clone()));
+    database_info(request, &args.id)
+}
+
+pub fn rename(request: &Request, args: Rename) -> Result<DatabaseInfo> {
+    check_not_default(request, &args.id)?;
+    let from = request.path_settings.database(Some(&args.id))?;
+    let to = request.path_settings.database(Some(&args.new_id))?;
+    if !from.exists() {
+        bail!(ErrorKind::UnknownDatabase(args.id));
+    }
+    if to.exists() {
+        bail!(ErrorKind::DatabaseExists(args.new_id));
+    }
+    fs::rename(&from, &to).chain_err(|| format!("Unable to rename database {}", args.id))?;
+    if request.connection.database().as_ref() == Some(&args.id) {
+        request.connection.set_database(Some(args.new_id.clone()));
+    }
+    info!(request.log, "Renamed database {} to {}", args.id, args.new_id);
+    database_info(request, &args.new_id)
+}
+
+pub fn delete(request: &Request, args: DatabaseId) -> Result<DatabaseId> {
+    check_not_default(request, &args.id)?;
+    let path = request.path_settings.database(Some(&args.id))?;
+    if !path.exists() {
+        bail!(ErrorKind::UnknownDatabase(args.id));
+    }
+    fs::remove_file(&path).chain_err(|| format!("Unable to delete database {}", args.id))?;
+    if request.connection.database().as_ref() == Some(&args.id) {
+        request.connection.set_database(None);
+    }
+    info!(request.log, "Deleted database {}", args.id);
+    Ok(args)
+}
@@ -0,0 +1,363 @@
+// delila - a desktop version of lila.
+//
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+//--------------------------------------------------------------------------------------------------
+// Describes the JSON that a type is written as, in the style of JSON Schema.
+//
+// There's no way to ask serde for the shape of a type, so instead the type is deserialized from a
+// tracer that makes up a value for each thing the type asks for, and notes down what was asked.
+// Sequences are traced with a single element and then left empty, which is what lets recursive
+// types (a variation tree, say) finish: the second time a struct is reached inside itself, it is
+// written as a reference to its name and the element is dropped.
+//--------------------------------------------------------------------------------------------------
+
+use serde::de::{self, Deserialize, DeserializeSeed, IntoDeserializer, Visitor};
+use serde_json::{Map, Value};
+use std::error;
+use std::fmt;
+
+#[derive(Debug)]
+pub struct TraceError(String);
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl error::Error for TraceError {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+
+impl de::Error for TraceError {
+    fn custom<T: fmt::Display>(message: T) -> TraceError {
+        TraceError(message.to_string())
+    }
+}
+
+fn schema(pairs: Vec<(&str, Value)>) -> Value {
+    let mut map = Map::new();
+    for (key, value) in pairs {
+        map.insert(key.into(), value);
+    }
+    Value::Object(map)
+}
+
+fn typed(name: &str) -> Value {
+    schema(vec![("type", Value::String(name.into()))])
+}
+
+// Strings are given a timestamp, so that types which parse their strings (like dates) still work.
+const SAMPLE_STRING: &'static str = "1970-01-01T00:00:00Z";
+
+struct Tracer<'a> {
+    out: &'a mut Value,
+    // The structs that are being traced, outermost first.
+    stack: &'a mut Vec<&'static str>,
+}
+
+macro_rules! primitive {
+    ($method:ident, $visit:ident, $value:expr, $type:expr) => (
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+            *self.out = typed($type);
+            visitor.$visit($value)
+        }
+    )
+}
+
+impl<'de, 'a> de::Deserializer<'de> for Tracer<'a> {
+    type Error = TraceError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        // Anything at all.
+        *self.out = Value::Object(Map::new());
+        visitor.visit_unit()
+    }
+
+    primitive!(deserialize_bool, visit_bool, false, "boolean");
+    primitive!(deserialize_i8, visit_i8, 0, "integer");
+    primitive!(deserialize_i16, visit_i16, 0, "integer");
+    primitive!(deserialize_i32, visit_i32, 0, "integer");
+    primitive!(deserialize_i64, visit_i64, 0, "integer");
+    primitive!(deserialize_u8, visit_u8, 0, "integer");
+    primitive!(deserialize_u16, visit_u16, 0, "integer");
+    primitive!(deserialize_u32, visit_u32, 0, "integer");
+    primitive!(deserialize_u64, visit_u64, 0, "integer");
+    primitive!(deserialize_f32, visit_f32, 0.0, "number");
+    primitive!(deserialize_f64, visit_f64, 0.0, "number");
+    primitive!(deserialize_char, visit_char, 'a', "string");
+    primitive!(deserialize_str, visit_str, SAMPLE_STRING, "string");
+    primitive!(deserialize_string, visit_str, SAMPLE_STRING, "string");
+    primitive!(deserialize_identifier, visit_str, SAMPLE_STRING, "string");
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        *self.out = typed("null");
+        visitor.visit_unit()
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        *self.out = schema(vec![("type", Value::String("array".into())), ("items", typed("integer"))]);
+        visitor.visit_bytes(&[])
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        let mut inner = Value::Null;
+        let result = visitor.visit_some(Tracer{out: &mut inner, stack: &mut *self.stack});
+        *self.out = schema(vec![("anyOf", Value::Array(vec![inner, typed("null")]))]);
+        result
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, TraceError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        let mut items = Value::Null;
+        let result = visitor.visit_seq(Element{items: &mut items, stack: &mut *self.stack, traced: false});
+        *self.out = schema(vec![("type", Value::String("array".into())), ("items", items)]);
+        result
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, TraceError> {
+        let mut items = Vec::with_capacity(len);
+        let result = visitor.visit_seq(TupleElements{items: &mut items, stack: &mut *self.stack, len: len});
+        *self.out = schema(vec![("type", Value::String("array".into())), ("items", Value::Array(items))]);
+        result
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, len: usize, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        *self.out = typed("object");
+        visitor.visit_map(Fields{fields: &[], index: 0, properties: &mut Map::new(), stack: &mut *self.stack})
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V
+    ) -> Result<V::Value, TraceError> {
+        if self.stack.contains(&name) {
+            *self.out = schema(vec![("$ref", Value::String(name.into()))]);
+            return Err(de::Error::custom(format!("{} contains itself", name)));
+        }
+        let mut properties = Map::new();
+        self.stack.push(name);
+        let result = visitor.visit_map(Fields{
+            fields: fields,
+            index: 0,
+            properties: &mut properties,
+            stack: &mut *self.stack
+        });
+        self.stack.pop();
+        *self.out = schema(vec![
+            ("title", Value::String(name.into())),
+            ("type", Value::String("object".into())),
+            ("properties", Value::Object(properties))
+        ]);
+        result
+    }
+
+    // Only enums without data are supported, as those are the only ones the protocol uses.
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V
+    ) -> Result<V::Value, TraceError> {
+        *self.out = schema(vec![
+            ("title", Value::String(name.into())),
+            ("enum", Value::Array(variants.iter().map(|variant| Value::String((*variant).into())).collect()))
+        ]);
+        match variants.first() {
+            Some(variant) => visitor.visit_enum(Variant(variant)),
+            None => Err(de::Error::custom(format!("{} has no variants", name)))
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        visitor.visit_unit()
+    }
+}
+
+// Traces the first element of a sequence, and then ends it.
+struct Element<'a> {
+    items: &'a mut Value,
+    stack: &'a mut Vec<&'static str>,
+    traced: bool,
+}
+
+impl<'de, 'a> de::SeqAccess<'de> for Element<'a> {
+    type Error = TraceError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, TraceError> {
+        if !self.traced {
+            self.traced = true;
+            // The element is only traced for its schema, so it doesn't matter if it fails.
+            let _ = seed.deserialize(Tracer{out: &mut *self.items, stack: &mut *self.stack});
+        }
+        Ok(None)
+    }
+}
+
+struct TupleElements<'a> {
+    items: &'a mut Vec<Value>,
+    stack: &'a mut Vec<&'static str>,
+    len: usize,
+}
+
+impl<'de, 'a> de::SeqAccess<'de> for TupleElements<'a> {
+    type Error = TraceError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, TraceError> {
+        if self.items.len() == self.len {
+            return Ok(None);
+        }
+        let mut item = Value::Null;
+        let result = seed.deserialize(Tracer{out: &mut item, stack: &mut *self.stack});
+        self.items.push(item);
+        result.map(Some)
+    }
+}
+
+struct Fields<'a> {
+    fields: &'static [&'static str],
+    index: usize,
+    properties: &'a mut Map<String, Value>,
+    stack: &'a mut Vec<&'static str>,
+}
+
+impl<'de, 'a> de::MapAccess<'de> for Fields<'a> {
+    type Error = TraceError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, TraceError> {
+        match self.fields.get(self.index) {
+            Some(field) => seed.deserialize(field.into_deserializer()).map(Some),
+            None => Ok(None)
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, TraceError> {
+        let field = self.fields[self.index];
+        self.index += 1;
+        let mut property = Value::Null;
+        let result = seed.deserialize(Tracer{out: &mut property, stack: &mut *self.stack});
+        self.properties.insert(field.into(), property);
+        result
+    }
+}
+
+struct Variant(&'static str);
+
+impl<'de> de::EnumAccess<'de> for Variant {
+    type Error = TraceError;
+    type Variant = Variant;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Variant), TraceError> {
+        let value = seed.deserialize(self.0.into_deserializer())?;
+        Ok((value, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Variant {
+    type Error = TraceError;
+
+    fn unit_variant(self) -> Result<(), TraceError> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, _seed: T) -> Result<T::Value, TraceError> {
+        Err(de::Error::custom(format!("{} carries data", self.0)))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, _visitor: V) -> Result<V::Value, TraceError> {
+        Err(de::Error::custom(format!("{} carries data", self.0)))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], _visitor: V) -> Result<V::Value, TraceError> {
+        Err(de::Error::custom(format!("{} carries data", self.0)))
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// schema_of():
+//      Describes the JSON form of T. Types that can't be traced all the way through still get as
+//      much of a description as was found.
+//
+pub fn schema_of<T>() -> Value where for<'de> T: Deserialize<'de> {
+    let mut out = Value::Null;
+    let mut stack = Vec::new();
+    let _ = T::deserialize(Tracer{out: &mut out, stack: &mut stack});
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    #[serde(rename_all = "snake_case")]
+    enum Colour {
+        White,
+        Black,
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct Node {
+        name: String,
+        rating: Option<i32>,
+        colour: Colour,
+        children: Vec<Node>,
+    }
+
+    #[test]
+    fn test_describes_structs() {
+        let node = schema_of::<Node>();
+        assert_eq!(node["title"], "Node");
+        assert_eq!(node["properties"]["name"]["type"], "string");
+        assert_eq!(node["properties"]["rating"]["anyOf"][0]["type"], "integer");
+        assert_eq!(node["properties"]["rating"]["anyOf"][1]["type"], "null");
+        assert_eq!(node["properties"]["colour"]["enum"][1], "black");
+        assert_eq!(node["properties"]["children"]["type"], "array");
+        assert_eq!(node["properties"]["children"]["items"]["$ref"], "Node");
+    }
+
+    #[test]
+    fn test_describes_primitives() {
+        assert_eq!(schema_of::<bool>()["type"], "boolean");
+        assert_eq!(schema_of::<f32>()["type"], "number");
+        assert_eq!(schema_of::<Vec<String>>()["items"]["type"], "string");
+        assert_eq!(schema_of::<(i32, String)>()["items"][1]["type"], "string");
+        assert_eq!(schema_of::<Value>(), Value::Object(Map::new()));
+    }
+}
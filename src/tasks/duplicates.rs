@@ -0,0 +1,154 @@
+// delila - a desktop version of lila.
+//
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//--------------------------------------------------------------------------------------------------
+// Duplicate games.
+//
+// The same game often arrives more than once: from overlapping TWIC issues, or from a broadcast
+// that was imported while it was still being played. Two games between the same players are
+// duplicates when their dates and results agree, allowing for parts that one of them doesn't know,
+// and one main line starts with the other. Ratings, events, rounds and annotations may differ.
+//--------------------------------------------------------------------------------------------------
+
+use std::cmp::Ordering;
+
+// A game that is shorter than another is only taken as a truncated copy of it if it has at least
+// this many plies, so that games that merely share an opening aren't merged.
+pub const MIN_SHARED_PLIES: usize = 20;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub id: i32,
+    pub date: String,
+    pub result: String,
+    // The ids of the main line's moves, in order. A move is stored once for each position it's
+    // played from, so games with the same ids have the same moves from the same start.
+    pub moves: Vec<i32>,
+    // The length of the game's PGN, which grows with its tags, comments and variations.
+    pub text_length: usize,
+}
+
+// PGN dates write unknown parts as '?', and may leave them off altogether, either of which matches
+// anything.
+pub fn dates_match(a: &str, b: &str) -> bool {
+    a.chars().zip(b.chars()).all(|(x, y)| x == y || x == '?' || y == '?')
+}
+
+// An unfinished game ("*") may have ended either way.
+pub fn results_match(a: &str, b: &str) -> bool {
+    a == b || a == "*" || b == "*"
+}
+
+pub fn moves_match(a: &[i32], b: &[i32]) -> bool {
+    let (shorter, longer) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    longer.starts_with(shorter) && (shorter.len() == longer.len() || shorter.len() >= MIN_SHARED_PLIES)
+}
+
+pub fn is_duplicate(a: &Candidate, b: &Candidate) -> bool {
+    dates_match(&a.date, &b.date) && results_match(&a.result, &b.result) && moves_match(&a.moves, &b.moves)
+}
+
+fn known_date_parts(date: &str) -> usize {
+    date.chars().filter(|&c| c != '?').count()
+}
+
+//--------------------------------------------------------------------------------------------------
+// more_complete():
+//      Orders the copies of a game from most to least complete: the one with the most moves, then
+//      a finished one, then the one with the most of its date, then the longest PGN. The copy that
+//      was imported first wins what's left.
+//
+pub fn more_complete(a: &Candidate, b: &Candidate) -> Ordering {
+    b.moves.len().cmp(&a.moves.len())
+        .then_with(|| (b.result != "*").cmp(&(a.result != "*")))
+        .then_with(|| known_date_parts(&b.date).cmp(&known_date_parts(&a.date)))
+        .then_with(|| b.text_length.cmp(&a.text_length))
+        .then_with(|| a.id.cmp(&b.id))
+}
+
+//--------------------------------------------------------------------------------------------------
+// group():
+//      Sorts the games into groups of copies of the same game, with the most complete copy first
+//      in each group. Only groups with more than one game are returned. A game only joins a group
+//      if it's a duplicate of every game in it, so that a vague date can't join two games that
+//      were played on different days.
+//
+pub fn group(mut candidates: Vec<Candidate>) -> Vec<Vec<Candidate>> {
+    candidates.sort_by(more_complete);
+    let mut groups: Vec<Vec<Candidate>> = Vec::new();
+    for candidate in candidates {
+        match groups.iter().position(|group| group.iter().all(|other| is_duplicate(other, &candidate))) {
+            Some(index) => groups[index].push(candidate),
+            None => groups.push(vec![candidate])
+        }
+    }
+    groups.retain(|group| group.len() > 1);
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(id: i32, date: &str, result: &str, plies: i32) -> Candidate {
+        Candidate{id: id, date: date.into(), result: result.into(), moves: (1..plies + 1).collect(), text_length: 100}
+    }
+
+    #[test]
+    fn test_matching() {
+        assert!(dates_match("2017.05.03", "2017.05.03"));
+        assert!(dates_match("2017.??.??", "2017.05.03"));
+        assert!(dates_match("2017", "2017.05.03"));
+        assert!(!dates_match("2017.05.04", "2017.05.03"));
+        assert!(results_match("*", "1-0"));
+        assert!(!results_match("0-1", "1-0"));
+        let long: Vec<i32> = (1..41).collect();
+        assert!(moves_match(&long[..30], &long));
+        assert!(!moves_match(&long[..10], &long));
+        assert!(moves_match(&[], &[]));
+        assert!(!moves_match(&[1, 2], &[1, 3]));
+    }
+
+    #[test]
+    fn test_groups_keep_the_most_complete_first() {
+        let mut annotated = candidate(4, "2017.05.03", "1-0", 60);
+        annotated.text_length = 900;
+        let groups = group(vec![
+            candidate(1, "2017.05.03", "*", 40),
+            candidate(2, "2017.05.03", "1-0", 60),
+            candidate(3, "2017.??.??", "1-0", 60),
+            annotated,
+            // Same players, another day.
+            candidate(5, "2017.05.04", "1-0", 60),
+        ]);
+        assert_eq!(groups.len(), 1);
+        let ids: Vec<i32> = groups[0].iter().map(|c| c.id).collect();
+        assert_eq!(ids, vec![4, 2, 3, 1]);
+    }
+
+    #[test]
+    fn test_vague_dates_join_one_group() {
+        let groups = group(vec![
+            candidate(1, "2017.05.03", "1-0", 30),
+            candidate(2, "2017.05.04", "1-0", 30),
+            candidate(3, "????.??.??", "1-0", 30),
+        ]);
+        assert_eq!(groups.len(), 1);
+        let ids: Vec<i32> = groups[0].iter().map(|c| c.id).collect();
+        assert_eq!(ids, vec![1, 3]);
+    }
+}
@@ -0,0 +1,287 @@
+// delila - a desktop version of lila.
+//
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+//--------------------------------------------------------------------------------------------------
+// Request handlers for the opening explorer: what was played from a position, and how it went, and
+// how each opening went.
+//--------------------------------------------------------------------------------------------------
+
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::{BigInt, Integer, Nullable, Text};
+use diesel::sqlite::SqliteConnection;
+use slog;
+
+use super::super::board::Board;
+use super::search::{escape_like, find_position};
+
+use super::Request;
+use ::errors::*;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Position {
+    pub fen: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MoveStatistics {
+    pub uci: String,
+    pub san: String,
+    pub games: i64,
+    pub white_percent: f32,
+    pub draw_percent: f32,
+    pub black_percent: f32,
+    // The average rating of the rated players in these games.
+    pub average_rating: Option<i32>,
+    pub last_played: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PositionStatistics {
+    pub fen: String,
+    pub games: i64,
+    pub moves: Vec<MoveStatistics>,
+}
+
+#[derive(QueryableByName)]
+struct MoveRow {
+    #[sql_type = "Text"] uci: String,
+    #[sql_type = "BigInt"] games: i64,
+    #[sql_type = "BigInt"] white_wins: i64,
+    #[sql_type = "BigInt"] draws: i64,
+    #[sql_type = "BigInt"] black_wins: i64,
+    #[sql_type = "BigInt"] rating_sum: i64,
+    #[sql_type = "BigInt"] rated_players: i64,
+    #[sql_type = "Nullable<Text>"] last_played: Option<String>,
+}
+
+// Each game is only counted once per move, even if it repeats the position. Dates with an unknown
+// year start with '?', which sorts after the digits, so they're left out of the last played date.
+const MOVE_STATISTICS: &'static str = "
+    SELECT x.uci AS uci,
+           COUNT(*) AS games,
+           SUM(CASE WHEN g.result = '1-0' THEN 1 ELSE 0 END) AS white_wins,
+           SUM(CASE WHEN g.result = '1/2-1/2' THEN 1 ELSE 0 END) AS draws,
+           SUM(CASE WHEN g.result = '0-1' THEN 1 ELSE 0 END) AS black_wins,
+           SUM(CASE WHEN g.white_player_rating > 0 THEN g.white_player_rating ELSE 0 END)
+             + SUM(CASE WHEN g.black_player_rating > 0 THEN g.black_player_rating ELSE 0 END)
+             AS rating_sum,
+           SUM(CASE WHEN g.white_player_rating > 0 THEN 1 ELSE 0 END)
+             + SUM(CASE WHEN g.black_player_rating > 0 THEN 1 ELSE 0 END)
+             AS rated_players,
+           MAX(CASE WHEN g.date LIKE '?%' THEN NULL ELSE g.date END) AS last_played
+      FROM (SELECT DISTINCT m.uci AS uci, l.game_id AS game_id
+              FROM _move m
+              JOIN line_move lm ON lm.move_id = m.id
+              JOIN line l ON l.id = lm.line_id
+             WHERE m.starting_position_id = ? AND l.parent_line_id IS NULL) x
+      JOIN game g ON g.id = x.game_id
+     GROUP BY x.uci
+     ORDER BY games DESC, x.uci";
+
+fn percent(count: i64, total: i64) -> f32 {
+    if total == 0 { 0.0 } else { count as f32 / total as f32 * 100.0 }
+}
+
+fn position_statistics(conn: &SqliteConnection, log: &slog::Logger, args: Position) -> Result<PositionStatistics> {
+    let board = Board::from_fen(&args.fen)?;
+    let rows = match find_position(conn, &args.fen)? {
+        Some(position_id) => sql_query(MOVE_STATISTICS)
+            .bind::<Integer, _>(position_id)
+            .load::<MoveRow>(conn)?,
+        None => Vec::new()
+    };
+
+    let mut moves = Vec::with_capacity(rows.len());
+    for row in rows {
+        // Hash collisions aside, every stored move is legal here, but a bad row shouldn't take
+        // the whole explorer down with it.
+        let san = match board.parse_uci(&row.uci) {
+            Ok(mv) => board.san(mv),
+            Err(_) => {
+                warn!(log, "Stored move {} is illegal in {}", row.uci, args.fen);
+                continue;
+            }
+        };
+        moves.push(MoveStatistics{
+            san: san,
+            games: row.games,
+            white_percent: percent(row.white_wins, row.games),
+            draw_percent: percent(row.draws, row.games),
+            black_percent: percent(row.black_wins, row.games),
+            average_rating: if row.rated_players > 0 {
+                Some((row.rating_sum / row.rated_players) as i32)
+            } else {
+                None
+            },
+            last_played: row.last_played,
+            uci: row.uci
+        });
+    }
+
+    Ok(PositionStatistics{
+        games: moves.iter().map(|m| m.games).sum(),
+        fen: args.fen,
+        moves: moves
+    })
+}
+
+pub fn move_statistics(request: &Request, args: Position) -> Result<PositionStatistics> {
+    let conn = request.get_connection()?;
+    position_statistics(&conn, &request.log, args)
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct EcoFilter {
+    // Only codes that start with this, e.g. "B" or "B9". All codes if it's missing.
+    pub prefix: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EcoStatistics {
+    pub eco: String,
+    pub games: i64,
+    pub white_percent: f32,
+    pub draw_percent: f32,
+    pub black_percent: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EcoStatisticsList {
+    pub games: i64,
+    pub openings: Vec<EcoStatistics>,
+}
+
+#[derive(QueryableByName)]
+struct EcoRow {
+    #[sql_type = "Text"] eco: String,
+    #[sql_type = "BigInt"] games: i64,
+    #[sql_type = "BigInt"] white_wins: i64,
+    #[sql_type = "BigInt"] draws: i64,
+    #[sql_type = "BigInt"] black_wins: i64,
+}
+
+// Games are grouped by their basic code (e.g. "B90"), leaving off SCID's extensions to it.
+const ECO_STATISTICS: &'static str = "
+    SELECT substr(g.eco, 1, 3) AS eco,
+           COUNT(*) AS games,
+           SUM(CASE WHEN g.result = '1-0' THEN 1 ELSE 0 END) AS white_wins,
+           SUM(CASE WHEN g.result = '1/2-1/2' THEN 1 ELSE 0 END) AS draws,
+           SUM(CASE WHEN g.result = '0-1' THEN 1 ELSE 0 END) AS black_wins
+      FROM game g
+     WHERE g.eco LIKE ? ESCAPE '\\'
+     GROUP BY substr(g.eco, 1, 3)
+     ORDER BY eco";
+
+fn openings(conn: &SqliteConnection, args: EcoFilter) -> Result<EcoStatisticsList> {
+    let prefix = args.prefix.unwrap_or_default();
+    let rows = sql_query(ECO_STATISTICS)
+        .bind::<Text, _>(format!("{}%", escape_like(&prefix)))
+        .load::<EcoRow>(conn)?;
+    let openings: Vec<EcoStatistics> = rows.into_iter()
+        .map(|row| EcoStatistics{
+            white_percent: percent(row.white_wins, row.games),
+            draw_percent: percent(row.draws, row.games),
+            black_percent: percent(row.black_wins, row.games),
+            games: row.games,
+            eco: row.eco
+        })
+        .collect();
+    Ok(EcoStatisticsList{
+        games: openings.iter().map(|o| o.games).sum(),
+        openings: openings
+    })
+}
+
+pub fn eco_statistics(request: &Request, args: EcoFilter) -> Result<EcoStatisticsList> {
+    let conn = request.get_connection()?;
+    openings(&conn, args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::fixture;
+
+    fn fen_after(moves: &[&str]) -> String {
+        let mut board = Board::start();
+        for san in moves {
+            let mv = board.parse_san(san).unwrap();
+            board.make(mv);
+        }
+        board.to_fen()
+    }
+
+    #[test]
+    fn test_move_statistics() {
+        let conn = fixture::database(&[
+            fixture::game(&[("Date", "2014.11.23"), ("WhiteElo", "2863"), ("BlackElo", "2792"), ("Result", "1-0")],
+                "1. e4 e5 2. Nf3 Nf6 3. Ng1 Ng8 4. Nf3 Nc6"),
+            fixture::game(&[("Date", "2013.11.22"), ("Result", "1/2-1/2")], "1. e4 c5"),
+            fixture::game(&[("Date", "????.??.??"), ("WhiteElo", "2700"), ("Result", "0-1")], "1. e4 e5"),
+            // Only a variation plays 1. e4 here, so it isn't counted.
+            fixture::game(&[("Date", "2017.??.??"), ("Result", "1-0")], "1. d4 (1. e4 e5) 1... d5"),
+        ]);
+        let statistics = position_statistics(&conn, &fixture::log(), Position{fen: fen_after(&["e4"])}).unwrap();
+        assert_eq!(statistics.games, 3);
+        assert_eq!(statistics.moves.len(), 2);
+
+        let e5 = &statistics.moves[0];
+        assert_eq!((e5.uci.as_str(), e5.san.as_str(), e5.games), ("e7e5", "e5", 2));
+        assert_eq!((e5.white_percent, e5.draw_percent, e5.black_percent), (50.0, 0.0, 50.0));
+        assert_eq!(e5.average_rating, Some((2863 + 2792 + 2700) / 3));
+        assert_eq!(e5.last_played, Some("2014.11.23".into()));
+
+        let c5 = &statistics.moves[1];
+        assert_eq!((c5.uci.as_str(), c5.san.as_str(), c5.games), ("c7c5", "c5", 1));
+        assert_eq!(c5.draw_percent, 100.0);
+        assert_eq!(c5.average_rating, None);
+        assert_eq!(c5.last_played, Some("2013.11.22".into()));
+
+        // The first game plays 2. Nf3 from here twice, but is only counted once.
+        let after_e5 = position_statistics(&conn, &fixture::log(), Position{fen: fen_after(&["e4", "e5"])}).unwrap();
+        assert_eq!(after_e5.games, 1);
+        assert_eq!(after_e5.moves[0].san, "Nf3");
+
+        let unplayed = position_statistics(&conn, &fixture::log(), Position{fen: "8/8/8/4k3/8/8/8/4K3 w - - 0 1".into()}).unwrap();
+        assert_eq!(unplayed.games, 0);
+        assert!(unplayed.moves.is_empty());
+    }
+
+    #[test]
+    fn test_eco_statistics() {
+        let conn = fixture::database(&[
+            fixture::game(&[("ECO", "B90"), ("Result", "1-0")], "1. e4 c5"),
+            fixture::game(&[("ECO", "B90a"), ("Result", "0-1")], "1. e4 c5"),
+            fixture::game(&[("ECO", "C65"), ("Result", "1/2-1/2")], "1. e4 e5"),
+            fixture::game(&[("Result", "1-0")], "1. d4"),
+        ]);
+        let codes = |prefix: Option<&str>| {
+            let list = openings(&conn, EcoFilter{prefix: prefix.map(|p| p.to_string())}).unwrap();
+            list.openings.iter().map(|o| (o.eco.clone(), o.games)).collect::<Vec<_>>()
+        };
+        assert_eq!(codes(None), vec![("B90".to_string(), 2), ("C65".to_string(), 1)]);
+        assert_eq!(codes(Some("B9")), vec![("B90".to_string(), 2)]);
+        assert_eq!(codes(Some("_90")), vec![]);
+        assert_eq!(codes(Some("%")), vec![]);
+
+        let b90 = openings(&conn, EcoFilter{prefix: Some("B".into())}).unwrap();
+        assert_eq!(b90.games, 2);
+        assert_eq!((b90.openings[0].white_percent, b90.openings[0].black_percent), (50.0, 50.0));
+    }
+}
@@ -0,0 +1,161 @@
+// delila - a desktop version of lila.
+//
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+//--------------------------------------------------------------------------------------------------
+// A request handler that writes games from the database back out as PGN.
+//--------------------------------------------------------------------------------------------------
+
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use std::fs;
+use std::io::{BufWriter, Write};
+
+use super::super::models;
+use super::super::pgn;
+use super::super::pgn::writer::SEVEN_TAG_ROSTER;
+use super::super::schema::{event, game, player, site};
+use super::import::Progress;
+use super::search::{display_name, matching_games, GameFilter};
+use super::variations::{load_tree, LineNode};
+
+use super::{ProgressChannel, Request};
+use ::errors::*;
+
+// Exactly one of game_id, game_ids and search should be given. A search exports every game that
+// it matches, not just one page of them.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PgnExport {
+    pub path: String,
+    pub game_id: Option<i32>,
+    pub game_ids: Option<Vec<i32>>,
+    pub search: Option<GameFilter>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Summary {
+    pub path: String,
+    pub exported: u32,
+}
+
+fn tag(name: &str, value: String) -> pgn::Tag {
+    pgn::Tag{name: name.into(), value: value}
+}
+
+fn player_name(conn: &SqliteConnection, id: i32) -> Result<String> {
+    let player = player::table.find(id).first::<models::Player>(conn)?;
+    let name = display_name(player.first_name, player.last_name, player.middle_name);
+    Ok(if name.is_empty() { "?".into() } else { name })
+}
+
+// The tags that load_game() fills in from the game tables.
+fn from_database(name: &str) -> bool {
+    SEVEN_TAG_ROSTER.contains(&name) || name == "WhiteElo" || name == "BlackElo"
+}
+
+fn to_pgn(node: LineNode) -> pgn::Line {
+    let mut line = pgn::Line::default();
+    line.comment = node.comment;
+    for m in node.moves {
+        let mut pgn_move = pgn::Move::new(m.san);
+        pgn_move.comment = m.comment;
+        pgn_move.nags = m.nags;
+        pgn_move.variations = m.variations.into_iter().map(to_pgn).collect();
+        line.moves.push(pgn_move);
+    }
+    line
+}
+
+//--------------------------------------------------------------------------------------------------
+// load_game():
+//      Rebuilds a game from the database. The Seven Tag Roster and the ratings come from the game
+//      tables, so that later corrections to names are picked up, while any other tags (FEN, ECO,
+//      TimeControl, ...) are kept from the original PGN. Rounds are kept from the original too,
+//      as only the main round number is stored.
+//
+pub fn load_game(conn: &SqliteConnection, id: i32) -> Result<pgn::Game> {
+    let row = game::table.find(id).first::<models::Game>(conn)?;
+    let original = pgn::parse_game(&row.pgn)?;
+
+    let event = match row.event_id {
+        Some(event_id) => event::table.find(event_id).select(event::name).first::<String>(conn)?,
+        None => "?".into()
+    };
+    let site = match row.site_id {
+        Some(site_id) => site::table.find(site_id).select(site::name).first::<String>(conn)?,
+        None => "?".into()
+    };
+    let round = original.tag("Round").map(|round| round.to_string())
+        .or_else(|| row.round.map(|round| round.to_string()))
+        .unwrap_or_else(|| "?".into());
+
+    let mut game = pgn::Game::default();
+    game.tags.push(tag("Event", event));
+    game.tags.push(tag("Site", site));
+    game.tags.push(tag("Date", row.date.clone()));
+    game.tags.push(tag("Round", round));
+    game.tags.push(tag("White", player_name(conn, row.white_player_id)?));
+    game.tags.push(tag("Black", player_name(conn, row.black_player_id)?));
+    game.tags.push(tag("Result", row.result.clone()));
+    if row.white_player_rating > 0 {
+        game.tags.push(tag("WhiteElo", row.white_player_rating.to_string()));
+    }
+    if row.black_player_rating > 0 {
+        game.tags.push(tag("BlackElo", row.black_player_rating.to_string()));
+    }
+    for original_tag in original.tags {
+        if !from_database(&original_tag.name) {
+            game.tags.push(original_tag);
+        }
+    }
+    game.result = row.result;
+
+    game.line = to_pgn(load_tree(conn, id)?);
+    Ok(game)
+}
+
+pub fn pgn(request: &Request, args: PgnExport, progress: &ProgressChannel<Progress>) -> Result<Summary> {
+    let conn = request.get_connection()?;
+    let ids = match (args.game_id, args.game_ids, args.search) {
+        (Some(game_id), None, None) => vec![game_id],
+        (None, Some(game_ids), None) => game_ids,
+        (None, None, Some(search)) => matching_games(&conn, &search)?,
+        _ => bail!("export::pgn needs exactly one of game_id, game_ids or search")
+    };
+
+    let mut out = BufWriter::new(fs::File::create(&args.path)?);
+    let mut state = Progress{activity: "Exporting games".into(), progress: 0.0};
+    progress.send(&state)?;
+
+    let mut exported = 0;
+    for (index, id) in ids.iter().enumerate() {
+        request.check_cancelled()?;
+        let game = load_game(&conn, *id).chain_err(|| format!("Unable to export game {}", id))?;
+        out.write_all(pgn::write_game(&game).as_bytes())?;
+        exported += 1;
+
+        let done = (index + 1) as f32 / ids.len() as f32 * 100.0;
+        if done - state.progress >= 1.0 {
+            state.progress = done;
+            progress.send(&state)?;
+        }
+    }
+    out.flush()?;
+
+    info!(request.log, "export::pgn wrote {} games to {}", exported, args.path);
+    Ok(Summary{path: args.path, exported: exported})
+}
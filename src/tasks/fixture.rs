@@ -0,0 +1,60 @@
+// delila - a desktop version of lila.
+//
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+//--------------------------------------------------------------------------------------------------
+// In-memory databases for testing the request handlers against.
+//--------------------------------------------------------------------------------------------------
+
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use diesel_migrations::{find_migrations_directory, run_pending_migrations_in_directory};
+use slog;
+use std::io;
+
+use super::super::pgn::parse_game;
+use super::import::{replay, GameWriter};
+
+// The text of a game with the given tags. The result is taken from the Result tag.
+pub fn game(tags: &[(&str, &str)], moves: &str) -> String {
+    let mut text = String::new();
+    for &(name, value) in tags {
+        text.push_str(&format!("[{} \"{}\"]\n", name, value));
+    }
+    let result = tags.iter().find(|&&(name, _)| name == "Result").map_or("*", |&(_, value)| value);
+    text.push_str(&format!("\n{} {}\n", moves, result));
+    text
+}
+
+// A database with the given games imported into it, in order, so the first game has id 1.
+pub fn database(games: &[String]) -> SqliteConnection {
+    let conn = SqliteConnection::establish(":memory:").unwrap();
+    let migrations = find_migrations_directory().unwrap();
+    run_pending_migrations_in_directory(&conn, &migrations, &mut io::sink()).unwrap();
+    {
+        let mut writer = GameWriter::new(&conn);
+        for text in games {
+            let game = parse_game(text).unwrap();
+            writer.write(&replay(&game).unwrap(), text).unwrap();
+        }
+    }
+    conn
+}
+
+pub fn log() -> slog::Logger {
+    slog::Logger::root(slog::Discard, o!())
+}
@@ -17,22 +17,42 @@
 
 
 //--------------------------------------------------------------------------------------------------
-// A request handler for importing a PGN file.
+// Request handlers for importing PGN files and SCID databases.
 //--------------------------------------------------------------------------------------------------
 
+use diesel;
 use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
 
+use super::super::board::{Board, Move};
+use super::super::board::zobrist::ZobristHash;
 use super::super::models::*;
-//use super::super::schema::database::dsl::*;
+use super::super::schema::{_move, event, game, line, line_move, player, position, site};
+use super::super::pgn;
+use super::super::pgn::{GameReader, parse_game, write_game};
+use super::super::scid::ScidBase;
+use super::super::scid::eco::EcoBook;
+use super::super::scid::namebase::{NameType, NAME_EVENT, NAME_PLAYER, NAME_SITE};
+use super::super::scid::spelling::Spelling;
+use super::super::scid::common::{EMPTY, QUEEN};
+use super::super::last_insert_id;
 
-use super::Request;
+use super::{ProgressChannel, Request};
 use::errors::*;
-use std::{thread, time};
+use std::collections::HashMap;
+use std::fs;
+use std::io::BufReader;
+use std::path::Path;
+
+// Games are written in batches so that SQLite doesn't have to sync to disk after every insert.
+const GAMES_PER_TRANSACTION: usize = 500;
+// The number of ids of each kind to remember before starting over, which keeps memory bounded on
+// imports of millions of games while still catching the common players and opening positions.
+const CACHE_SIZE: usize = 1_000_000;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct File {
     pub path: String
-    //target_database: u32
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -41,19 +61,594 @@ pub struct Progress {
     pub progress: f32,
 }
 
-pub fn import_file(request: &Request, args:File) -> Result<()> {
-    let conn = request.get_connection();
-    let mut state: Progress = Progress{activity: "Loading ...".into(), progress: 0.0};
-    request.send("import::updateProgress".into(), &state)?;
-    let increment = 1f32;
-    for _ in 0..100 {
-        let _0_5s = time::Duration::from_millis(50);
-        thread::sleep(_0_5s);
-        state.progress += increment;
-        info!(request.log, "import::updateProgress {}", state.progress);
-        request.send("import::updateProgress".into(), &state)?
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Summary {
+    pub imported: u32,
+    pub skipped: u32,
+}
+
+pub fn import_file(request: &Request, args: File, progress: &ProgressChannel<Progress>) -> Result<Summary> {
+    let conn = request.get_connection()?;
+    let file = fs::File::open(&args.path)
+        .chain_err(|| format!("Unable to open {}", args.path))?;
+    let size = file.metadata()?.len();
+    let mut games = GameReader::new(BufReader::new(file));
+    let mut writer = GameWriter::new(&conn)
+        .with_eco(request.eco.as_ref().map(|eco| &**eco))
+        .with_spelling(request.spelling.as_ref().map(|spelling| &**spelling));
+    let mut summary = Summary{imported: 0, skipped: 0};
+    let mut state: Progress = Progress{activity: "Importing games".into(), progress: 0.0};
+    progress.send(&state)?;
+
+    let mut finished = false;
+    while !finished {
+        // Batches that are already written stay imported.
+        request.check_cancelled()?;
+        conn.transaction::<_, Error, _>(|| {
+            for _ in 0..GAMES_PER_TRANSACTION {
+                let raw = match games.next() {
+                    Some(raw) => raw?,
+                    None => {
+                        finished = true;
+                        break;
+                    }
+                };
+                let game = match parse_game(&raw.text) {
+                    Ok(game) => game,
+                    Err(e) => {
+                        warn!(request.log, "Skipping the game at byte {}: {}", raw.start, e);
+                        summary.skipped += 1;
+                        continue;
+                    }
+                };
+                let replayed = match replay(&game) {
+                    Ok(replayed) => replayed,
+                    Err(e) => {
+                        warn!(request.log, "Skipping the game at byte {}: {}", raw.start, e);
+                        summary.skipped += 1;
+                        continue;
+                    }
+                };
+                writer.write(&replayed, &raw.text)?;
+                summary.imported += 1;
+            }
+            Ok(())
+        })?;
+        if size > 0 {
+            state.progress = games.offset() as f32 / size as f32 * 100.0;
+        }
+        info!(request.log, "import::importFile progress {}", state.progress);
+        progress.send(&state)?
+    }
+    Ok(summary)
+}
+
+// Imports a SCID base, named by any of its files. Games that SCID has marked as deleted are skipped.
+pub fn import_scid(request: &Request, args: File, progress: &ProgressChannel<Progress>) -> Result<Summary> {
+    let conn = request.get_connection()?;
+    let mut base = ScidBase::open(Path::new(&args.path))?;
+    let mut writer = GameWriter::new(&conn)
+        .with_eco(request.eco.as_ref().map(|eco| &**eco))
+        .with_spelling(request.spelling.as_ref().map(|spelling| &**spelling));
+    let mut summary = Summary{imported: 0, skipped: 0};
+    let mut state: Progress = Progress{activity: "Importing games".into(), progress: 0.0};
+    progress.send(&state)?;
+
+    let mut number = 0;
+    while number < base.len() {
+        // Batches that are already written stay imported.
+        request.check_cancelled()?;
+        let end = (number + GAMES_PER_TRANSACTION).min(base.len());
+        conn.transaction::<_, Error, _>(|| {
+            for n in number..end {
+                if base.is_deleted(n) {
+                    summary.skipped += 1;
+                    continue;
+                }
+                let game = match base.game(n) {
+                    Ok(game) => game,
+                    Err(e) => {
+                        warn!(request.log, "Skipping game {}: {}", n + 1, e);
+                        summary.skipped += 1;
+                        continue;
+                    }
+                };
+                let replayed = match replay(&game) {
+                    Ok(replayed) => replayed,
+                    Err(e) => {
+                        warn!(request.log, "Skipping game {}: {}", n + 1, e);
+                        summary.skipped += 1;
+                        continue;
+                    }
+                };
+                writer.write(&replayed, &write_game(&game))?;
+                summary.imported += 1;
+            }
+            Ok(())
+        })?;
+        number = end;
+        state.progress = number as f32 / base.len() as f32 * 100.0;
+        info!(request.log, "import::importScid progress {}", state.progress);
+        progress.send(&state)?
+    }
+    Ok(summary)
+}
+
+//--------------------------------------------------------------------------------------------------
+// A game whose moves have been replayed on a board, so that every move is known to be legal and
+// the positions each one passes through are known.
+//--------------------------------------------------------------------------------------------------
+pub struct ReplayedGame<'g> {
+    pub game: &'g pgn::Game,
+    pub line: ReplayedLine<'g>,
+    // The position at the end of the main line.
+    pub board: Board,
+    // Whether the main line has any promotions, and any to something other than a queen.
+    pub promotions: bool,
+    pub underpromotions: bool
+}
+
+pub struct ReplayedLine<'g> {
+    pub line: &'g pgn::Line,
+    pub start: ZobristHash,
+    pub first_ply: i32,
+    pub moves: Vec<ReplayedMove<'g>>
+}
+
+pub struct ReplayedMove<'g> {
+    pub source: &'g pgn::Move,
+    pub mv: Move,
+    pub start: ZobristHash,
+    pub end: ZobristHash,
+    pub variations: Vec<ReplayedLine<'g>>
+}
+
+pub fn replay(game: &pgn::Game) -> Result<ReplayedGame> {
+    let mut board = match game.tag("FEN") {
+        Some(fen) => Board::from_fen(fen)?,
+        None => Board::start()
+    };
+    let line = replay_line(&game.line, &mut board)?;
+    let mut promotions = false;
+    let mut underpromotions = false;
+    for m in &line.moves {
+        promotions = promotions || m.mv.promotion != EMPTY;
+        underpromotions = underpromotions || (m.mv.promotion != EMPTY && m.mv.promotion != QUEEN);
+        board.make(m.mv);
+    }
+    Ok(ReplayedGame{
+        game: game,
+        line: line,
+        board: board,
+        promotions: promotions,
+        underpromotions: underpromotions
+    })
+}
+
+// Replays a line and its variations, leaving the board as it was found.
+pub fn replay_line<'g>(line: &'g pgn::Line, board: &mut Board) -> Result<ReplayedLine<'g>> {
+    let mut replayed = ReplayedLine{
+        line: line,
+        start: board.hash(),
+        first_ply: board.ply() as i32 + 1,
+        moves: Vec::with_capacity(line.moves.len())
+    };
+    for m in &line.moves {
+        // Variations are alternatives to this move, so they start before it is played.
+        let mut variations = Vec::with_capacity(m.variations.len());
+        for variation in &m.variations {
+            variations.push(replay_line(variation, board)?);
+        }
+        let mv = board.parse_san(&m.san)
+            .chain_err(|| format!("Unable to play {} at ply {}", m.san, board.ply() + 1))?;
+        let start = board.hash();
+        board.make(mv);
+        replayed.moves.push(ReplayedMove{
+            source: m,
+            mv: mv,
+            start: start,
+            end: board.hash(),
+            variations: variations
+        });
+    }
+    for _ in &line.moves {
+        board.unmake();
     }
+    Ok(replayed)
+}
 
-    Ok(())
+//--------------------------------------------------------------------------------------------------
+// eco_code():
+//      The ECO code of the deepest position in the main line that the book knows, or else the
+//      code from the game's ECO tag.
+//
+pub fn eco_code(book: Option<&EcoBook>, replayed: &ReplayedGame) -> Option<String> {
+    let positions = Some(replayed.line.start).into_iter()
+        .chain(replayed.line.moves.iter().map(|m| m.end));
+    book.and_then(|book| book.classify(positions))
+        .map(|opening| opening.code.clone())
+        .or_else(|| known(replayed.game.tag("ECO")).map(|code| code.to_string()))
 }
 
+//--------------------------------------------------------------------------------------------------
+// Writes replayed games into the database. Players, events, sites and positions are shared between
+// games, so the ids of the ones we've already seen are remembered by the writer.
+//--------------------------------------------------------------------------------------------------
+pub struct GameWriter<'a> {
+    conn: &'a SqliteConnection,
+    players: HashMap<String, i32>,
+    events: HashMap<(String, i32), i32>,
+    sites: HashMap<String, i32>,
+    positions: HashMap<ZobristHash, i32>,
+    moves: HashMap<(i32, String), i32>,
+    eco: Option<&'a EcoBook>,
+    spelling: Option<&'a Spelling>,
+}
+
+impl<'a> GameWriter<'a> {
+    pub fn new(conn: &'a SqliteConnection) -> GameWriter<'a> {
+        GameWriter{
+            conn: conn,
+            players: HashMap::new(),
+            events: HashMap::new(),
+            sites: HashMap::new(),
+            positions: HashMap::new(),
+            moves: HashMap::new(),
+            eco: None,
+            spelling: None
+        }
+    }
+
+    // Classifies the games that are written with the given openings.
+    pub fn with_eco(mut self, eco: Option<&'a EcoBook>) -> GameWriter<'a> {
+        self.eco = eco;
+        self
+    }
+
+    // Corrects the names of the games that are written with the given spellings.
+    pub fn with_spelling(mut self, spelling: Option<&'a Spelling>) -> GameWriter<'a> {
+        self.spelling = spelling;
+        self
+    }
+
+    fn correct<'n>(&self, name_type: NameType, name: &'n str) -> &'n str
+        where 'a: 'n
+    {
+        self.spelling.and_then(|spelling| spelling.correct(name_type, name)).unwrap_or(name)
+    }
+
+    // The rating from the game, or else from the spelling file for the year it was played.
+    fn rating(&self, elo: Option<&str>, name: &str, date: &str) -> i32 {
+        match rating(elo) {
+            0 => self.spelling
+                .and_then(|spelling| spelling.player(name))
+                .and_then(|info| info.rating(year(date)))
+                .unwrap_or(0),
+            rating => rating
+        }
+    }
+
+    pub fn write(&mut self, replayed: &ReplayedGame, text: &str) -> Result<i32> {
+        let game = replayed.game;
+        let white = self.correct(NAME_PLAYER, game.tag("White").unwrap_or("?"));
+        let black = self.correct(NAME_PLAYER, game.tag("Black").unwrap_or("?"));
+        let white_player_id = self.player(white)?;
+        let black_player_id = self.player(black)?;
+        let date = game.tag("Date").unwrap_or("????.??.??");
+        let event_id = match known(game.tag("Event")) {
+            Some(name) => {
+                let name = self.correct(NAME_EVENT, name);
+                Some(self.event(name, year(date))?)
+            },
+            None => None
+        };
+        let site_id = match known(game.tag("Site")) {
+            Some(name) => {
+                let name = self.correct(NAME_SITE, name);
+                Some(self.site(name)?)
+            },
+            None => None
+        };
+        let eco = eco_code(self.eco, replayed);
+
+        diesel::insert_into(game::table)
+            .values(&NewGame{
+                white_player_id: white_player_id,
+                white_player_rating: self.rating(game.tag("WhiteElo"), white, date),
+                black_player_id: black_player_id,
+                black_player_rating: self.rating(game.tag("BlackElo"), black, date),
+                event_id: event_id,
+                site_id: site_id,
+                date: date,
+                round: game.tag("Round").and_then(round),
+                result: &game.result,
+                pgn: text,
+                // Filled in below, once the line exists.
+                line_id: 0,
+                matsig: replayed.board.material_signature() as i32,
+                promotions: replayed.promotions,
+                underpromotions: replayed.underpromotions,
+                eco: eco.as_ref().map(|code| code.as_str())
+            })
+            .execute(self.conn)?;
+        let game_id = last_insert_id(self.conn)?;
+
+        let line_id = self.line(game_id, &replayed.line, None)?;
+        diesel::update(game::table.find(game_id))
+            .set(game::line_id.eq(line_id))
+            .execute(self.conn)?;
+        Ok(game_id)
+    }
+
+    // Writes a line and all of its variations, returning the id of the line.
+    pub fn line(&mut self, game_id: i32, replayed: &ReplayedLine, parent: Option<(i32, i32)>) -> Result<i32> {
+        let starting_position_id = self.position(replayed.start)?;
+        diesel::insert_into(line::table)
+            .values(&NewLine{
+                starting_position_id: starting_position_id,
+                parent_line_id: parent.map(|(parent_line_id, _)| parent_line_id),
+                game_id: Some(game_id),
+                parent_ply: parent.map(|(_, parent_ply)| parent_ply),
+                comment: replayed.line.comment.as_ref().map(|c| c.as_str())
+            })
+            .execute(self.conn)?;
+        let line_id = last_insert_id(self.conn)?;
+
+        let mut line_moves = Vec::with_capacity(replayed.moves.len());
+        for (index, m) in replayed.moves.iter().enumerate() {
+            line_moves.push(NewLineMove{
+                move_id: self.move_id(&m.mv.uci(), m.start, m.end)?,
+                line_id: line_id,
+                ply: replayed.first_ply + index as i32,
+                san: &m.source.san,
+                comment: m.source.comment.as_ref().map(|c| c.as_str()),
+                nags: nags(&m.source.nags)
+            });
+        }
+        diesel::insert_into(line_move::table)
+            .values(&line_moves)
+            .execute(self.conn)?;
+
+        for (index, m) in replayed.moves.iter().enumerate() {
+            let ply = replayed.first_ply + index as i32;
+            for variation in &m.variations {
+                self.line(game_id, variation, Some((line_id, ply)))?;
+            }
+        }
+        Ok(line_id)
+    }
+
+    fn position(&mut self, hash: ZobristHash) -> Result<i32> {
+        if let Some(&id) = self.positions.get(&hash) {
+            return Ok(id);
+        }
+        let (hash_1, hash_2) = hash.to_columns();
+        let existing = position::table
+            .filter(position::hash_1.eq(hash_1))
+            .filter(position::hash_2.eq(hash_2))
+            .select(position::id)
+            .first::<i32>(self.conn)
+            .optional()?;
+        let id = match existing {
+            Some(id) => id,
+            None => {
+                diesel::insert_into(position::table)
+                    .values(&NewPosition{hash_1: hash_1, hash_2: hash_2})
+                    .execute(self.conn)?;
+                last_insert_id(self.conn)?
+            }
+        };
+        if self.positions.len() >= CACHE_SIZE {
+            self.positions.clear();
+        }
+        self.positions.insert(hash, id);
+        Ok(id)
+    }
+
+    fn move_id(&mut self, uci: &str, start: ZobristHash, end: ZobristHash) -> Result<i32> {
+        let starting_position_id = self.position(start)?;
+        let key = (starting_position_id, uci.to_string());
+        if let Some(&id) = self.moves.get(&key) {
+            return Ok(id);
+        }
+        let existing = _move::table
+            .filter(_move::starting_position_id.eq(starting_position_id))
+            .filter(_move::uci.eq(uci))
+            .select(_move::id)
+            .first::<i32>(self.conn)
+            .optional()?;
+        let id = match existing {
+            Some(id) => id,
+            None => {
+                let ending_position_id = self.position(end)?;
+                diesel::insert_into(_move::table)
+                    .values(&NewMove{
+                        uci: uci,
+                        starting_position_id: starting_position_id,
+                        ending_position_id: ending_position_id
+                    })
+                    .execute(self.conn)?;
+                last_insert_id(self.conn)?
+            }
+        };
+        if self.moves.len() >= CACHE_SIZE {
+            self.moves.clear();
+        }
+        self.moves.insert(key, id);
+        Ok(id)
+    }
+
+    fn player(&mut self, name: &str) -> Result<i32> {
+        let name = name.trim();
+        if let Some(&id) = self.players.get(name) {
+            return Ok(id);
+        }
+        let (first_name, last_name, middle_name) = split_name(name);
+        let fide_id = self.spelling.and_then(|spelling| spelling.player(name)).and_then(|info| info.fide_id);
+        let existing = player::table
+            .filter(player::first_name.eq(&first_name))
+            .filter(player::last_name.eq(&last_name))
+            .select((player::id, player::middle_name))
+            .load::<(i32, Option<String>)>(self.conn)?
+            .into_iter()
+            .find(|&(_, ref middle)| *middle == middle_name)
+            .map(|(id, _)| id);
+        let id = match existing {
+            Some(id) => id,
+            None => {
+                diesel::insert_into(player::table)
+                    .values(&NewPlayer{
+                        first_name: &first_name,
+                        last_name: &last_name,
+                        middle_name: middle_name.as_ref().map(|m| m.as_str()),
+                        fide_id: fide_id
+                    })
+                    .execute(self.conn)?;
+                last_insert_id(self.conn)?
+            }
+        };
+        if self.players.len() >= CACHE_SIZE {
+            self.players.clear();
+        }
+        self.players.insert(name.into(), id);
+        Ok(id)
+    }
+
+    fn event(&mut self, name: &str, year: i32) -> Result<i32> {
+        let key = (name.to_string(), year);
+        if let Some(&id) = self.events.get(&key) {
+            return Ok(id);
+        }
+        let existing = event::table
+            .filter(event::name.eq(name))
+            .filter(event::year.eq(year))
+            .select(event::id)
+            .first::<i32>(self.conn)
+            .optional()?;
+        let id = match existing {
+            Some(id) => id,
+            None => {
+                diesel::insert_into(event::table)
+                    .values(&NewEvent{name: name, city: "", country: "", year: year})
+                    .execute(self.conn)?;
+                last_insert_id(self.conn)?
+            }
+        };
+        if self.events.len() >= CACHE_SIZE {
+            self.events.clear();
+        }
+        self.events.insert(key, id);
+        Ok(id)
+    }
+
+    fn site(&mut self, name: &str) -> Result<i32> {
+        if let Some(&id) = self.sites.get(name) {
+            return Ok(id);
+        }
+        let existing = site::table
+            .filter(site::name.eq(name))
+            .select(site::id)
+            .first::<i32>(self.conn)
+            .optional()?;
+        let id = match existing {
+            Some(id) => id,
+            None => {
+                diesel::insert_into(site::table)
+                    .values(&NewSite{name: name})
+                    .execute(self.conn)?;
+                last_insert_id(self.conn)?
+            }
+        };
+        if self.sites.len() >= CACHE_SIZE {
+            self.sites.clear();
+        }
+        self.sites.insert(name.into(), id);
+        Ok(id)
+    }
+}
+
+// PGN uses "?" (and sometimes nothing at all) for unknown tag values.
+fn known(value: Option<&str>) -> Option<&str> {
+    value.map(|v| v.trim()).and_then(|v| if v.is_empty() || v == "?" { None } else { Some(v) })
+}
+
+// Splits "Last, First Middle" into (first, last, middle). Names without a comma (as used by most
+// online servers) are treated as a last name only.
+pub fn split_name(name: &str) -> (String, String, Option<String>) {
+    let mut parts = name.splitn(2, ',');
+    let last_name = parts.next().unwrap_or("").trim().to_string();
+    let given: Vec<&str> = parts.next().unwrap_or("").split_whitespace().collect();
+    let first_name = given.first().map_or("", |first| *first).to_string();
+    let middle_name = if given.len() > 1 { Some(given[1..].join(" ")) } else { None };
+    (first_name, last_name, middle_name)
+}
+
+fn rating(elo: Option<&str>) -> i32 {
+    elo.and_then(|elo| elo.trim().parse().ok()).unwrap_or(0)
+}
+
+// Rounds like "3.1" are stored as the main round number.
+fn round(round: &str) -> Option<i32> {
+    round.split('.').next().and_then(|r| r.trim().parse().ok())
+}
+
+fn year(date: &str) -> i32 {
+    date.get(0..4).and_then(|y| y.parse().ok()).unwrap_or(0)
+}
+
+fn nags(nags: &[u8]) -> Option<String> {
+    if nags.is_empty() {
+        return None;
+    }
+    Some(nags.iter().map(|nag| nag.to_string()).collect::<Vec<_>>().join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_name() {
+        assert_eq!(split_name("Carlsen, Magnus"), ("Magnus".into(), "Carlsen".into(), None));
+        assert_eq!(split_name("  Anand ,  Viswanathan  "), ("Viswanathan".into(), "Anand".into(), None));
+        assert_eq!(split_name("Nepomniachtchi, Ian Alexandrovich Jr"),
+            ("Ian".into(), "Nepomniachtchi".into(), Some("Alexandrovich Jr".into())));
+        assert_eq!(split_name("DrNykterstein"), ("".into(), "DrNykterstein".into(), None));
+        assert_eq!(split_name("Carlsen,"), ("".into(), "Carlsen".into(), None));
+        assert_eq!(split_name(""), ("".into(), "".into(), None));
+    }
+
+    #[test]
+    fn test_round() {
+        assert_eq!(round("3"), Some(3));
+        assert_eq!(round("3.1"), Some(3));
+        assert_eq!(round(" 12 "), Some(12));
+        assert_eq!(round("?"), None);
+        assert_eq!(round("-"), None);
+        assert_eq!(round(""), None);
+    }
+
+    #[test]
+    fn test_first_ply() {
+        let game = parse_game("1. e4 e5 (1... c5 2. Nf3) 2. Nf3 *").unwrap();
+        let replayed = replay(&game).unwrap();
+        assert_eq!(replayed.line.first_ply, 1);
+        assert_eq!(replayed.line.moves[1].variations[0].first_ply, 2);
+
+        // Games that start from a FEN with black to move start on an even ply.
+        let game = parse_game("[FEN \"rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1\"]\n\n1... e5 (1... c5) 2. Nf3 *").unwrap();
+        let replayed = replay(&game).unwrap();
+        assert_eq!(replayed.line.first_ply, 2);
+        assert_eq!(replayed.line.moves[0].variations[0].first_ply, 2);
+        assert_eq!(replayed.line.moves.len(), 2);
+    }
+
+    #[test]
+    fn test_illegal_moves_are_not_replayed() {
+        // import::importFile skips, and counts, the games that fail here.
+        let game = parse_game("1. e4 e5 2. Ke3 *").unwrap();
+        assert!(replay(&game).is_err());
+        let game = parse_game("1. e4 (1. e5) 1... e5 *").unwrap();
+        assert!(replay(&game).is_err());
+    }
+}
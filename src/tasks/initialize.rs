@@ -30,7 +30,7 @@ use diesel::prelude::*;
 //use std::io::{Read, Write, BufWriter, BufReader};
 //use hyper::Client;
 
-use super::Request;
+use super::{ProgressChannel, Request, LEGACY_PROTOCOL, PROTOCOL_VERSION};
 use ::errors::*;
 use std::{thread, time};
 use app_info::DELILA_VERSION;
@@ -44,20 +44,26 @@ pub struct InitializeError {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct InitializeFinished {
-    pub finished: bool
+    pub finished: bool,
+    // The version of the protocol used from here on.
+    pub protocol_version: u32,
+    // Sent with task::resume after reconnecting, to pick up the requests left running.
+    pub session: String
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct VersionId(String);
 
 #[derive(Serialize, Deserialize, Debug)]
-pub struct VersionMismatchError {
-    pub server_version: VersionId
+pub struct Version {
+    pub client_version: VersionId,
+    // The newest protocol the client speaks. Clients from before there were versions don't say.
+    #[serde(default = "legacy_protocol")]
+    pub protocol_version: u32
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Version {
-    pub client_version: VersionId
+fn legacy_protocol() -> u32 {
+    LEGACY_PROTOCOL
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -68,44 +74,45 @@ pub struct InitializeProgress {
 
 pub fn run_migrations(request: &Request) -> Result<()>
 {
-    run_pending_migrations(&request.get_connection())
+    run_pending_migrations(&request.get_connection()?)
         .chain_err(|| "Unable to run database migrations during startup")
 }
 
-pub fn initialize(request: &Request, version:Version) -> Result<()> {
-    let Version {client_version: VersionId(client_version)} = version;
+pub fn initialize(
+    request: &Request,
+    version: Version,
+    progress: &ProgressChannel<InitializeProgress>
+) -> Result<InitializeFinished> {
+    let Version {client_version: VersionId(client_version), protocol_version} = version;
     if client_version != DELILA_VERSION {
-        let mismatch =
-        request.send(
-            "VersionMismatchError".into(),
-            &VersionMismatchError{server_version: VersionId(DELILA_VERSION.into())}
-        );
-    } else {
-        let mut state: InitializeProgress = InitializeProgress{
-            activity: "Running database migrations".into(),
-            progress: 0.0
-        };
-        request.send("InitializeProgress".into(), &state)?;
-        // TODO(lakin): This error should be sent to the client as well.
-        run_migrations(&request)?;
-
-        let increment = 1f32;
-        let tasks = vec![
-            "Reticulating splines",
-            "Checking for updates",
-            "Done",
-        ];
-        for activity in tasks {
-            let _50ms = time::Duration::from_millis(50);
-            thread::sleep(_50ms);
-            state.progress += increment * 20.0f32;
-            state.activity = activity.into();
-            info!(request.log, "InitializeProgress {}", state.progress);
-            request.send("InitializeProgress".into(), &state)?
-        }
-        request.send("InitializeFinished".into(), &InitializeFinished{finished: true})?;
+        bail!(ErrorKind::VersionMismatch(client_version, DELILA_VERSION.into()));
     }
-
-    Ok(())
+    let protocol_version = protocol_version.min(PROTOCOL_VERSION);
+    request.connection.set_protocol(protocol_version);
+    let mut state: InitializeProgress = InitializeProgress{
+        activity: "Running database migrations".into(),
+        progress: 0.0
+    };
+    progress.send(&state)?;
+    run_migrations(&request)?;
+
+    let increment = 1f32;
+    let tasks = vec![
+        "Reticulating splines",
+        "Checking for updates",
+        "Done",
+    ];
+    for activity in tasks {
+        let _50ms = time::Duration::from_millis(50);
+        thread::sleep(_50ms);
+        state.progress += increment * 20.0f32;
+        state.activity = activity.into();
+        info!(request.log, "InitializeProgress {}", state.progress);
+        progress.send(&state)?
+    }
+    Ok(InitializeFinished{
+        finished: true,
+        protocol_version: protocol_version,
+        session: request.connection.session()
+    })
 }
-
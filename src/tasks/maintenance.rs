@@ -0,0 +1,459 @@
+// delila - a desktop version of lila.
+//
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//--------------------------------------------------------------------------------------------------
+// Request handlers for keeping a database tidy: finding the games that were imported more than
+// once and merging them back into one, and correcting the names of players, events and sites.
+//--------------------------------------------------------------------------------------------------
+
+use diesel;
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::{BigInt, Integer, Text};
+use diesel::sqlite::SqliteConnection;
+use std::collections::HashMap;
+
+use super::super::models;
+use super::super::schema::{event, game, line, line_move, player, site};
+use super::super::scid::namebase::{NAME_EVENT, NAME_PLAYER, NAME_SITE};
+use super::duplicates::{Candidate, group, more_complete};
+use super::import::{split_name, Progress};
+use super::search::{display_name, load_summaries, GameSummary};
+
+use super::{ProgressChannel, Request};
+use ::errors::*;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FindDuplicates {}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DuplicateGroup {
+    // The most complete copy, which a merge keeps.
+    pub keep: i32,
+    // Every copy, the most complete first.
+    pub games: Vec<GameSummary>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DuplicateGroups {
+    pub groups: Vec<DuplicateGroup>,
+}
+
+// The games are merged into keep, or into the most complete of them if keep isn't given.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Merge {
+    pub game_ids: Vec<i32>,
+    pub keep: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Merged {
+    pub kept: i32,
+    pub deleted: Vec<i32>,
+}
+
+#[derive(QueryableByName)]
+struct CandidateRow {
+    #[sql_type = "Integer"] id: i32,
+    #[sql_type = "Integer"] white_player_id: i32,
+    #[sql_type = "Integer"] black_player_id: i32,
+    #[sql_type = "Text"] date: String,
+    #[sql_type = "Text"] result: String,
+    #[sql_type = "Integer"] line_id: i32,
+    #[sql_type = "BigInt"] text_length: i64,
+}
+
+const CANDIDATE_SELECT: &'static str = "
+    SELECT g.id AS id,
+           g.white_player_id AS white_player_id,
+           g.black_player_id AS black_player_id,
+           g.date AS date,
+           g.result AS result,
+           g.line_id AS line_id,
+           length(g.pgn) AS text_length
+      FROM game g";
+
+// Only games between two players who have met more than once can be duplicates.
+const REPEATED_PAIRINGS: &'static str = "
+      JOIN (SELECT white_player_id, black_player_id
+              FROM game
+             GROUP BY white_player_id, black_player_id
+            HAVING COUNT(*) > 1) p
+        ON p.white_player_id = g.white_player_id AND p.black_player_id = g.black_player_id
+     ORDER BY g.white_player_id, g.black_player_id, g.id";
+
+// Fills in the main line moves of the games.
+fn candidates(conn: &SqliteConnection, rows: &[CandidateRow]) -> Result<Vec<Candidate>> {
+    let line_ids: Vec<i32> = rows.iter().map(|row| row.line_id).collect();
+    let mut moves: HashMap<i32, Vec<i32>> = HashMap::new();
+    let line_moves = line_move::table
+        .filter(line_move::line_id.eq_any(line_ids))
+        .order((line_move::line_id, line_move::ply))
+        .select((line_move::line_id, line_move::move_id))
+        .load::<(i32, i32)>(conn)?;
+    for (line_id, move_id) in line_moves {
+        moves.entry(line_id).or_insert_with(Vec::new).push(move_id);
+    }
+    Ok(rows.iter()
+        .map(|row| Candidate{
+            id: row.id,
+            date: row.date.clone(),
+            result: row.result.clone(),
+            moves: moves.remove(&row.line_id).unwrap_or_default(),
+            text_length: row.text_length as usize
+        })
+        .collect())
+}
+
+pub fn find_duplicates(request: &Request, _args: FindDuplicates, progress: &ProgressChannel<Progress>) -> Result<DuplicateGroups> {
+    let conn = request.get_connection()?;
+    let mut state = Progress{activity: "Finding duplicate games".into(), progress: 0.0};
+    progress.send(&state)?;
+
+    let rows = sql_query(format!("{}{}", CANDIDATE_SELECT, REPEATED_PAIRINGS))
+        .load::<CandidateRow>(&conn)?;
+    let mut groups = Vec::new();
+    let mut start = 0;
+    while start < rows.len() {
+        request.check_cancelled()?;
+        let players = (rows[start].white_player_id, rows[start].black_player_id);
+        let end = rows[start..].iter()
+            .position(|row| (row.white_player_id, row.black_player_id) != players)
+            .map_or(rows.len(), |length| start + length);
+        for copies in group(candidates(&conn, &rows[start..end])?) {
+            let ids: Vec<i32> = copies.iter().map(|copy| copy.id).collect();
+            groups.push(DuplicateGroup{keep: ids[0], games: load_summaries(&conn, &ids)?});
+        }
+        let done = end as f32 / rows.len() as f32 * 100.0;
+        if done - state.progress >= 1.0 || end == rows.len() {
+            state.progress = done;
+            progress.send(&state)?;
+        }
+        start = end;
+    }
+    info!(request.log, "maintenance::findDuplicates found {} groups", groups.len());
+    Ok(DuplicateGroups{groups: groups})
+}
+
+fn delete_game(conn: &SqliteConnection, game_id: i32) -> Result<()> {
+    let lines = line::table.filter(line::game_id.eq(game_id)).select(line::id);
+    diesel::delete(line_move::table.filter(line_move::line_id.eq_any(lines))).execute(conn)?;
+    diesel::delete(line::table.filter(line::game_id.eq(game_id))).execute(conn)?;
+    diesel::delete(game::table.find(game_id)).execute(conn)?;
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+// merge_duplicates():
+//      Keeps one copy of a game and deletes the others. Anything the kept copy doesn't know that
+//      another copy does (its event, site, round, ratings, ECO code, or more of its date) is
+//      filled in from the others, the most complete first.
+//
+pub fn merge_duplicates(request: &Request, args: Merge) -> Result<Merged> {
+    let conn = request.get_connection()?;
+    if args.game_ids.is_empty() {
+        bail!("Merging needs at least two games");
+    }
+    let merged = conn.transaction::<_, Error, _>(|| {
+        let id_list = args.game_ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(",");
+        let rows = sql_query(format!("{} WHERE g.id IN ({})", CANDIDATE_SELECT, id_list))
+            .load::<CandidateRow>(&conn)?;
+        if let Some(missing) = args.game_ids.iter().find(|&&id| rows.iter().all(|row| row.id != id)) {
+            bail!("There is no game {}", missing);
+        }
+        let mut copies = candidates(&conn, &rows)?;
+        copies.sort_by(more_complete);
+        if copies.len() < 2 {
+            bail!("Merging needs at least two games");
+        }
+        let kept = match args.keep {
+            Some(keep) if copies.iter().all(|copy| copy.id != keep) => bail!("Game {} isn't being merged", keep),
+            Some(keep) => keep,
+            None => copies[0].id
+        };
+
+        let mut games = game::table
+            .filter(game::id.eq_any(copies.iter().map(|copy| copy.id).collect::<Vec<_>>()))
+            .load::<models::Game>(&conn)?;
+        games.sort_by_key(|g| copies.iter().position(|copy| copy.id == g.id));
+        let (mut keeper, others): (Vec<models::Game>, Vec<models::Game>) = games.into_iter()
+            .partition(|g| g.id == kept);
+        let mut keeper = keeper.remove(0);
+        let known = |date: &str| date.chars().filter(|&c| c != '?').count();
+        for other in &others {
+            keeper.event_id = keeper.event_id.or(other.event_id);
+            keeper.site_id = keeper.site_id.or(other.site_id);
+            keeper.round = keeper.round.or(other.round);
+            if keeper.white_player_rating == 0 {
+                keeper.white_player_rating = other.white_player_rating;
+            }
+            if keeper.black_player_rating == 0 {
+                keeper.black_player_rating = other.black_player_rating;
+            }
+            if keeper.eco.is_none() {
+                keeper.eco = other.eco.clone();
+            }
+            if known(&other.date) > known(&keeper.date) {
+                keeper.date = other.date.clone();
+            }
+        }
+        diesel::update(game::table.find(kept))
+            .set((
+                game::event_id.eq(keeper.event_id),
+                game::site_id.eq(keeper.site_id),
+                game::round.eq(keeper.round),
+                game::white_player_rating.eq(keeper.white_player_rating),
+                game::black_player_rating.eq(keeper.black_player_rating),
+                game::eco.eq(keeper.eco),
+                game::date.eq(keeper.date)
+            ))
+            .execute(&conn)?;
+
+        let deleted: Vec<i32> = others.iter().map(|g| g.id).collect();
+        for &game_id in &deleted {
+            delete_game(&conn, game_id)?;
+        }
+        Ok(Merged{kept: kept, deleted: deleted})
+    })?;
+    info!(request.log, "maintenance::mergeDuplicates kept game {} and deleted {:?}", merged.kept, merged.deleted);
+    Ok(merged)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum NameKind {
+    Player,
+    Event,
+    Site,
+}
+
+// Gives every one of the ids the name. Any that then share a name, with each other or with one
+// that's already in the database, are merged into one and their games moved to it. Events are only
+// merged with events of the same year.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Rename {
+    pub kind: NameKind,
+    pub ids: Vec<i32>,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Renamed {
+    // The ids that have the name now.
+    pub ids: Vec<i32>,
+    // The ids that were merged into them, which no longer exist.
+    pub merged: Vec<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ApplySpelling {}
+
+// The number of players, events and sites whose names were corrected.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct SpellingApplied {
+    pub players: u32,
+    pub events: u32,
+    pub sites: u32,
+}
+
+fn missing(kind: NameKind, ids: &[i32], found: &[i32]) -> Result<()> {
+    match ids.iter().find(|&&id| !found.contains(&id)) {
+        Some(id) => bail!("There is no {:?} {}", kind, id),
+        None => Ok(())
+    }
+}
+
+fn rename_players(conn: &SqliteConnection, ids: &[i32], name: &str) -> Result<Renamed> {
+    let (first_name, last_name, middle_name) = split_name(name.trim());
+    let found = player::table.filter(player::id.eq_any(ids.to_vec())).load::<models::Player>(conn)?;
+    missing(NameKind::Player, ids, &found.iter().map(|p| p.id).collect::<Vec<_>>())?;
+    let existing = player::table
+        .filter(player::first_name.eq(&first_name))
+        .filter(player::last_name.eq(&last_name))
+        .load::<models::Player>(conn)?
+        .into_iter()
+        .find(|p| p.middle_name == middle_name);
+    let fide_id = existing.iter().chain(found.iter()).filter_map(|p| p.fide_id).next();
+    let keep = match existing {
+        Some(p) => p.id,
+        None => found.iter().map(|p| p.id).min().unwrap_or(0)
+    };
+    let merged: Vec<i32> = found.iter().map(|p| p.id).filter(|&id| id != keep).collect();
+
+    diesel::update(player::table.find(keep))
+        .set((
+            player::first_name.eq(&first_name),
+            player::last_name.eq(&last_name),
+            player::middle_name.eq(&middle_name),
+            player::fide_id.eq(fide_id)
+        ))
+        .execute(conn)?;
+    diesel::update(game::table.filter(game::white_player_id.eq_any(merged.clone())))
+        .set(game::white_player_id.eq(keep))
+        .execute(conn)?;
+    diesel::update(game::table.filter(game::black_player_id.eq_any(merged.clone())))
+        .set(game::black_player_id.eq(keep))
+        .execute(conn)?;
+    diesel::delete(player::table.filter(player::id.eq_any(merged.clone()))).execute(conn)?;
+    Ok(Renamed{ids: vec![keep], merged: merged})
+}
+
+fn rename_events(conn: &SqliteConnection, ids: &[i32], name: &str) -> Result<Renamed> {
+    let name = name.trim();
+    let found = event::table.filter(event::id.eq_any(ids.to_vec())).load::<models::Event>(conn)?;
+    missing(NameKind::Event, ids, &found.iter().map(|e| e.id).collect::<Vec<_>>())?;
+    let mut years: Vec<i32> = found.iter().map(|e| e.year).collect();
+    years.sort();
+    years.dedup();
+
+    let mut renamed = Renamed::default();
+    for year in years {
+        let existing = event::table
+            .filter(event::name.eq(name))
+            .filter(event::year.eq(year))
+            .select(event::id)
+            .first::<i32>(conn)
+            .optional()?;
+        let of_year: Vec<i32> = found.iter().filter(|e| e.year == year).map(|e| e.id).collect();
+        let keep = existing.or_else(|| of_year.iter().cloned().min()).unwrap_or(0);
+        let merged: Vec<i32> = of_year.into_iter().filter(|&id| id != keep).collect();
+
+        diesel::update(event::table.find(keep)).set(event::name.eq(name)).execute(conn)?;
+        diesel::update(game::table.filter(game::event_id.eq_any(merged.clone())))
+            .set(game::event_id.eq(keep))
+            .execute(conn)?;
+        diesel::delete(event::table.filter(event::id.eq_any(merged.clone()))).execute(conn)?;
+        renamed.ids.push(keep);
+        renamed.merged.extend(merged);
+    }
+    Ok(renamed)
+}
+
+fn rename_sites(conn: &SqliteConnection, ids: &[i32], name: &str) -> Result<Renamed> {
+    let name = name.trim();
+    let found = site::table.filter(site::id.eq_any(ids.to_vec())).select(site::id).load::<i32>(conn)?;
+    missing(NameKind::Site, ids, &found)?;
+    let existing = site::table
+        .filter(site::name.eq(name))
+        .select(site::id)
+        .first::<i32>(conn)
+        .optional()?;
+    let keep = existing.or_else(|| found.iter().cloned().min()).unwrap_or(0);
+    let merged: Vec<i32> = found.into_iter().filter(|&id| id != keep).collect();
+
+    diesel::update(site::table.find(keep)).set(site::name.eq(name)).execute(conn)?;
+    diesel::update(game::table.filter(game::site_id.eq_any(merged.clone())))
+        .set(game::site_id.eq(keep))
+        .execute(conn)?;
+    diesel::delete(site::table.filter(site::id.eq_any(merged.clone()))).execute(conn)?;
+    Ok(Renamed{ids: vec![keep], merged: merged})
+}
+
+fn rename_names(conn: &SqliteConnection, kind: NameKind, ids: &[i32], name: &str) -> Result<Renamed> {
+    match kind {
+        NameKind::Player => rename_players(conn, ids, name),
+        NameKind::Event => rename_events(conn, ids, name),
+        NameKind::Site => rename_sites(conn, ids, name)
+    }
+}
+
+pub fn rename(request: &Request, args: Rename) -> Result<Renamed> {
+    let conn = request.get_connection()?;
+    if args.ids.is_empty() {
+        bail!("There is nothing to rename");
+    }
+    if args.name.trim().is_empty() {
+        bail!("Names can't be empty");
+    }
+    let renamed = conn.transaction::<_, Error, _>(|| rename_names(&conn, args.kind, &args.ids, &args.name))?;
+    info!(request.log, "maintenance::rename renamed {:?} {:?} to {}, merging {:?}",
+        args.kind, args.ids, args.name, renamed.merged);
+    Ok(renamed)
+}
+
+//--------------------------------------------------------------------------------------------------
+// apply_spelling():
+//      Corrects the names already in the database with the spelling file, merging the ones that
+//      turn out to be the same, and fills in the FIDE ids of the players it knows.
+//
+pub fn apply_spelling(request: &Request, _args: ApplySpelling, progress: &ProgressChannel<Progress>) -> Result<SpellingApplied> {
+    let spelling = match request.spelling {
+        Some(ref spelling) => spelling.clone(),
+        None => bail!("No spelling file is loaded")
+    };
+    let conn = request.get_connection()?;
+    let mut applied = SpellingApplied::default();
+    let mut state = Progress{activity: "Correcting players".into(), progress: 0.0};
+    progress.send(&state)?;
+
+    conn.transaction::<_, Error, _>(|| {
+        for p in player::table.load::<models::Player>(&conn)? {
+            let name = display_name(p.first_name, p.last_name, p.middle_name);
+            let id = match spelling.correct(NAME_PLAYER, &name) {
+                Some(correct) if correct != name => {
+                    applied.players += 1;
+                    rename_players(&conn, &[p.id], correct)?.ids[0]
+                },
+                _ => p.id
+            };
+            let fide_id = spelling.player(&name).and_then(|info| info.fide_id);
+            if p.fide_id.is_none() && fide_id.is_some() {
+                diesel::update(player::table.find(id).filter(player::fide_id.is_null()))
+                    .set(player::fide_id.eq(fide_id))
+                    .execute(&conn)?;
+            }
+        }
+        Ok(())
+    })?;
+    request.check_cancelled()?;
+    state = Progress{activity: "Correcting events".into(), progress: 100.0 / 3.0};
+    progress.send(&state)?;
+
+    conn.transaction::<_, Error, _>(|| {
+        for e in event::table.load::<models::Event>(&conn)? {
+            match spelling.correct(NAME_EVENT, &e.name) {
+                Some(correct) if correct != e.name => {
+                    rename_events(&conn, &[e.id], correct)?;
+                    applied.events += 1;
+                },
+                _ => {}
+            }
+        }
+        Ok(())
+    })?;
+    request.check_cancelled()?;
+    state = Progress{activity: "Correcting sites".into(), progress: 200.0 / 3.0};
+    progress.send(&state)?;
+
+    conn.transaction::<_, Error, _>(|| {
+        for s in site::table.load::<models::Site>(&conn)? {
+            match spelling.correct(NAME_SITE, &s.name) {
+                Some(correct) if correct != s.name => {
+                    rename_sites(&conn, &[s.id], correct)?;
+                    applied.sites += 1;
+                },
+                _ => {}
+            }
+        }
+        Ok(())
+    })?;
+    state.progress = 100.0;
+    progress.send(&state)?;
+    info!(request.log, "maintenance::applySpelling corrected {} players, {} events and {} sites",
+        applied.players, applied.events, applied.sites);
+    Ok(applied)
+}
+
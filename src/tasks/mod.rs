@@ -18,80 +18,422 @@
 //------------------------------------------------------------------------------
 // The various tasks that the server support.
 //------------------------------------------------------------------------------
+pub mod commands;
+pub mod database;
+pub mod describe;
+pub mod duplicates;
+pub mod explorer;
+pub mod export;
+#[cfg(test)]
+mod fixture;
 pub mod import;
 pub mod initialize;
+pub mod maintenance;
+pub mod search;
+pub mod task;
+pub mod variations;
 
-use std::sync::Arc;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use diesel::sqlite::SqliteConnection;
 use serde;
 use serde_json;
+use serde_json::Value;
+use rmp_serde;
 use slog;
-use ws::Sender;
+use ws::{CloseCode, Sender};
 
 use errors::*;
 use super::pathsettings::{PathSettings};
-use super::establish_connection;
+use super::scid::eco::EcoBook;
+use super::scid::spelling::Spelling;
+use self::task::{TaskRegistry, session_id};
+
+// The first version of the protocol sent args as a string of JSON inside the message. Clients
+// that don't ask for a newer version in initialize::initialize are still spoken to that way.
+pub const LEGACY_PROTOCOL: u32 = 1;
+// Args are JSON values.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+// Replies carry the id of the request they answer. A request may send any number of "progress"
+// messages, and then ends with exactly one "response", "error" or "cancelled" message.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Message {
+    pub name: String,
+    pub id: u32,
+    pub args: Value,
+    // The id of the database that a request works on. Requests that leave it out use the one
+    // that the connection opened with database::open, or else the default database.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database: Option<String>
+}
+
+#[derive(Serialize)]
+struct LegacyMessage<'a> {
+    name: &'a str,
+    id: u32,
+    args: String
+}
+
+// Messages from clients are first read as a plain value, so that the request id can still be
+// found in messages that turn out not to be requests.
+pub fn decode_text(text: &str) -> Result<Value> {
+    serde_json::from_str(text).chain_err(|| ErrorKind::MalformedMessage)
+}
+
+// Binary frames hold the same messages as text frames, in MessagePack.
+pub fn decode_binary(bytes: &[u8]) -> Result<Value> {
+    rmp_serde::from_slice(bytes).chain_err(|| ErrorKind::MalformedMessage)
+}
+
+impl Message {
+    // Reads a message in the version of the protocol that the connection speaks. Legacy clients
+    // send their args as a string of JSON. Newer ones start out speaking the legacy protocol too,
+    // until initialize agrees on theirs, so args that aren't a string are taken as they are.
+    pub fn from_envelope(envelope: Value, protocol: u32) -> Result<Message> {
+        let mut message: Message = serde_json::from_value(envelope).chain_err(
+            || ErrorKind::MalformedMessage
+        )?;
+        let legacy_args = match message.args {
+            Value::String(ref args) if protocol < PROTOCOL_VERSION => Some(
+                serde_json::from_str(args).chain_err(|| ErrorKind::MalformedMessage)?
+            ),
+            _ => None
+        };
+        if let Some(args) = legacy_args {
+            message.args = args;
+        }
+        Ok(message)
+    }
+
+    pub fn encode(&self, protocol: u32) -> Result<String> {
+        let encoded = if protocol < PROTOCOL_VERSION {
+            serde_json::to_string(&LegacyMessage{name: &self.name, id: self.id, args: self.args.to_string()})
+        } else {
+            serde_json::to_string(self)
+        };
+        encoded.chain_err(|| "Unable to serialize outoing message")
+    }
+
+    // Binary frames came after the legacy protocol, so they always carry args as values.
+    pub fn encode_binary(&self) -> Result<Vec<u8>> {
+        rmp_serde::to_vec_named(self).chain_err(|| "Unable to serialize outoing message")
+    }
+}
+
+// A client's connection, the version of the protocol that it speaks, whether it speaks it in
+// binary frames, and the database it has open. Replies go back the same way that the client
+// last wrote. The session id says which requests in the task registry are the client's.
+//
+// A client that reconnects can resume its session with task::resume, which attaches the new
+// socket to the session's connection, so its running requests reply to the new socket, and moves
+// the new connection onto the session.
+#[derive(Clone)]
+pub struct Connection {
+    out: Arc<Mutex<Sender>>,
+    session: Arc<Mutex<String>>,
+    protocol: Arc<AtomicUsize>,
+    binary: Arc<AtomicBool>,
+    database: Arc<Mutex<Option<String>>>
+}
+
+impl Connection {
+    pub fn new(out: Sender) -> Connection {
+        Connection{
+            out: Arc::new(Mutex::new(out)),
+            session: Arc::new(Mutex::new(session_id())),
+            protocol: Arc::new(AtomicUsize::new(LEGACY_PROTOCOL as usize)),
+            binary: Arc::new(AtomicBool::new(false)),
+            database: Arc::new(Mutex::new(None))
+        }
+    }
+    pub fn session(&self) -> String {
+        self.session.lock().unwrap().clone()
+    }
+    pub fn set_session(&self, session: &str) {
+        *self.session.lock().unwrap() = session.into();
+    }
+    // Sends everything for this connection's session to the other connection's socket, the way
+    // the other connection's client speaks.
+    pub fn attach(&self, other: &Connection) {
+        let out = other.out.lock().unwrap().clone();
+        *self.out.lock().unwrap() = out;
+        self.set_protocol(other.protocol());
+        self.set_binary(other.binary.load(Ordering::SeqCst));
+    }
+    pub fn database(&self) -> Option<String> {
+        self.database.lock().unwrap().clone()
+    }
+    pub fn set_database(&self, id: Option<String>) {
+        *self.database.lock().unwrap() = id;
+    }
+    pub fn set_binary(&self, binary: bool) {
+        self.binary.store(binary, Ordering::SeqCst);
+    }
+    pub fn protocol(&self) -> u32 {
+        self.protocol.load(Ordering::SeqCst) as u32
+    }
+    pub fn set_protocol(&self, version: u32) {
+        self.protocol.store(version as usize, Ordering::SeqCst);
+    }
+    // Closes a connection that isn't allowed to talk to the server.
+    pub fn refuse(&self, reason: &'static str) -> Result<()> {
+        self.out.lock().unwrap().close_with_reason(CloseCode::Policy, reason)
+            .chain_err(|| "Unable to close the connection")
+    }
+    pub fn send(&self, message: &Message) -> Result<()> {
+        let out = self.out.lock().unwrap();
+        let sent = if self.binary.load(Ordering::SeqCst) {
+            out.send(message.encode_binary()?)
+        } else {
+            out.send(message.encode(self.protocol())?)
+        };
+        sent.chain_err(|| "Unable to send message")
+    }
+}
 
 #[derive(Clone)]
 pub struct Request {
     pub id: u32,
     pub name: String,
-    pub out: Sender,
+    pub connection: Connection,
+    // The session the request belongs to, even if task::resume later moves the connection.
+    pub session: String,
     pub log: slog::Logger,
-    pub path_settings: PathSettings
+    pub path_settings: PathSettings,
+    pub tasks: Arc<TaskRegistry>,
+    pub cancelled: Arc<AtomicBool>,
+    // The id of the database to work on, or None for the default database.
+    pub database: Option<String>,
+    // The openings games are classified with, if an opening file was loaded.
+    pub eco: Option<Arc<EcoBook>>,
+    // The spellings names are corrected to, if a spelling file was loaded.
+    pub spelling: Option<Arc<Spelling>>
 }
 impl Request {
-    fn send<T>(&self, method_name: String, args: &T) -> Result<()>
+    fn message<T>(&self, method_name: String, args: &T) -> Result<Message>
         where T: serde::Serialize
     {
-        serde_json::to_string(&args).chain_err(
+        let args = serde_json::to_value(args).chain_err(
             || "Unable to serialize outoing args"
-        ).and_then(|args| {
-            let message = Message{name: method_name, id: self.id, args: args};
-            serde_json::to_string(&message).chain_err(
-                || "Unable to serialize outoing message"
-            ).and_then(|outgoing|  {
-                self.out.send(outgoing).chain_err(
-                    || "Unable to send message"
-                )
-            })
-        })
+        )?;
+        Ok(Message{name: method_name, id: self.id, args: args, database: None})
+    }
+    fn send<T>(&self, method_name: String, args: &T) -> Result<()>
+        where T: serde::Serialize
+    {
+        self.message(method_name, args).and_then(|message| self.connection.send(&message))
     }
-    fn get_connection(&self) -> SqliteConnection {
-        establish_connection(&self.path_settings.database_path.to_str().unwrap())
+    // The one message that ends a request that succeeded.
+    fn respond<T>(&self, response: &T) -> Result<()>
+        where T: serde::Serialize
+    {
+        self.send("response".into(), response)
+    }
+    // Sends an update on how the request is going, which is also kept for task::status.
+    fn send_progress<T>(&self, progress: &T) -> Result<()>
+        where T: serde::Serialize
+    {
+        let message = self.message("progress".into(), progress)?;
+        self.tasks.progress(&self.session, self.id, &message);
+        self.connection.send(&message)
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+    // Long running handlers call this between units of work, and stop if it fails.
+    pub fn check_cancelled(&self) -> Result<()> {
+        if self.is_cancelled() {
+            bail!(ErrorKind::Cancelled);
+        }
+        Ok(())
+    }
+    // The last message sent for a request that stopped because it was cancelled.
+    pub fn send_cancelled(&self) -> Result<()> {
+        self.send("cancelled".into(), &task::TaskId{id: self.id})
+    }
+    // Tells the client that this request failed.
+    pub fn send_error(&self, error: &Error) -> Result<()> {
+        send_error(&self.connection, Some(self.id), error)
+    }
+    fn get_connection(&self) -> Result<SqliteConnection> {
+        database::connect(&self.path_settings, self.database.as_ref().map(|id| id.as_str()))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    // The message itself couldn't be read.
+    ParseError,
+    UnknownCommand,
+    // The message's args don't fit the command.
+    InvalidArguments,
+    // The client and server are different versions, and can't talk to each other.
+    VersionMismatch,
+    // The connection has to authenticate first.
+    Unauthorized,
+    // The command ran, and failed.
+    HandlerError,
+}
+
+impl ErrorCode {
+    pub fn of(error: &Error) -> ErrorCode {
+        match *error.kind() {
+            ErrorKind::MalformedMessage => ErrorCode::ParseError,
+            ErrorKind::UnknownCommand(_) => ErrorCode::UnknownCommand,
+            ErrorKind::InvalidArguments(_) => ErrorCode::InvalidArguments,
+            ErrorKind::VersionMismatch(..) => ErrorCode::VersionMismatch,
+            ErrorKind::Unauthorized => ErrorCode::Unauthorized,
+            _ => ErrorCode::HandlerError
+        }
     }
 }
 
+// Sent as the args of an "error" message, in reply to a request that failed. The request id is
+// missing when the message was too broken to find one.
 #[derive(Serialize, Deserialize, Debug)]
-pub struct Message {
-    pub name: String,
-    pub id: u32,
-    pub args: String
+pub struct ErrorReply {
+    pub code: ErrorCode,
+    pub message: String,
+    // The errors that led to this one, outermost first.
+    pub causes: Vec<String>,
+    pub request_id: Option<u32>,
 }
 
-// A function that takes a string (representing the JSON arguments)
-// And returns an optional thread. If a thread is returned the task has been
-// backgrounded.
-//
-// TODO: Make this type safe like rocket.rs
+impl ErrorReply {
+    pub fn new(request_id: Option<u32>, error: &Error) -> ErrorReply {
+        ErrorReply{
+            code: ErrorCode::of(error),
+            message: error.to_string(),
+            causes: error.iter().skip(1).map(|cause| cause.to_string()).collect(),
+            request_id: request_id
+        }
+    }
+}
+
+pub fn send_error(connection: &Connection, request_id: Option<u32>, error: &Error) -> Result<()> {
+    let reply = serde_json::to_value(&ErrorReply::new(request_id, error)).chain_err(
+        || "Unable to serialize error reply"
+    )?;
+    connection.send(&Message{name: "error".into(), id: request_id.unwrap_or(0), args: reply, database: None})
+}
+
+// A function that takes the JSON arguments of a request and runs the
+// request, ending it with a "response" message. Handlers are wrapped in one of
+// these by the commands! macro in commands.rs, which checks that they take and
+// return the declared types.
 pub trait RequestDispatch {
-    fn dispatch(&self, request: &Request, args: String) -> Result<()>;
+    fn dispatch(&self, request: &Request, args: Value) -> Result<()>;
 }
 
-pub struct JSONDispatch<T> where T: serde::de::DeserializeOwned {
-    pub handler: Arc<Fn(&Request, T) -> Result<()> + Send + Sync>
+pub struct JSONDispatch<T, R>
+    where T: serde::de::DeserializeOwned, R: serde::Serialize
+{
+    pub handler: Arc<Fn(&Request, T) -> Result<R> + Send + Sync>
 }
 
-impl<T> RequestDispatch for JSONDispatch<T> where T: serde::de::DeserializeOwned {
-    fn dispatch(&self, request: &Request, args: String) -> Result<()> {
+impl<T, R> RequestDispatch for JSONDispatch<T, R>
+    where T: serde::de::DeserializeOwned, R: serde::Serialize
+{
+    fn dispatch(&self, request: &Request, args: Value) -> Result<()> {
         info!(request.log, "Marshalling arguments");
-        serde_json::from_str(&args).chain_err(
-            || "Unable to parse incoming json into the given argument type"
-        ).and_then(|args| {
-            info!(request.log, "Invoking handler");
-            (self.handler)(&request, args)
-        })
+        let args = serde_json::from_value(args).chain_err(
+            || ErrorKind::InvalidArguments(request.name.clone())
+        )?;
+        info!(request.log, "Invoking handler");
+        let response = (self.handler)(&request, args)?;
+        request.respond(&response)
     }
 }
 
+// Where a long running handler sends "progress" messages, each of which is a P.
+pub struct ProgressChannel<'a, P> {
+    request: &'a Request,
+    progress: PhantomData<P>
+}
+
+impl<'a, P> ProgressChannel<'a, P> where P: serde::Serialize {
+    pub fn send(&self, progress: &P) -> Result<()> {
+        self.request.send_progress(progress)
+    }
+}
+
+pub struct ProgressDispatch<T, R, P>
+    where T: serde::de::DeserializeOwned, R: serde::Serialize, P: serde::Serialize
+{
+    pub handler: Arc<Fn(&Request, T, &ProgressChannel<P>) -> Result<R> + Send + Sync>
+}
+
+impl<T, R, P> RequestDispatch for ProgressDispatch<T, R, P>
+    where T: serde::de::DeserializeOwned, R: serde::Serialize, P: serde::Serialize
+{
+    fn dispatch(&self, request: &Request, args: Value) -> Result<()> {
+        info!(request.log, "Marshalling arguments");
+        let args = serde_json::from_value(args).chain_err(
+            || ErrorKind::InvalidArguments(request.name.clone())
+        )?;
+        info!(request.log, "Invoking handler");
+        let progress = ProgressChannel{request: request, progress: PhantomData};
+        let response = (self.handler)(&request, args, &progress)?;
+        request.respond(&response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(args: &str) -> Message {
+        Message{name: "search::games".into(), id: 4, args: serde_json::from_str(args).unwrap(), database: None}
+    }
+
+    fn round_trip(sent: &Message, protocol: u32) -> Message {
+        let text = sent.encode(protocol).unwrap();
+        Message::from_envelope(decode_text(&text).unwrap(), protocol).unwrap()
+    }
+
+    #[test]
+    fn test_legacy_round_trip() {
+        let sent = message(r#"{"player": "Carlsen", "page": 2}"#);
+        let text = sent.encode(LEGACY_PROTOCOL).unwrap();
+        assert!(decode_text(&text).unwrap()["args"].is_string());
+        let received = round_trip(&sent, LEGACY_PROTOCOL);
+        assert_eq!((received.name, received.id, received.args), (sent.name, sent.id, sent.args));
+    }
+
+    #[test]
+    fn test_raw_round_trip() {
+        for args in &[r#"{"player": "Carlsen", "page": 2}"#, r#""{\"player\": \"Carlsen\"}""#] {
+            let sent = message(args);
+            let received = round_trip(&sent, PROTOCOL_VERSION);
+            assert_eq!((received.name, received.id, received.args), (sent.name, sent.id, sent.args));
+        }
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let sent = message(r#"{"fen": "8/8/8/8/8/8/8/8 w - - 0 1", "include_variations": true}"#);
+        let bytes = sent.encode_binary().unwrap();
+        let received = Message::from_envelope(decode_binary(&bytes).unwrap(), PROTOCOL_VERSION).unwrap();
+        assert_eq!((received.name, received.id, received.args), (sent.name, sent.id, sent.args));
+    }
+
+    #[test]
+    fn test_initialize_before_negotiating() {
+        // A newer client's initialize arrives while the connection still speaks the legacy protocol.
+        let envelope = decode_text(r#"{"name": "initialize::initialize", "id": 1,
+            "args": {"client_version": "0.1.0", "protocol_version": 2}}"#).unwrap();
+        let received = Message::from_envelope(envelope, LEGACY_PROTOCOL).unwrap();
+        assert_eq!(received.args["protocol_version"], 2);
+    }
+
+    #[test]
+    fn test_malformed_messages() {
+        assert!(decode_text("{").is_err());
+        let envelope = decode_text(r#"{"name": "search::games", "id": 1, "args": "{"}"#).unwrap();
+        assert!(Message::from_envelope(envelope, LEGACY_PROTOCOL).is_err());
+    }
+}
@@ -0,0 +1,765 @@
+// delila - a desktop version of lila.
+//
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+//--------------------------------------------------------------------------------------------------
+// Request handlers for searching the games in the database.
+//--------------------------------------------------------------------------------------------------
+
+use diesel::prelude::*;
+use diesel::dsl::sql;
+use diesel::sql_query;
+use diesel::sql_types::{BigInt, Integer, Nullable, Text};
+use diesel::sqlite::{Sqlite, SqliteConnection};
+use slog;
+use std::collections::HashMap;
+
+use super::super::board::Board;
+use super::super::pgn::parse_game;
+use super::super::schema::{event, game, player, position, site};
+use super::super::scid::common::*;
+use super::super::scid::matsig;
+use super::super::scid::matsig::MaterialSignature;
+use super::super::scid::matpattern::MaterialPattern;
+
+use super::Request;
+use ::errors::*;
+
+macro_rules! sorted {
+    ($query:expr, $column:expr, $descending:expr) => (
+        if $descending {
+            $query.order(($column.desc(), game::id.desc()))
+        } else {
+            $query.order(($column.asc(), game::id.asc()))
+        }
+    )
+}
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 1000;
+// search::byMaterial fetches and replays the games that might match this many at a time.
+const REPLAY_BATCH_SIZE: usize = 100;
+
+// Everything needed to show a game in a list of results.
+const GAME_SUMMARY_SELECT: &'static str = "
+    SELECT g.id AS id,
+           g.date AS date,
+           g.round AS round,
+           g.result AS result,
+           g.white_player_rating AS white_rating,
+           g.black_player_rating AS black_rating,
+           g.eco AS eco,
+           w.first_name AS white_first_name,
+           w.last_name AS white_last_name,
+           w.middle_name AS white_middle_name,
+           b.first_name AS black_first_name,
+           b.last_name AS black_last_name,
+           b.middle_name AS black_middle_name,
+           e.name AS event,
+           s.name AS site
+      FROM game g
+      JOIN player w ON w.id = g.white_player_id
+      JOIN player b ON b.id = g.black_player_id
+      LEFT JOIN event e ON e.id = g.event_id
+      LEFT JOIN site s ON s.id = g.site_id";
+
+#[derive(QueryableByName)]
+struct GameRow {
+    #[sql_type = "Integer"] id: i32,
+    #[sql_type = "Text"] date: String,
+    #[sql_type = "Nullable<Integer>"] round: Option<i32>,
+    #[sql_type = "Text"] result: String,
+    #[sql_type = "Integer"] white_rating: i32,
+    #[sql_type = "Integer"] black_rating: i32,
+    #[sql_type = "Nullable<Text>"] eco: Option<String>,
+    #[sql_type = "Text"] white_first_name: String,
+    #[sql_type = "Text"] white_last_name: String,
+    #[sql_type = "Nullable<Text>"] white_middle_name: Option<String>,
+    #[sql_type = "Text"] black_first_name: String,
+    #[sql_type = "Text"] black_last_name: String,
+    #[sql_type = "Nullable<Text>"] black_middle_name: Option<String>,
+    #[sql_type = "Nullable<Text>"] event: Option<String>,
+    #[sql_type = "Nullable<Text>"] site: Option<String>,
+}
+
+#[derive(QueryableByName)]
+struct Count {
+    #[sql_type = "BigInt"] count: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GameSummary {
+    pub id: i32,
+    pub white: String,
+    pub white_rating: i32,
+    pub black: String,
+    pub black_rating: i32,
+    pub event: Option<String>,
+    pub site: Option<String>,
+    pub date: String,
+    pub round: Option<i32>,
+    pub result: String,
+    pub eco: Option<String>,
+}
+
+fn summarize(row: GameRow) -> GameSummary {
+    GameSummary{
+        id: row.id,
+        white: display_name(row.white_first_name, row.white_last_name, row.white_middle_name),
+        white_rating: row.white_rating,
+        black: display_name(row.black_first_name, row.black_last_name, row.black_middle_name),
+        black_rating: row.black_rating,
+        event: row.event,
+        site: row.site,
+        date: row.date,
+        round: row.round,
+        result: row.result,
+        eco: row.eco
+    }
+}
+
+// Puts a stored name back together the way PGN writes it: "Last, First Middle".
+pub fn display_name(first_name: String, last_name: String, middle_name: Option<String>) -> String {
+    let mut name = last_name;
+    if !first_name.is_empty() {
+        name.push_str(", ");
+        name.push_str(&first_name);
+    }
+    if let Some(middle_name) = middle_name {
+        name.push(' ');
+        name.push_str(&middle_name);
+    }
+    name
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GamePage {
+    pub total: i64,
+    pub page: i64,
+    pub page_size: i64,
+    pub games: Vec<GameSummary>,
+}
+
+// Loads the summaries of the given games, in the same order as the ids.
+pub fn load_summaries(conn: &SqliteConnection, ids: &[i32]) -> Result<Vec<GameSummary>> {
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let id_list = ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(",");
+    let rows = sql_query(format!("{} WHERE g.id IN ({})", GAME_SUMMARY_SELECT, id_list))
+        .load::<GameRow>(conn)?;
+    let mut summaries: HashMap<i32, GameSummary> = rows.into_iter()
+        .map(|row| (row.id, summarize(row)))
+        .collect();
+    Ok(ids.iter().filter_map(|id| summaries.remove(id)).collect())
+}
+
+// Pages past the last are empty, however far past, so offsets are taken with saturating_mul.
+fn page_bounds(page: Option<i64>, page_size: Option<i64>) -> (i64, i64) {
+    let page_size = page_size.unwrap_or(DEFAULT_PAGE_SIZE).max(1).min(MAX_PAGE_SIZE);
+    (page.unwrap_or(0).max(0), page_size)
+}
+
+// Looks up the id of the position row for a FEN, if any game has ever reached it.
+pub fn find_position(conn: &SqliteConnection, fen: &str) -> Result<Option<i32>> {
+    let (hash_1, hash_2) = Board::from_fen(fen)?.hash().to_columns();
+    position::table
+        .filter(position::hash_1.eq(hash_1))
+        .filter(position::hash_2.eq(hash_2))
+        .select(position::id)
+        .first::<i32>(conn)
+        .optional()
+        .chain_err(|| "Unable to look up the position")
+}
+
+//--------------------------------------------------------------------------------------------------
+// search::byPosition
+//--------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PositionQuery {
+    pub fen: String,
+    // Also count games where the position only appears in a variation.
+    #[serde(default)]
+    pub include_variations: bool,
+    pub page: Option<i64>,
+    pub page_size: Option<i64>,
+}
+
+// The ids of the games that reach a position, either by a move or by starting there.
+fn games_reaching_position(include_variations: bool) -> String {
+    let main_line_only = if include_variations { "" } else { "AND l.parent_line_id IS NULL" };
+    format!("
+        SELECT l.game_id
+          FROM _move m
+          JOIN line_move lm ON lm.move_id = m.id
+          JOIN line l ON l.id = lm.line_id
+         WHERE m.ending_position_id = ? {main_line_only}
+        UNION
+        SELECT l.game_id
+          FROM line l
+         WHERE l.starting_position_id = ? {main_line_only}",
+        main_line_only = main_line_only
+    )
+}
+
+fn position_page(conn: &SqliteConnection, args: &PositionQuery) -> Result<GamePage> {
+    let (page, page_size) = page_bounds(args.page, args.page_size);
+    let position_id = match find_position(conn, &args.fen)? {
+        Some(id) => id,
+        None => {
+            return Ok(GamePage{total: 0, page: page, page_size: page_size, games: Vec::new()});
+        }
+    };
+    let game_ids = games_reaching_position(args.include_variations);
+
+    let total = sql_query(format!("SELECT COUNT(*) AS count FROM ({})", game_ids))
+        .bind::<Integer, _>(position_id)
+        .bind::<Integer, _>(position_id)
+        .get_result::<Count>(conn)?
+        .count;
+    let games = sql_query(format!(
+            "{} WHERE g.id IN ({}) ORDER BY g.date DESC, g.id DESC LIMIT ? OFFSET ?",
+            GAME_SUMMARY_SELECT, game_ids
+        ))
+        .bind::<Integer, _>(position_id)
+        .bind::<Integer, _>(position_id)
+        .bind::<BigInt, _>(page_size)
+        .bind::<BigInt, _>(page.saturating_mul(page_size))
+        .load::<GameRow>(conn)?;
+
+    Ok(GamePage{
+        total: total,
+        page: page,
+        page_size: page_size,
+        games: games.into_iter().map(summarize).collect()
+    })
+}
+
+pub fn by_position(request: &Request, args: PositionQuery) -> Result<GamePage> {
+    let conn = request.get_connection()?;
+    let page = position_page(&conn, &args)?;
+    info!(request.log, "search::byPosition found {} games", page.total);
+    Ok(page)
+}
+
+//--------------------------------------------------------------------------------------------------
+// search::byMaterial
+//--------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+pub struct Material {
+    #[serde(default)] pub queens: u32,
+    #[serde(default)] pub rooks: u32,
+    #[serde(default)] pub bishops: u32,
+    #[serde(default)] pub knights: u32,
+    #[serde(default)] pub pawns: u32,
+}
+
+// Either a material pattern as typed by the user (see scid::matpattern), or exact counts.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MaterialQuery {
+    pub pattern: Option<String>,
+    #[serde(default)]
+    pub white: Material,
+    #[serde(default)]
+    pub black: Material,
+    pub page: Option<i64>,
+    pub page_size: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MaterialMatch {
+    pub game: GameSummary,
+    // The first ply of the main line at which the material was on the board.
+    pub ply: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MaterialPage {
+    pub page: i64,
+    pub page_size: i64,
+    // Whether there are matches past this page. Counting every match would mean replaying every
+    // game, so the search stops as soon as it knows.
+    pub more: bool,
+    pub matches: Vec<MaterialMatch>,
+}
+
+pub fn material_signature(white: &Material, black: &Material) -> MaterialSignature {
+    [
+        (WQ, white.queens), (WR, white.rooks), (WB, white.bishops), (WN, white.knights), (WP, white.pawns),
+        (BQ, black.queens), (BR, black.rooks), (BB, black.bishops), (BN, black.knights), (BP, black.pawns),
+    ].iter().fold(matsig::EMPTY, |signature, &(piece, count)| matsig::set_count(signature, piece, count))
+}
+
+// Replays the main line of a stored game, looking for the first position with the material.
+fn first_ply_with_material(pgn: &str, target: &MaterialPattern) -> Result<Option<i32>> {
+    let game = parse_game(pgn)?;
+    let mut board = match game.tag("FEN") {
+        Some(fen) => Board::from_fen(fen)?,
+        None => Board::start()
+    };
+    if target.matches(board.material_signature()) {
+        return Ok(Some(board.ply() as i32));
+    }
+    for m in &game.line.moves {
+        let mv = board.parse_san(&m.san)?;
+        board.make(mv);
+        if target.matches(board.material_signature()) {
+            return Ok(Some(board.ply() as i32));
+        }
+    }
+    Ok(None)
+}
+
+fn material_page(
+    conn: &SqliteConnection,
+    log: &slog::Logger,
+    args: &MaterialQuery,
+    check_cancelled: &Fn() -> Result<()>
+) -> Result<MaterialPage> {
+    let (page, page_size) = page_bounds(args.page, args.page_size);
+    let target = match args.pattern {
+        Some(ref pattern) => MaterialPattern::parse(pattern)?,
+        None => MaterialPattern::exact(material_signature(&args.white, &args.black))
+    };
+
+    // Material only ever comes off the board (promotions aside), so a game can only have passed
+    // through the target if its final material is reachable from the most material the target
+    // allows.
+    let candidates: Vec<i32> = game::table
+        .select((game::id, game::matsig, game::promotions, game::underpromotions))
+        .order(game::id.desc())
+        .load::<(i32, i32, bool, bool)>(conn)?
+        .into_iter()
+        .filter(|&(_, final_matsig, promotions, underpromotions)| {
+            matsig::is_reachable(target.max, final_matsig as MaterialSignature, promotions, underpromotions)
+        })
+        .map(|(id, _, _, _)| id)
+        .collect();
+
+    // One match past the page tells us there is another page.
+    let wanted = page.saturating_add(1).saturating_mul(page_size).saturating_add(1) as usize;
+    let mut replayed = 0;
+    let mut matches: Vec<(i32, i32)> = Vec::new();
+    for batch in candidates.chunks(REPLAY_BATCH_SIZE) {
+        if matches.len() >= wanted {
+            break;
+        }
+        check_cancelled()?;
+        let mut pgns: HashMap<i32, String> = game::table
+            .filter(game::id.eq_any(batch.to_vec()))
+            .select((game::id, game::pgn))
+            .load::<(i32, String)>(conn)?
+            .into_iter()
+            .collect();
+        for id in batch {
+            if matches.len() >= wanted {
+                break;
+            }
+            let pgn = match pgns.remove(id) {
+                Some(pgn) => pgn,
+                None => continue
+            };
+            replayed += 1;
+            match first_ply_with_material(&pgn, &target) {
+                Ok(Some(ply)) => matches.push((*id, ply)),
+                Ok(None) => {},
+                Err(e) => warn!(log, "Unable to replay game {}: {}", id, e)
+            }
+        }
+    }
+    info!(log, "search::byMaterial replayed {} of {} candidate games", replayed, candidates.len());
+
+    let more = matches.len() >= wanted;
+    let page_matches: Vec<(i32, i32)> = matches.into_iter()
+        .skip(page.saturating_mul(page_size) as usize)
+        .take(page_size as usize)
+        .collect();
+    let ids: Vec<i32> = page_matches.iter().map(|&(id, _)| id).collect();
+    let plies: HashMap<i32, i32> = page_matches.into_iter().collect();
+    let summaries = load_summaries(conn, &ids)?;
+    Ok(MaterialPage{
+        page: page,
+        page_size: page_size,
+        more: more,
+        matches: summaries.into_iter().map(|game| MaterialMatch{ply: plies[&game.id], game: game}).collect()
+    })
+}
+
+pub fn by_material(request: &Request, args: MaterialQuery) -> Result<MaterialPage> {
+    let conn = request.get_connection()?;
+    material_page(&conn, &request.log, &args, &|| request.check_cancelled())
+}
+
+//--------------------------------------------------------------------------------------------------
+// search::games
+//--------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum SortField {
+    Date,
+    WhiteRating,
+    BlackRating,
+    Round,
+    Result,
+    Eco,
+    Id,
+}
+
+// Every field is optional, and only the ones that are given are used. Names match from the start,
+// either just the last name ("Carlsen") or "Last, First" ("Carlsen, M"). Dates are PGN style
+// (YYYY.MM.DD) and may be shortened, so a date_to of "2017" includes all of 2017. Dates that are
+// partly unknown, like "2017.??.??", match if they could be in range. ECO codes match from the
+// start too, so "B9" finds B90 to B99. There are no wildcards: '%' and '_' match themselves.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct GameFilter {
+    // Either colour.
+    pub player: Option<String>,
+    pub white: Option<String>,
+    pub black: Option<String>,
+    // Both players' ratings must be within the range.
+    pub min_rating: Option<i32>,
+    pub max_rating: Option<i32>,
+    pub event: Option<String>,
+    pub site: Option<String>,
+    pub date_from: Option<String>,
+    pub date_to: Option<String>,
+    pub round: Option<i32>,
+    pub result: Option<String>,
+    pub eco: Option<String>,
+    pub sort: Option<SortField>,
+    #[serde(default)]
+    pub descending: bool,
+    pub page: Option<i64>,
+    pub page_size: Option<i64>,
+}
+
+// Escapes LIKE's wildcards, for patterns used with ESCAPE '\', so that a '%' or '_' in a name
+// only matches itself.
+pub fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+fn players_named(name: &str) -> player::BoxedQuery<'static, Sqlite, Integer> {
+    let mut parts = name.splitn(2, ',');
+    let last_name = parts.next().unwrap_or("").trim();
+    let mut query = player::table.select(player::id).into_boxed();
+    match parts.next().map(|first_name| first_name.trim()) {
+        Some(first_name) => {
+            query = query
+                .filter(player::last_name.like(escape_like(last_name)).escape('\\'))
+                .filter(player::first_name.like(format!("{}%", escape_like(first_name))).escape('\\'));
+        },
+        None => {
+            query = query.filter(player::last_name.like(format!("{}%", escape_like(last_name))).escape('\\'));
+        }
+    }
+    query
+}
+
+fn filtered(filter: &GameFilter) -> game::BoxedQuery<'static, Sqlite> {
+    let mut query = game::table.into_boxed();
+    if let Some(ref name) = filter.player {
+        query = query.filter(
+            game::white_player_id.eq_any(players_named(name))
+                .or(game::black_player_id.eq_any(players_named(name)))
+        );
+    }
+    if let Some(ref name) = filter.white {
+        query = query.filter(game::white_player_id.eq_any(players_named(name)));
+    }
+    if let Some(ref name) = filter.black {
+        query = query.filter(game::black_player_id.eq_any(players_named(name)));
+    }
+    if let Some(min_rating) = filter.min_rating {
+        query = query
+            .filter(game::white_player_rating.ge(min_rating))
+            .filter(game::black_player_rating.ge(min_rating));
+    }
+    if let Some(max_rating) = filter.max_rating {
+        query = query
+            .filter(game::white_player_rating.le(max_rating))
+            .filter(game::black_player_rating.le(max_rating));
+    }
+    if let Some(ref name) = filter.event {
+        query = query.filter(game::event_id.eq_any(
+            event::table.filter(event::name.like(format!("{}%", escape_like(name))).escape('\\'))
+                .select(event::id.nullable())
+        ));
+    }
+    if let Some(ref name) = filter.site {
+        query = query.filter(game::site_id.eq_any(
+            site::table.filter(site::name.like(format!("{}%", escape_like(name))).escape('\\'))
+                .select(site::id.nullable())
+        ));
+    }
+    // Unknown parts of a date are written as '?'. A game is kept if the parts that are known could
+    // fall within the range, so "2017.??.??" is inside both a date_from of "2017.06.01" and a
+    // date_to of "2017.05.31". Games without even a year can't be placed, and are left out.
+    if filter.date_from.is_some() || filter.date_to.is_some() {
+        query = query.filter(game::date.not_like("?%"));
+    }
+    if let Some(ref date_from) = filter.date_from {
+        // '?' sorts after the digits, so unknown parts compare as late as they could be.
+        query = query.filter(game::date.ge(date_from.clone()));
+    }
+    if let Some(ref date_to) = filter.date_to {
+        // And here as early as they could be. The '\u{7f}' sorts after everything in a date, so
+        // a shortened date_to like "2017" includes all of 2017.
+        query = query.filter(sql::<Text>("replace(game.date, '?', '0')").le(format!("{}\u{7f}", date_to)));
+    }
+    if let Some(round) = filter.round {
+        query = query.filter(game::round.eq(round));
+    }
+    if let Some(ref result) = filter.result {
+        query = query.filter(game::result.eq(result.clone()));
+    }
+    if let Some(ref eco) = filter.eco {
+        query = query.filter(game::eco.like(format!("{}%", escape_like(eco))).escape('\\'));
+    }
+    query
+}
+
+// The ids of the games that pass the filter, in the order asked for.
+fn sorted_ids(filter: &GameFilter) -> game::BoxedQuery<'static, Sqlite, Integer> {
+    let query = filtered(filter).select(game::id);
+    match filter.sort.unwrap_or(SortField::Date) {
+        SortField::Date => sorted!(query, game::date, filter.descending),
+        SortField::WhiteRating => sorted!(query, game::white_player_rating, filter.descending),
+        SortField::BlackRating => sorted!(query, game::black_player_rating, filter.descending),
+        SortField::Round => sorted!(query, game::round, filter.descending),
+        SortField::Result => sorted!(query, game::result, filter.descending),
+        SortField::Eco => sorted!(query, game::eco, filter.descending),
+        SortField::Id => sorted!(query, game::id, filter.descending),
+    }
+}
+
+// All of the games that pass the filter, ignoring its page.
+pub fn matching_games(conn: &SqliteConnection, filter: &GameFilter) -> Result<Vec<i32>> {
+    Ok(sorted_ids(filter).load::<i32>(conn)?)
+}
+
+fn game_page(conn: &SqliteConnection, args: &GameFilter) -> Result<GamePage> {
+    let (page, page_size) = page_bounds(args.page, args.page_size);
+    let total = filtered(args).count().get_result::<i64>(conn)?;
+
+    let ids = sorted_ids(args)
+        .limit(page_size)
+        .offset(page.saturating_mul(page_size))
+        .load::<i32>(conn)?;
+
+    Ok(GamePage{
+        total: total,
+        page: page,
+        page_size: page_size,
+        games: load_summaries(conn, &ids)?
+    })
+}
+
+pub fn games(request: &Request, args: GameFilter) -> Result<GamePage> {
+    let conn = request.get_connection()?;
+    let page = game_page(&conn, &args)?;
+    info!(request.log, "search::games found {} games", page.total);
+    Ok(page)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::fixture;
+
+    fn fen_after(moves: &[&str]) -> String {
+        let mut board = Board::start();
+        for san in moves {
+            let mv = board.parse_san(san).unwrap();
+            board.make(mv);
+        }
+        board.to_fen()
+    }
+
+    fn position_query(fen: &str, include_variations: bool, page: i64, page_size: i64) -> PositionQuery {
+        PositionQuery{fen: fen.into(), include_variations: include_variations, page: Some(page), page_size: Some(page_size)}
+    }
+
+    fn ids(page: &GamePage) -> Vec<i32> {
+        page.games.iter().map(|game| game.id).collect()
+    }
+
+    #[test]
+    fn test_by_position() {
+        let conn = fixture::database(&[
+            fixture::game(&[("White", "Carlsen, Magnus"), ("Date", "2014.11.23"), ("Result", "1-0")], "1. e4 e5 2. Nf3"),
+            fixture::game(&[("White", "Anand, Viswanathan"), ("Date", "2013.11.22"), ("Result", "1/2-1/2")], "1. e4 c5"),
+            fixture::game(&[("White", "Kasparov, Garry"), ("Date", "1985.??.??"), ("Result", "0-1")], "1. e4 e5 2. Nf3"),
+            fixture::game(&[("White", "Karpov, Anatoly"), ("Date", "2017.??.??")], "1. d4 (1. e4 e5) 1... d5"),
+        ]);
+        let after_e4 = fen_after(&["e4"]);
+
+        let first = position_query(&after_e4, false, 0, 2);
+        let page = position_page(&conn, &first).unwrap();
+        assert_eq!(page.total, 3);
+        assert_eq!(ids(&page), vec![1, 2]);
+        assert_eq!(page.games[0].white, "Carlsen, Magnus");
+        assert_eq!(page.games[0].result, "1-0");
+        assert_eq!(ids(&position_page(&conn, &position_query(&after_e4, false, 1, 2)).unwrap()), vec![3]);
+        assert!(position_page(&conn, &position_query(&after_e4, false, 9, 2)).unwrap().games.is_empty());
+
+        let page = position_page(&conn, &position_query(&after_e4, true, 0, 10)).unwrap();
+        assert_eq!(page.total, 4);
+        assert_eq!(ids(&page), vec![4, 1, 2, 3]);
+
+        // Games reach their starting position too.
+        let start = Board::start().to_fen();
+        assert_eq!(position_page(&conn, &position_query(&start, false, 0, 10)).unwrap().total, 4);
+        let unplayed = fen_after(&["a4"]);
+        assert_eq!(position_page(&conn, &position_query(&unplayed, true, 0, 10)).unwrap().total, 0);
+    }
+
+    fn material_query(pattern: Option<&str>, material: Material, page: i64, page_size: i64) -> MaterialQuery {
+        MaterialQuery{
+            pattern: pattern.map(|p| p.to_string()),
+            white: material,
+            black: material,
+            page: Some(page),
+            page_size: Some(page_size)
+        }
+    }
+
+    fn matched(page: &MaterialPage) -> Vec<(i32, i32)> {
+        page.matches.iter().map(|m| (m.game.id, m.ply)).collect()
+    }
+
+    #[test]
+    fn test_by_material() {
+        let conn = fixture::database(&[
+            fixture::game(&[], "1. e4 d5 2. exd5 Qxd5 3. Nc3 Qa5"),
+            fixture::game(&[], "1. e4 e5 2. Nf3 Nc6"),
+            fixture::game(&[], "1. e4 d5 2. exd5 Nf6 3. Nc3 Nxd5"),
+        ]);
+        let log = fixture::log();
+        let search = |query: &MaterialQuery| material_page(&conn, &log, query, &|| Ok(())).unwrap();
+        let pawn_each = Material{queens: 1, rooks: 2, bishops: 2, knights: 2, pawns: 7};
+
+        let page = search(&material_query(None, pawn_each, 0, 10));
+        assert_eq!(matched(&page), vec![(3, 6), (1, 4)]);
+        assert!(!page.more);
+
+        let page = search(&material_query(None, pawn_each, 0, 1));
+        assert_eq!(matched(&page), vec![(3, 6)]);
+        assert!(page.more);
+        let page = search(&material_query(None, pawn_each, 1, 1));
+        assert_eq!(matched(&page), vec![(1, 4)]);
+        assert!(!page.more);
+
+        // A pattern is used instead of the counts when there is one.
+        let page = search(&material_query(Some("QRRBBNN P:7-8 vs QRRBBNN P:7"), pawn_each, 0, 10));
+        assert_eq!(matched(&page), vec![(3, 3), (1, 3)]);
+
+        // Every game starts with all of its pawns.
+        let all_pawns = Material{queens: 1, rooks: 2, bishops: 2, knights: 2, pawns: 8};
+        assert_eq!(matched(&search(&material_query(None, all_pawns, 0, 10))), vec![(3, 0), (2, 0), (1, 0)]);
+        // No game gets down to bare kings.
+        assert!(search(&material_query(None, Material::default(), 0, 10)).matches.is_empty());
+    }
+
+    #[test]
+    fn test_material_search_can_be_cancelled() {
+        let conn = fixture::database(&[fixture::game(&[], "1. e4 e5")]);
+        let all_pawns = Material{queens: 1, rooks: 2, bishops: 2, knights: 2, pawns: 8};
+        let query = material_query(None, all_pawns, 0, 10);
+        let cancelled = material_page(&conn, &fixture::log(), &query, &|| bail!(ErrorKind::Cancelled));
+        match cancelled {
+            Err(Error(ErrorKind::Cancelled, _)) => {},
+            other => panic!("Expected the search to be cancelled, got {:?}", other.map(|page| page.matches.len()))
+        }
+    }
+
+    #[test]
+    fn test_escape_like() {
+        assert_eq!(escape_like("Carlsen"), "Carlsen");
+        assert_eq!(escape_like("100%"), "100\\%");
+        assert_eq!(escape_like("a_b"), "a\\_b");
+        assert_eq!(escape_like("a\\%"), "a\\\\\\%");
+    }
+
+    fn dated_games() -> SqliteConnection {
+        let dates = ["2017.05.03", "2017.??.??", "2018.01.01", "????.??.??", "2016.12.31"];
+        let games: Vec<String> = dates.iter().map(|date| fixture::game(&[("Date", date)], "1. e4")).collect();
+        fixture::database(&games)
+    }
+
+    fn dated(conn: &SqliteConnection, date_from: Option<&str>, date_to: Option<&str>) -> Vec<i32> {
+        let filter = GameFilter{
+            date_from: date_from.map(|d| d.to_string()),
+            date_to: date_to.map(|d| d.to_string()),
+            sort: Some(SortField::Id),
+            ..Default::default()
+        };
+        matching_games(conn, &filter).unwrap()
+    }
+
+    #[test]
+    fn test_date_bounds() {
+        let conn = dated_games();
+        assert_eq!(dated(&conn, None, None), vec![1, 2, 3, 4, 5]);
+        assert_eq!(dated(&conn, None, Some("2017.12.31")), vec![1, 2, 5]);
+        assert_eq!(dated(&conn, None, Some("2017")), vec![1, 2, 5]);
+        assert_eq!(dated(&conn, None, Some("2017.04")), vec![2, 5]);
+        assert_eq!(dated(&conn, None, Some("2016.12.31")), vec![5]);
+        assert_eq!(dated(&conn, Some("2017.06.01"), None), vec![2, 3]);
+        assert_eq!(dated(&conn, Some("2017"), Some("2017")), vec![1, 2]);
+        assert_eq!(dated(&conn, Some("2017.05.03"), Some("2017.05.03")), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_games() {
+        let conn = fixture::database(&[
+            fixture::game(&[("White", "Under_score"), ("Black", "Carlsen, Magnus"), ("Result", "1-0")], "1. e4"),
+            fixture::game(&[("White", "UnderXscore"), ("Black", "Carlsen, Henrik"), ("Result", "0-1")], "1. e4"),
+            fixture::game(&[("White", "100%"), ("Black", "Carlsen, Magnus"), ("Result", "1-0"), ("ECO", "B90")], "1. e4"),
+        ]);
+        let named = |white: Option<&str>, black: Option<&str>| {
+            let filter = GameFilter{
+                white: white.map(|w| w.to_string()),
+                black: black.map(|b| b.to_string()),
+                sort: Some(SortField::Id),
+                ..Default::default()
+            };
+            matching_games(&conn, &filter).unwrap()
+        };
+        assert_eq!(named(Some("Under_"), None), vec![1]);
+        assert_eq!(named(Some("100%"), None), vec![3]);
+        assert_eq!(named(Some("1%"), None), Vec::<i32>::new());
+        assert_eq!(named(None, Some("Carlsen")), vec![1, 2, 3]);
+        assert_eq!(named(None, Some("Carlsen, M")), vec![1, 3]);
+
+        let filter = GameFilter{
+            player: Some("Carlsen".into()),
+            result: Some("1-0".into()),
+            sort: Some(SortField::Id),
+            descending: true,
+            page: Some(0),
+            page_size: Some(1),
+            ..Default::default()
+        };
+        let page = game_page(&conn, &filter).unwrap();
+        assert_eq!(page.total, 2);
+        assert_eq!(ids(&page), vec![3]);
+        assert_eq!(page.games[0].white, "100%");
+        assert_eq!(page.games[0].eco, Some("B90".into()));
+        let page = game_page(&conn, &GameFilter{page: Some(1), ..filter}).unwrap();
+        assert_eq!(ids(&page), vec![1]);
+    }
+}
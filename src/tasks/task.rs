@@ -0,0 +1,332 @@
+// delila - a desktop version of lila.
+//
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+//--------------------------------------------------------------------------------------------------
+// Keeps track of the requests that have been started, so that clients can see what is running,
+// pick up its progress after reconnecting, and cancel it.
+//
+// Clients choose their own request ids, so a request is known by its id together with the
+// session of the connection that sent it. Clients only ever see their own session's requests.
+//
+// The registry also keeps each session's connection while it has requests. A client that loses
+// its connection can reconnect and send task::resume with the session id that initialize replied
+// with, and then the requests it left running send their progress and replies to the new
+// connection.
+//
+// Cancelling only raises a flag. Long running handlers check it between units of work with
+// Request::check_cancelled(), and stop by returning ErrorKind::Cancelled.
+//--------------------------------------------------------------------------------------------------
+
+use chrono::{DateTime, Utc};
+use rand::{Rng, thread_rng};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::{Connection, Message, Request};
+use ::errors::*;
+
+// The number of finished requests to remember the outcome of.
+const FINISHED_TASKS: usize = 100;
+const SESSION_BYTES: usize = 16;
+
+// A new id for a connection's session. Requests belong to the session that started them, and the
+// id is only ever told to that session's client, so no other client can see or cancel them.
+pub fn session_id() -> String {
+    let mut bytes = [0u8; SESSION_BYTES];
+    thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskState {
+    Running,
+    // Asked to stop, and hasn't yet.
+    Cancelling,
+    Finished,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TaskStatus {
+    pub id: u32,
+    pub name: String,
+    pub started: DateTime<Utc>,
+    pub state: TaskState,
+    // The last progress message that the request sent.
+    pub progress: Option<Message>,
+}
+
+struct Task {
+    status: TaskStatus,
+    cancelled: Arc<AtomicBool>,
+}
+
+// The session that sent a request, and the request's id.
+type TaskKey = (String, u32);
+
+fn key(session: &str, id: u32) -> TaskKey {
+    (session.to_string(), id)
+}
+
+#[derive(Default)]
+struct Tasks {
+    by_id: HashMap<TaskKey, Task>,
+    // Oldest first.
+    finished: VecDeque<TaskKey>,
+    // By session, for the sessions that have requests.
+    connections: HashMap<String, Connection>,
+}
+
+#[derive(Default)]
+pub struct TaskRegistry {
+    tasks: Mutex<Tasks>
+}
+
+impl TaskRegistry {
+    pub fn new() -> TaskRegistry {
+        TaskRegistry::default()
+    }
+
+    // Registers a request, returning the flag that cancels it.
+    pub fn start(&self, connection: &Connection, id: u32, name: &str) -> Arc<AtomicBool> {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let session = connection.session();
+        let key = key(&session, id);
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.connections.entry(session).or_insert_with(|| connection.clone());
+        tasks.finished.retain(|finished| *finished != key);
+        tasks.by_id.insert(key, Task{
+            status: TaskStatus{
+                id: id,
+                name: name.into(),
+                started: Utc::now(),
+                state: TaskState::Running,
+                progress: None
+            },
+            cancelled: cancelled.clone()
+        });
+        cancelled
+    }
+
+    pub fn progress(&self, session: &str, id: u32, message: &Message) {
+        if let Some(task) = self.tasks.lock().unwrap().by_id.get_mut(&key(session, id)) {
+            task.status.progress = Some(message.clone());
+        }
+    }
+
+    pub fn finish(&self, session: &str, id: u32, state: TaskState) {
+        let key = key(session, id);
+        let mut tasks = self.tasks.lock().unwrap();
+        if let Some(task) = tasks.by_id.get_mut(&key) {
+            task.status.state = state;
+        }
+        tasks.finished.push_back(key);
+        while tasks.finished.len() > FINISHED_TASKS {
+            if let Some(oldest) = tasks.finished.pop_front() {
+                tasks.by_id.remove(&oldest);
+                let (session, _) = oldest;
+                if !tasks.by_id.keys().any(|&(ref owner, _)| *owner == session) {
+                    tasks.connections.remove(&session);
+                }
+            }
+        }
+    }
+
+    // Returns false if the session has no such request running.
+    pub fn cancel(&self, session: &str, id: u32) -> bool {
+        match self.tasks.lock().unwrap().by_id.get_mut(&key(session, id)) {
+            Some(ref mut task) if task.status.state == TaskState::Running => {
+                task.cancelled.store(true, Ordering::SeqCst);
+                task.status.state = TaskState::Cancelling;
+                true
+            },
+            _ => false
+        }
+    }
+
+    pub fn status(&self, session: &str, id: u32) -> Option<TaskStatus> {
+        self.tasks.lock().unwrap().by_id.get(&key(session, id)).map(|task| task.status.clone())
+    }
+
+    // The connection of a session that has requests, for a client that's reconnected.
+    pub fn resume(&self, session: &str) -> Option<Connection> {
+        self.tasks.lock().unwrap().connections.get(session).cloned()
+    }
+
+    // The session's requests, oldest first.
+    pub fn list(&self, session: &str, include_finished: bool) -> Vec<TaskStatus> {
+        let mut statuses: Vec<TaskStatus> = self.tasks.lock().unwrap().by_id.iter()
+            .filter(|&(&(ref owner, _), _)| owner == session)
+            .map(|(_, task)| task.status.clone())
+            .filter(|status| include_finished || status.state == TaskState::Running || status.state == TaskState::Cancelling)
+            .collect();
+        statuses.sort_by_key(|status| (status.started, status.id));
+        statuses
+    }
+}
+
+// The args of task::resume.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Session {
+    pub session: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TaskId {
+    pub id: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TaskFilter {
+    #[serde(default)]
+    pub include_finished: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Cancellation {
+    pub id: u32,
+    // Whether the request was still running. It replies with a "cancelled" message once it stops.
+    pub cancelling: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TaskList {
+    pub tasks: Vec<TaskStatus>,
+}
+
+// The status is missing if the request is unknown, or finished long enough ago to be forgotten.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TaskStatusReply {
+    pub id: u32,
+    pub status: Option<TaskStatus>,
+}
+
+pub fn cancel(request: &Request, args: TaskId) -> Result<Cancellation> {
+    let cancelling = request.tasks.cancel(&request.connection.session(), args.id);
+    info!(request.log, "task::cancel {} (running: {})", args.id, cancelling);
+    Ok(Cancellation{id: args.id, cancelling: cancelling})
+}
+
+pub fn list(request: &Request, args: TaskFilter) -> Result<TaskList> {
+    Ok(TaskList{tasks: request.tasks.list(&request.connection.session(), args.include_finished)})
+}
+
+pub fn status(request: &Request, args: TaskId) -> Result<TaskStatusReply> {
+    Ok(TaskStatusReply{id: args.id, status: request.tasks.status(&request.connection.session(), args.id)})
+}
+
+// Moves a client that reconnected onto the session that it had, so that the requests it left
+// running reply on this connection. The reply lists the ones that are still running.
+pub fn resume(request: &Request, args: Session) -> Result<TaskList> {
+    match request.tasks.resume(&args.session) {
+        Some(connection) => connection.attach(&request.connection),
+        None => bail!(ErrorKind::UnknownSession)
+    }
+    request.connection.set_session(&args.session);
+    info!(request.log, "task::resume picked up a session");
+    Ok(TaskList{tasks: request.tasks.list(&args.session, false)})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ws;
+
+    fn connection() -> Connection {
+        let socket = ws::WebSocket::new(|_| |_| Ok(())).unwrap();
+        Connection::new(socket.broadcaster())
+    }
+
+    #[test]
+    fn test_sessions_only_see_their_own_tasks() {
+        let tasks = TaskRegistry::new();
+        let (mine, theirs) = (connection(), connection());
+        let cancelled = tasks.start(&mine, 1, "import::importFile");
+        tasks.start(&theirs, 1, "search::byMaterial");
+
+        let listed: Vec<String> = tasks.list(&mine.session(), false).into_iter().map(|task| task.name).collect();
+        assert_eq!(listed, vec!["import::importFile".to_string()]);
+        assert_eq!(tasks.status(&theirs.session(), 1).unwrap().name, "search::byMaterial");
+
+        assert!(tasks.cancel(&theirs.session(), 1));
+        assert!(!cancelled.load(Ordering::SeqCst));
+        assert_eq!(tasks.status(&mine.session(), 1).unwrap().state, TaskState::Running);
+        assert!(!tasks.cancel(&mine.session(), 2));
+    }
+
+    #[test]
+    fn test_cancel_raises_the_flag() {
+        let tasks = TaskRegistry::new();
+        let client = connection();
+        let cancelled = tasks.start(&client, 7, "export::pgn");
+        assert!(tasks.cancel(&client.session(), 7));
+        assert!(cancelled.load(Ordering::SeqCst));
+        assert_eq!(tasks.status(&client.session(), 7).unwrap().state, TaskState::Cancelling);
+        // It's already on its way out.
+        assert!(!tasks.cancel(&client.session(), 7));
+    }
+
+    #[test]
+    fn test_finished_tasks_keep_their_state() {
+        let tasks = TaskRegistry::new();
+        let client = connection();
+        let session = client.session();
+        tasks.start(&client, 1, "search::games");
+        tasks.finish(&session, 1, TaskState::Failed);
+        assert_eq!(tasks.status(&session, 1).unwrap().state, TaskState::Failed);
+        assert!(tasks.list(&session, false).is_empty());
+        assert_eq!(tasks.list(&session, true).len(), 1);
+        assert!(!tasks.cancel(&session, 1));
+
+        for id in 2..(FINISHED_TASKS as u32 + 2) {
+            tasks.start(&client, id, "search::games");
+            tasks.finish(&session, id, TaskState::Finished);
+        }
+        assert!(tasks.status(&session, 1).is_none());
+        assert_eq!(tasks.list(&session, true).len(), FINISHED_TASKS);
+    }
+
+    #[test]
+    fn test_resume_moves_a_session_to_the_new_connection() {
+        let tasks = TaskRegistry::new();
+        let (old, new) = (connection(), connection());
+        let session = old.session();
+        tasks.start(&old, 3, "import::importFile");
+        assert!(tasks.resume("no such session").is_none());
+
+        new.set_protocol(2);
+        let found = tasks.resume(&session).unwrap();
+        found.attach(&new);
+        new.set_session(&session);
+        // The request still holds the old connection, which now speaks the way the new one does.
+        assert_eq!(old.protocol(), 2);
+        assert_eq!(tasks.list(&new.session(), false).len(), 1);
+
+        // Sessions are forgotten along with their last request.
+        tasks.finish(&session, 3, TaskState::Finished);
+        let other = connection();
+        for id in 0..FINISHED_TASKS as u32 {
+            tasks.start(&other, id, "search::games");
+            tasks.finish(&other.session(), id, TaskState::Finished);
+        }
+        assert!(tasks.resume(&session).is_none());
+    }
+}
@@ -0,0 +1,417 @@
+// delila - a desktop version of lila.
+//
+// Copyright (C) 2017 Lakin Wecker <lakin@wecker.ca>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+//--------------------------------------------------------------------------------------------------
+// Request handlers for reading and editing the variation tree of a game.
+//
+// Each line is a row in the line table. A variation is an alternative to the move at parent_ply in
+// its parent line, so it starts from the position before that move. Every edit responds with the
+// game's new tree, and rewrites the game's stored PGN and material signature to match it.
+//--------------------------------------------------------------------------------------------------
+
+use diesel;
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use std::collections::HashMap;
+
+use super::super::board::Board;
+use super::super::models;
+use super::super::pgn;
+use super::super::scid::eco::EcoBook;
+use super::super::schema::{_move, game, line, line_move};
+use super::export::load_game;
+use super::import::{eco_code, replay, replay_line, GameWriter};
+
+use super::Request;
+use ::errors::*;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LineNode {
+    pub id: i32,
+    pub comment: Option<String>,
+    pub moves: Vec<MoveNode>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MoveNode {
+    pub ply: i32,
+    pub san: String,
+    pub uci: String,
+    pub comment: Option<String>,
+    pub nags: Vec<u8>,
+    // Alternatives to this move.
+    pub variations: Vec<LineNode>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Tree {
+    pub game_id: i32,
+    pub line: LineNode,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GameRef {
+    pub game_id: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LineRef {
+    pub game_id: i32,
+    pub line_id: i32,
+}
+
+// Adds a variation to the move at ply in the given line. Moves may be in SAN or UCI.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NewVariation {
+    pub game_id: i32,
+    pub line_id: i32,
+    pub ply: i32,
+    pub moves: Vec<String>,
+    pub comment: Option<String>,
+}
+
+fn build_node(
+    id: i32,
+    comments: &mut HashMap<i32, Option<String>>,
+    moves: &mut HashMap<i32, Vec<models::LineMove>>,
+    ucis: &HashMap<i32, String>,
+    children: &HashMap<(i32, i32), Vec<i32>>
+) -> LineNode {
+    let mut node = LineNode{
+        id: id,
+        comment: comments.remove(&id).unwrap_or(None),
+        moves: Vec::new()
+    };
+    for line_move in moves.remove(&id).unwrap_or_default() {
+        let variations = children.get(&(id, line_move.ply)).map_or(Vec::new(), |variations| {
+            variations.iter().map(|v| build_node(*v, comments, moves, ucis, children)).collect()
+        });
+        node.moves.push(MoveNode{
+            ply: line_move.ply,
+            uci: ucis.get(&line_move.move_id).cloned().unwrap_or_default(),
+            san: line_move.san,
+            comment: line_move.comment,
+            nags: line_move.nags.map_or(Vec::new(), |nags| {
+                nags.split_whitespace().filter_map(|nag| nag.parse().ok()).collect()
+            }),
+            variations: variations
+        });
+    }
+    node
+}
+
+//--------------------------------------------------------------------------------------------------
+// load_tree():
+//      Loads the main line of a game with all of its variations. Variations are given in the
+//      order that they were added.
+//
+pub fn load_tree(conn: &SqliteConnection, game_id: i32) -> Result<LineNode> {
+    let main_line_id = game::table.find(game_id).select(game::line_id).first::<i32>(conn)?;
+    let lines = line::table
+        .filter(line::game_id.eq(game_id))
+        .order(line::id)
+        .load::<models::Line>(conn)?;
+    let line_moves = line_move::table
+        .filter(line_move::line_id.eq_any(line::table.filter(line::game_id.eq(game_id)).select(line::id)))
+        .order((line_move::line_id, line_move::ply))
+        .load::<models::LineMove>(conn)?;
+    let ucis: HashMap<i32, String> = _move::table
+        .filter(_move::id.eq_any(line_move::table
+            .filter(line_move::line_id.eq_any(line::table.filter(line::game_id.eq(game_id)).select(line::id)))
+            .select(line_move::move_id)))
+        .select((_move::id, _move::uci))
+        .load::<(i32, String)>(conn)?
+        .into_iter()
+        .collect();
+
+    let mut comments = HashMap::new();
+    let mut children: HashMap<(i32, i32), Vec<i32>> = HashMap::new();
+    for l in lines {
+        if let (Some(parent_line_id), Some(parent_ply)) = (l.parent_line_id, l.parent_ply) {
+            children.entry((parent_line_id, parent_ply)).or_insert_with(Vec::new).push(l.id);
+        }
+        comments.insert(l.id, l.comment);
+    }
+    let mut moves: HashMap<i32, Vec<models::LineMove>> = HashMap::new();
+    for line_move in line_moves {
+        moves.entry(line_move.line_id).or_insert_with(Vec::new).push(line_move);
+    }
+    Ok(build_node(main_line_id, &mut comments, &mut moves, &ucis, &children))
+}
+
+fn starting_board(conn: &SqliteConnection, game_id: i32) -> Result<Board> {
+    let text = game::table.find(game_id).select(game::pgn).first::<String>(conn)?;
+    match pgn::parse_game(&text)?.tag("FEN") {
+        Some(fen) => Board::from_fen(fen),
+        None => Ok(Board::start())
+    }
+}
+
+// Plays the board forward to just before the move at ply in the given line, returning whether the
+// line was found. If it wasn't, the board is left as it was.
+fn play_to(board: &mut Board, node: &LineNode, line_id: i32, ply: i32) -> Result<bool> {
+    if node.id == line_id {
+        for m in node.moves.iter().take_while(|m| m.ply < ply) {
+            let mv = board.parse_uci(&m.uci)?;
+            board.make(mv);
+        }
+        return Ok(true);
+    }
+    let mut played = 0;
+    for m in &node.moves {
+        for variation in &m.variations {
+            if play_to(board, variation, line_id, ply)? {
+                return Ok(true);
+            }
+        }
+        let mv = board.parse_uci(&m.uci)?;
+        board.make(mv);
+        played += 1;
+    }
+    for _ in 0..played {
+        board.unmake();
+    }
+    Ok(false)
+}
+
+fn find_line(conn: &SqliteConnection, game_id: i32, line_id: i32) -> Result<models::Line> {
+    let found = line::table.find(line_id).first::<models::Line>(conn).optional()?;
+    match found {
+        Some(found) if found.game_id == Some(game_id) => Ok(found),
+        _ => bail!("Game {} has no line {}", game_id, line_id)
+    }
+}
+
+// Brings the game's stored PGN, material signature and ECO code back in line with its tree.
+fn refresh_game(conn: &SqliteConnection, book: Option<&EcoBook>, game_id: i32) -> Result<()> {
+    let game = load_game(conn, game_id)?;
+    let replayed = replay(&game)?;
+    let eco = eco_code(book, &replayed);
+    diesel::update(game::table.find(game_id))
+        .set((
+            game::pgn.eq(pgn::write_game(&game)),
+            game::matsig.eq(replayed.board.material_signature() as i32),
+            game::promotions.eq(replayed.promotions),
+            game::underpromotions.eq(replayed.underpromotions),
+            game::eco.eq(eco)
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+fn tree_of(conn: &SqliteConnection, game_id: i32) -> Result<Tree> {
+    Ok(Tree{game_id: game_id, line: load_tree(conn, game_id)?})
+}
+
+pub fn tree(request: &Request, args: GameRef) -> Result<Tree> {
+    let conn = request.get_connection()?;
+    tree_of(&conn, args.game_id)
+}
+
+fn add_variation(conn: &SqliteConnection, book: Option<&EcoBook>, args: &NewVariation) -> Result<()> {
+    conn.transaction::<_, Error, _>(|| {
+        find_line(conn, args.game_id, args.line_id)?;
+        let tree = load_tree(conn, args.game_id)?;
+        let mut board = starting_board(conn, args.game_id)?;
+        if !play_to(&mut board, &tree, args.line_id, args.ply)? {
+            bail!("Line {} is not part of game {}", args.line_id, args.game_id);
+        }
+        let has_move = line_move::table
+            .filter(line_move::line_id.eq(args.line_id))
+            .filter(line_move::ply.eq(args.ply))
+            .count()
+            .get_result::<i64>(conn)? > 0;
+        if !has_move {
+            bail!("Line {} has no move at ply {}", args.line_id, args.ply);
+        }
+
+        // Check the moves, and write them the way the rest of the tree is written.
+        let mut variation = pgn::Line::default();
+        variation.comment = args.comment.clone();
+        for notation in &args.moves {
+            let mv = board.parse_san(notation).or_else(|_| board.parse_uci(notation))?;
+            variation.moves.push(pgn::Move::new(board.san(mv)));
+            board.make(mv);
+        }
+        if variation.moves.is_empty() {
+            bail!("A variation needs at least one move");
+        }
+        for _ in &variation.moves {
+            board.unmake();
+        }
+
+        let replayed = replay_line(&variation, &mut board)?;
+        GameWriter::new(conn).line(args.game_id, &replayed, Some((args.line_id, args.ply)))?;
+        refresh_game(conn, book, args.game_id)
+    })
+}
+
+pub fn add(request: &Request, args: NewVariation) -> Result<Tree> {
+    let conn = request.get_connection()?;
+    add_variation(&conn, request.eco.as_ref().map(|eco| &**eco), &args)?;
+    info!(request.log, "variations::add added a variation to game {}", args.game_id);
+    tree_of(&conn, args.game_id)
+}
+
+//--------------------------------------------------------------------------------------------------
+// promote():
+//      Swaps a variation with the moves of its parent line that it is an alternative to. The
+//      variation's moves, and the variations hanging off them, become part of the parent line,
+//      and the parent's moves from parent_ply on become the variation. Other alternatives to the
+//      same move stay where they are.
+//
+fn promote_line(conn: &SqliteConnection, book: Option<&EcoBook>, args: &LineRef) -> Result<()> {
+    conn.transaction::<_, Error, _>(|| {
+        let variation = find_line(conn, args.game_id, args.line_id)?;
+        let (parent_id, parent_ply) = match (variation.parent_line_id, variation.parent_ply) {
+            (Some(parent_id), Some(parent_ply)) => (parent_id, parent_ply),
+            _ => bail!("Line {} is already the main line", args.line_id)
+        };
+
+        let parent_tail = line_move::table
+            .filter(line_move::line_id.eq(parent_id))
+            .filter(line_move::ply.ge(parent_ply))
+            .select(line_move::id)
+            .load::<i32>(conn)?;
+        let parent_tail_lines = line::table
+            .filter(line::parent_line_id.eq(parent_id))
+            .filter(line::parent_ply.gt(parent_ply))
+            .select(line::id)
+            .load::<i32>(conn)?;
+        let variation_lines = line::table
+            .filter(line::parent_line_id.eq(variation.id))
+            .select(line::id)
+            .load::<i32>(conn)?;
+
+        diesel::update(line_move::table.filter(line_move::line_id.eq(variation.id)))
+            .set(line_move::line_id.eq(parent_id))
+            .execute(conn)?;
+        diesel::update(line_move::table.filter(line_move::id.eq_any(parent_tail)))
+            .set(line_move::line_id.eq(variation.id))
+            .execute(conn)?;
+        diesel::update(line::table.filter(line::id.eq_any(variation_lines)))
+            .set(line::parent_line_id.eq(parent_id))
+            .execute(conn)?;
+        diesel::update(line::table.filter(line::id.eq_any(parent_tail_lines)))
+            .set(line::parent_line_id.eq(variation.id))
+            .execute(conn)?;
+        refresh_game(conn, book, args.game_id)
+    })
+}
+
+pub fn promote(request: &Request, args: LineRef) -> Result<Tree> {
+    let conn = request.get_connection()?;
+    promote_line(&conn, request.eco.as_ref().map(|eco| &**eco), &args)?;
+    info!(request.log, "variations::promote promoted line {} in game {}", args.line_id, args.game_id);
+    tree_of(&conn, args.game_id)
+}
+
+// Deletes a variation along with every variation inside it.
+fn delete_line(conn: &SqliteConnection, book: Option<&EcoBook>, args: &LineRef) -> Result<()> {
+    conn.transaction::<_, Error, _>(|| {
+        let variation = find_line(conn, args.game_id, args.line_id)?;
+        if variation.parent_line_id.is_none() {
+            bail!("The main line of game {} can't be deleted", args.game_id);
+        }
+
+        let lines = line::table
+            .filter(line::game_id.eq(args.game_id))
+            .select((line::id, line::parent_line_id))
+            .load::<(i32, Option<i32>)>(conn)?;
+        let mut doomed = vec![variation.id];
+        let mut index = 0;
+        while index < doomed.len() {
+            let parent = doomed[index];
+            doomed.extend(lines.iter().filter(|&&(_, p)| p == Some(parent)).map(|&(id, _)| id));
+            index += 1;
+        }
+
+        diesel::delete(line_move::table.filter(line_move::line_id.eq_any(doomed.clone())))
+            .execute(conn)?;
+        diesel::delete(line::table.filter(line::id.eq_any(doomed)))
+            .execute(conn)?;
+        refresh_game(conn, book, args.game_id)
+    })
+}
+
+pub fn delete(request: &Request, args: LineRef) -> Result<Tree> {
+    let conn = request.get_connection()?;
+    delete_line(&conn, request.eco.as_ref().map(|eco| &**eco), &args)?;
+    info!(request.log, "variations::delete deleted line {} from game {}", args.line_id, args.game_id);
+    tree_of(&conn, args.game_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::fixture;
+
+    fn sans(node: &LineNode) -> Vec<&str> {
+        node.moves.iter().map(|m| m.san.as_str()).collect()
+    }
+
+    fn stored_pgn(conn: &SqliteConnection) -> String {
+        game::table.find(1).select(game::pgn).first::<String>(conn).unwrap()
+    }
+
+    #[test]
+    fn test_add_promote_and_delete() {
+        let conn = fixture::database(&[fixture::game(&[("Result", "1-0")], "1. e4 e5 2. Nf3 Nc6")]);
+        let main_line = load_tree(&conn, 1).unwrap().id;
+
+        // Moves may be given in SAN or UCI.
+        let variation = NewVariation{
+            game_id: 1,
+            line_id: main_line,
+            ply: 2,
+            moves: vec!["c5".into(), "g1f3".into()],
+            comment: Some("Sharper".into())
+        };
+        add_variation(&conn, None, &variation).unwrap();
+        let tree = load_tree(&conn, 1).unwrap();
+        assert_eq!(sans(&tree), vec!["e4", "e5", "Nf3", "Nc6"]);
+        assert_eq!(tree.moves[1].variations.len(), 1);
+        let added = &tree.moves[1].variations[0];
+        assert_eq!(sans(added), vec!["c5", "Nf3"]);
+        assert_eq!(added.moves[0].ply, 2);
+        assert_eq!(added.comment, Some("Sharper".into()));
+        assert!(stored_pgn(&conn).contains("c5"));
+
+        // Only moves that are legal from the branching position are accepted.
+        let illegal = NewVariation{moves: vec!["e4".into()], comment: None, ..variation};
+        assert!(add_variation(&conn, None, &illegal).is_err());
+        let missing = NewVariation{ply: 9, moves: vec!["a6".into()], ..illegal};
+        assert!(add_variation(&conn, None, &missing).is_err());
+
+        let swap = LineRef{game_id: 1, line_id: added.id};
+        promote_line(&conn, None, &swap).unwrap();
+        let tree = load_tree(&conn, 1).unwrap();
+        assert_eq!(tree.id, main_line);
+        assert_eq!(sans(&tree), vec!["e4", "c5", "Nf3"]);
+        assert_eq!(sans(&tree.moves[1].variations[0]), vec!["e5", "Nf3", "Nc6"]);
+        assert!(stored_pgn(&conn).contains("1. e4 c5 ({Sharper} 1... e5 2. Nf3 Nc6) 2. Nf3 1-0"));
+        assert!(promote_line(&conn, None, &LineRef{game_id: 1, line_id: main_line}).is_err());
+
+        assert!(delete_line(&conn, None, &LineRef{game_id: 1, line_id: main_line}).is_err());
+        delete_line(&conn, None, &swap).unwrap();
+        let tree = load_tree(&conn, 1).unwrap();
+        assert_eq!(sans(&tree), vec!["e4", "c5", "Nf3"]);
+        assert!(tree.moves.iter().all(|m| m.variations.is_empty()));
+        assert_eq!(line::table.count().get_result::<i64>(&conn).unwrap(), 1);
+    }
+}